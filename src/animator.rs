@@ -0,0 +1,388 @@
+//! Values that move over time, like the Lua SDK's `playdate.graphics.animator`.
+//!
+//! An `Animator` eases a value between two endpoints.  Animators can be chained into a
+//! `Sequence` or run side by side in a `Group`, and anything implementing `Animation` can be
+//! nested in either.  Call `update` once a frame, which advances by the time since the last
+//! update, or `advance` to drive an animation from your own clock.
+//!
+//! ```rust
+//! let mut slide_in = Animator::new(
+//!     400,
+//!     point2(-32.0, 120.0),
+//!     point2(200.0, 120.0),
+//!     Easing::Out(Curve::Back),
+//! )
+//! .with_sprite_target(&sprite);
+//! // Each frame:
+//! slide_in.update()?;
+//! ```
+
+use {
+    crate::{
+        display::Display,
+        easing::Easing,
+        geometry::{GrPoint, GrRect, ScreenPoint},
+        sprite::Sprite,
+        system::System,
+    },
+    alloc::{boxed::Box, vec::Vec},
+    anyhow::Error,
+    euclid::{point2, size2},
+};
+
+/// Values an `Animator` can move between.
+pub trait Lerp: Copy {
+    /// The value `t` of the way from `self` to `to`.  `t` can be outside 0..1 for easing
+    /// curves that overshoot.
+    fn lerp(self, to: Self, t: f32) -> Self;
+}
+
+impl Lerp for f32 {
+    fn lerp(self, to: Self, t: f32) -> Self {
+        self + (to - self) * t
+    }
+}
+
+impl Lerp for GrPoint {
+    fn lerp(self, to: Self, t: f32) -> Self {
+        point2(self.x.lerp(to.x, t), self.y.lerp(to.y, t))
+    }
+}
+
+impl Lerp for ScreenPoint {
+    fn lerp(self, to: Self, t: f32) -> Self {
+        let lerp = |from: i32, to: i32| libm::roundf((from as f32).lerp(to as f32, t)) as i32;
+        point2(lerp(self.x, to.x), lerp(self.y, to.y))
+    }
+}
+
+impl Lerp for GrRect {
+    fn lerp(self, to: Self, t: f32) -> Self {
+        GrRect::new(
+            Lerp::lerp(self.origin, to.origin, t),
+            size2(
+                self.size.width.lerp(to.size.width, t),
+                self.size.height.lerp(to.size.height, t),
+            ),
+        )
+    }
+}
+
+/// Something that plays out over time: an `Animator`, `Sequence` or `Group`.
+pub trait Animation {
+    /// Moves forward by `elapsed_ms`, returning how much of it was left over if the animation
+    /// finished part way through.
+    fn advance(&mut self, elapsed_ms: usize) -> Result<usize, Error>;
+
+    fn is_finished(&self) -> bool;
+
+    /// Goes back to the beginning.
+    fn reset(&mut self);
+}
+
+// Measures the time between calls to `update`.
+#[derive(Clone, Copy, Debug, Default)]
+struct FrameClock {
+    last_update_ms: Option<usize>,
+}
+
+impl FrameClock {
+    fn elapsed(&mut self) -> Result<usize, Error> {
+        let now = System::get().get_current_time_milliseconds()?;
+        let elapsed = now.saturating_sub(self.last_update_ms.unwrap_or(now));
+        self.last_update_ms = Some(now);
+        Ok(elapsed)
+    }
+}
+
+type Target<T> = Box<dyn FnMut(T) -> Result<(), Error>>;
+
+pub struct Animator<T: Lerp> {
+    from: T,
+    to: T,
+    duration_ms: usize,
+    easing: Easing,
+    delay_ms: usize,
+    // Extra plays after the first; None repeats forever.
+    repeat_count: Option<usize>,
+    reverses: bool,
+    elapsed_ms: usize,
+    // Whether the final value has been applied, after which advancing does nothing.
+    done: bool,
+    value: T,
+    target: Option<Target<T>>,
+    clock: FrameClock,
+}
+
+impl<T: Lerp> Animator<T> {
+    pub fn new(duration_ms: usize, from: T, to: T, easing: Easing) -> Self {
+        Self {
+            from,
+            to,
+            duration_ms,
+            easing,
+            delay_ms: 0,
+            repeat_count: Some(0),
+            reverses: false,
+            elapsed_ms: 0,
+            done: false,
+            value: from,
+            target: None,
+            clock: FrameClock::default(),
+        }
+    }
+
+    /// Holds the starting value for `delay_ms` before moving.
+    pub fn with_delay(mut self, delay_ms: usize) -> Self {
+        self.delay_ms = delay_ms;
+        self
+    }
+
+    /// Plays `count` more times after the first, or forever for None.
+    pub fn with_repeat_count(mut self, count: Option<usize>) -> Self {
+        self.repeat_count = count;
+        self
+    }
+
+    /// Plays every other repeat backwards, from `to` to `from`.
+    pub fn with_reverses(mut self, reverses: bool) -> Self {
+        self.reverses = reverses;
+        self
+    }
+
+    /// Calls `target` with the value every time it's advanced, e.g. to move a sprite.
+    pub fn with_target<F>(mut self, target: F) -> Self
+    where
+        F: FnMut(T) -> Result<(), Error> + 'static,
+    {
+        self.target = Some(Box::new(target));
+        self
+    }
+
+    pub fn value(&self) -> T {
+        self.value
+    }
+
+    pub fn elapsed_ms(&self) -> usize {
+        self.elapsed_ms
+    }
+
+    /// The time until the animator finishes, including the delay and repeats, or None if it
+    /// repeats forever.
+    pub fn total_duration_ms(&self) -> Option<usize> {
+        self.repeat_count
+            .map(|count| self.delay_ms + self.duration_ms * (count + 1))
+    }
+
+    /// How far through the current play the animator is, from 0 to 1, before easing.
+    pub fn progress(&self) -> f32 {
+        self.play_position().1
+    }
+
+    /// Advances by the time since the last update and returns the new value.  Call once a
+    /// frame.
+    pub fn update(&mut self) -> Result<T, Error> {
+        let elapsed = self.clock.elapsed()?;
+        self.advance(elapsed)?;
+        Ok(self.value)
+    }
+
+    // The index of the current play, and the uneased progress through it.
+    fn play_position(&self) -> (usize, f32) {
+        if self.elapsed_ms < self.delay_ms {
+            return (0, 0.0);
+        }
+        let time = self.elapsed_ms - self.delay_ms;
+        if self.is_finished() || self.duration_ms == 0 {
+            return (self.repeat_count.unwrap_or(0), 1.0);
+        }
+        (
+            time / self.duration_ms,
+            (time % self.duration_ms) as f32 / self.duration_ms as f32,
+        )
+    }
+}
+
+impl<T: Lerp> Animation for Animator<T> {
+    fn advance(&mut self, elapsed_ms: usize) -> Result<usize, Error> {
+        if self.done {
+            return Ok(elapsed_ms);
+        }
+        self.elapsed_ms = self.elapsed_ms.saturating_add(elapsed_ms);
+        let mut leftover = 0;
+        if let Some(total) = self.total_duration_ms() {
+            leftover = self.elapsed_ms.saturating_sub(total);
+            self.elapsed_ms = self.elapsed_ms.min(total);
+        }
+        let (play, progress) = self.play_position();
+        let progress = if self.reverses && play % 2 == 1 {
+            1.0 - progress
+        } else {
+            progress
+        };
+        self.value = self.from.lerp(self.to, self.easing.apply(progress));
+        self.done = self.is_finished();
+        if let Some(target) = self.target.as_mut() {
+            target(self.value)?;
+        }
+        Ok(leftover)
+    }
+
+    fn is_finished(&self) -> bool {
+        self.total_duration_ms()
+            .is_some_and(|total| self.elapsed_ms >= total)
+    }
+
+    fn reset(&mut self) {
+        self.elapsed_ms = 0;
+        self.done = false;
+        self.value = self.from;
+        self.clock = FrameClock::default();
+    }
+}
+
+impl Animator<GrPoint> {
+    /// Moves `sprite` to the value, which is its center.
+    pub fn with_sprite_target(self, sprite: &Sprite) -> Self {
+        let mut sprite = sprite.clone();
+        self.with_target(move |point: GrPoint| sprite.move_to(point.x, point.y))
+    }
+}
+
+impl Animator<ScreenPoint> {
+    /// Moves `sprite` to the value, which is its center.
+    pub fn with_sprite_target(self, sprite: &Sprite) -> Self {
+        let mut sprite = sprite.clone();
+        self.with_target(move |point: ScreenPoint| sprite.move_to(point.x as f32, point.y as f32))
+    }
+
+    /// Offsets the whole display by the value, e.g. for screen shake.
+    pub fn with_display_offset_target(self) -> Self {
+        self.with_target(|offset| Display::get().set_offset(offset))
+    }
+}
+
+/// Animations that play one after another.
+#[derive(Default)]
+pub struct Sequence {
+    animations: Vec<Box<dyn Animation>>,
+    current: usize,
+    clock: FrameClock,
+}
+
+impl Sequence {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `animation` to play after the ones already added.
+    pub fn then<A>(mut self, animation: A) -> Self
+    where
+        A: Animation + 'static,
+    {
+        self.push(animation);
+        self
+    }
+
+    pub fn push<A>(&mut self, animation: A)
+    where
+        A: Animation + 'static,
+    {
+        self.animations.push(Box::new(animation));
+    }
+
+    /// Advances by the time since the last update.  Call once a frame.
+    pub fn update(&mut self) -> Result<(), Error> {
+        let elapsed = self.clock.elapsed()?;
+        self.advance(elapsed)?;
+        Ok(())
+    }
+}
+
+impl Animation for Sequence {
+    fn advance(&mut self, elapsed_ms: usize) -> Result<usize, Error> {
+        let mut remaining = elapsed_ms;
+        while let Some(animation) = self.animations.get_mut(self.current) {
+            // Time left over from one animation carries into the next, so the sequence keeps
+            // its total duration whatever the frame rate.
+            remaining = animation.advance(remaining)?;
+            if !animation.is_finished() {
+                return Ok(0);
+            }
+            self.current += 1;
+        }
+        Ok(remaining)
+    }
+
+    fn is_finished(&self) -> bool {
+        self.current >= self.animations.len()
+    }
+
+    fn reset(&mut self) {
+        self.animations
+            .iter_mut()
+            .for_each(|animation| animation.reset());
+        self.current = 0;
+        self.clock = FrameClock::default();
+    }
+}
+
+/// Animations that play at the same time.  The group finishes when all of them have.
+#[derive(Default)]
+pub struct Group {
+    animations: Vec<Box<dyn Animation>>,
+    clock: FrameClock,
+}
+
+impl Group {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `animation` to play alongside the ones already added.
+    pub fn with<A>(mut self, animation: A) -> Self
+    where
+        A: Animation + 'static,
+    {
+        self.push(animation);
+        self
+    }
+
+    pub fn push<A>(&mut self, animation: A)
+    where
+        A: Animation + 'static,
+    {
+        self.animations.push(Box::new(animation));
+    }
+
+    /// Advances by the time since the last update.  Call once a frame.
+    pub fn update(&mut self) -> Result<(), Error> {
+        let elapsed = self.clock.elapsed()?;
+        self.advance(elapsed)?;
+        Ok(())
+    }
+}
+
+impl Animation for Group {
+    fn advance(&mut self, elapsed_ms: usize) -> Result<usize, Error> {
+        // The group needed as long as its longest member, so it has that member's leftover.
+        let mut leftover = elapsed_ms;
+        for animation in self.animations.iter_mut() {
+            leftover = leftover.min(animation.advance(elapsed_ms)?);
+        }
+        Ok(leftover)
+    }
+
+    fn is_finished(&self) -> bool {
+        self.animations
+            .iter()
+            .all(|animation| animation.is_finished())
+    }
+
+    fn reset(&mut self) {
+        self.animations
+            .iter_mut()
+            .for_each(|animation| animation.reset());
+        self.clock = FrameClock::default();
+    }
+}
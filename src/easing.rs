@@ -0,0 +1,103 @@
+//! Easing curves, like the Lua SDK's `playdate.easingFunctions`.
+//!
+//! Each curve maps progress through an animation, from 0 to 1, to how far along the value
+//! should be.  Back and elastic curves overshoot, so they can return values outside 0..1.
+
+use core::f32::consts::PI;
+
+/// The shape of an easing curve, before choosing which end it eases.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Curve {
+    Quad,
+    Cubic,
+    Quart,
+    Quint,
+    Sine,
+    Expo,
+    Circ,
+    /// Pulls back before moving, or overshoots and settles.
+    Back,
+    Elastic,
+    Bounce,
+}
+
+impl Curve {
+    // The "in" form of the curve; the others are built from it.
+    fn ease_in(self, t: f32) -> f32 {
+        match self {
+            Curve::Quad => t * t,
+            Curve::Cubic => t * t * t,
+            Curve::Quart => t * t * t * t,
+            Curve::Quint => t * t * t * t * t,
+            Curve::Sine => 1.0 - libm::cosf(t * PI / 2.0),
+            Curve::Expo if t <= 0.0 => 0.0,
+            Curve::Expo => libm::powf(2.0, 10.0 * (t - 1.0)),
+            Curve::Circ => 1.0 - libm::sqrtf((1.0 - t * t).max(0.0)),
+            Curve::Back => {
+                const OVERSHOOT: f32 = 1.70158;
+                t * t * ((OVERSHOOT + 1.0) * t - OVERSHOOT)
+            }
+            Curve::Elastic if t <= 0.0 || t >= 1.0 => t,
+            Curve::Elastic => {
+                const PERIOD: f32 = 0.3;
+                -libm::powf(2.0, 10.0 * (t - 1.0))
+                    * libm::sinf((t - 1.0 - PERIOD / 4.0) * 2.0 * PI / PERIOD)
+            }
+            Curve::Bounce => 1.0 - bounce_out(1.0 - t),
+        }
+    }
+}
+
+fn bounce_out(t: f32) -> f32 {
+    const SCALE: f32 = 7.5625;
+    if t < 1.0 / 2.75 {
+        SCALE * t * t
+    } else if t < 2.0 / 2.75 {
+        let t = t - 1.5 / 2.75;
+        SCALE * t * t + 0.75
+    } else if t < 2.5 / 2.75 {
+        let t = t - 2.25 / 2.75;
+        SCALE * t * t + 0.9375
+    } else {
+        let t = t - 2.625 / 2.75;
+        SCALE * t * t + 0.984375
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub enum Easing {
+    #[default]
+    Linear,
+    /// Starts slowly.
+    In(Curve),
+    /// Ends slowly.
+    Out(Curve),
+    /// Starts and ends slowly.
+    InOut(Curve),
+    /// Slows down in the middle.
+    OutIn(Curve),
+    /// Any other curve; it should map 0 to 0 and 1 to 1.
+    Custom(fn(f32) -> f32),
+}
+
+impl Easing {
+    /// Eases `t`, which is clamped to 0..1.
+    pub fn apply(self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Easing::Linear => t,
+            Easing::In(curve) => curve.ease_in(t),
+            Easing::Out(curve) => 1.0 - curve.ease_in(1.0 - t),
+            Easing::InOut(curve) if t < 0.5 => curve.ease_in(t * 2.0) / 2.0,
+            Easing::InOut(curve) => 1.0 - curve.ease_in(2.0 - t * 2.0) / 2.0,
+            Easing::OutIn(curve) if t < 0.5 => (1.0 - curve.ease_in(1.0 - t * 2.0)) / 2.0,
+            Easing::OutIn(curve) => 0.5 + curve.ease_in(t * 2.0 - 1.0) / 2.0,
+            Easing::Custom(f) => f(t),
+        }
+    }
+
+    /// The eased value `t` of the way from `from` to `to`.
+    pub fn interpolate(self, from: f32, to: f32, t: f32) -> f32 {
+        from + (to - from) * self.apply(t)
+    }
+}
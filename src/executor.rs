@@ -0,0 +1,331 @@
+//! A small single-threaded executor, polled once per frame by `GameRunner::update` before
+//! `Game::update`, so game flows like cutscenes and tutorials can be written as `async` code
+//! rather than state machines.
+//!
+//! ```rust
+//! spawn(async {
+//!     show_message("Press A to continue");
+//!     button_pressed(PDButtons::kButtonA).await;
+//!     sleep(500).await;
+//!     show_message("Done");
+//! });
+//! ```
+//!
+//! Tasks are only polled from `GameRunner::update`, so they can safely use any of the
+//! crankstart APIs.  A spawned task first runs on the next frame.
+
+use {
+    crate::{
+        network::HttpConnection,
+        system::{PDButtons, System},
+    },
+    alloc::{boxed::Box, rc::Rc, vec::Vec},
+    anyhow::{anyhow, Error},
+    core::{
+        cell::{Cell, RefCell},
+        future::Future,
+        pin::Pin,
+        ptr::addr_of_mut,
+        task::{Context, Poll, RawWaker, RawWakerVTable, Waker},
+    },
+    crankstart_sys::PDNetErr,
+};
+
+static mut EXECUTOR: Option<Executor> = None;
+
+#[derive(Default)]
+struct TaskShared {
+    woken: Cell<bool>,
+    cancelled: Cell<bool>,
+    finished: Cell<bool>,
+}
+
+struct Task {
+    future: Pin<Box<dyn Future<Output = ()>>>,
+    shared: Rc<TaskShared>,
+}
+
+/// A handle to a spawned task.  Dropping it does not cancel the task.
+#[derive(Clone)]
+pub struct TaskHandle(Rc<TaskShared>);
+
+impl TaskHandle {
+    /// Stops the task; it won't be polled again, and is dropped on the next frame.
+    pub fn cancel(&self) {
+        self.0.cancelled.set(true);
+    }
+
+    /// Returns whether the task has run to completion or been cancelled.
+    pub fn is_finished(&self) -> bool {
+        self.0.finished.get() || self.0.cancelled.get()
+    }
+}
+
+/// Runs a future on the executor.
+pub fn spawn<F>(future: F) -> TaskHandle
+where
+    F: Future<Output = ()> + 'static,
+{
+    Executor::get().spawn(future)
+}
+
+pub struct Executor {
+    tasks: RefCell<Vec<Option<Task>>>,
+    // Tasks spawned since the last frame started, which first run on the next one.
+    spawned: RefCell<Vec<Task>>,
+    // Wakers for futures waiting on something that's checked once a frame.
+    frame_waiters: RefCell<Vec<Waker>>,
+    frame: Cell<u32>,
+    now_ms: Cell<usize>,
+    pushed: Cell<PDButtons>,
+}
+
+impl Executor {
+    fn new() -> Self {
+        Self {
+            tasks: RefCell::new(Vec::new()),
+            spawned: RefCell::new(Vec::new()),
+            frame_waiters: RefCell::new(Vec::new()),
+            frame: Cell::new(0),
+            now_ms: Cell::new(0),
+            pushed: Cell::new(PDButtons(0)),
+        }
+    }
+
+    pub fn get() -> &'static Executor {
+        // Only touched from the game's thread, so nothing else holds a reference.
+        unsafe { (*addr_of_mut!(EXECUTOR)).get_or_insert_with(Executor::new) }
+    }
+
+    fn spawn<F>(&self, future: F) -> TaskHandle
+    where
+        F: Future<Output = ()> + 'static,
+    {
+        let shared = Rc::new(TaskShared::default());
+        shared.woken.set(true);
+        self.spawned.borrow_mut().push(Task {
+            future: Box::pin(future),
+            shared: shared.clone(),
+        });
+        TaskHandle(shared)
+    }
+
+    /// Returns the number of tasks that haven't finished.
+    pub fn task_count(&self) -> usize {
+        self.tasks.borrow().iter().filter(|t| t.is_some()).count() + self.spawned.borrow().len()
+    }
+
+    /// Advances the frame count, wakes anything waiting on the frame, and polls woken tasks.
+    /// Called by `GameRunner::update`.
+    pub(crate) fn run_frame(&self) -> Result<(), Error> {
+        let system = System::get();
+        self.frame.set(self.frame.get().wrapping_add(1));
+        self.now_ms.set(system.get_current_time_milliseconds()?);
+        let (_, pushed, _) = system.get_button_state()?;
+        self.pushed.set(pushed);
+
+        let frame_waiters = core::mem::take(&mut *self.frame_waiters.borrow_mut());
+        for waker in frame_waiters {
+            waker.wake();
+        }
+
+        // Tasks spawned while polling stay queued until the next frame.
+        let spawned = core::mem::take(&mut *self.spawned.borrow_mut());
+        {
+            let mut tasks = self.tasks.borrow_mut();
+            let mut free_slots = tasks.iter_mut().filter(|slot| slot.is_none());
+            let mut overflow = Vec::new();
+            for task in spawned {
+                match free_slots.next() {
+                    Some(slot) => *slot = Some(task),
+                    None => overflow.push(Some(task)),
+                }
+            }
+            tasks.extend(overflow);
+        }
+
+        let task_count = self.tasks.borrow().len();
+        for index in 0..task_count {
+            // Take the task out while it runs, so it can spawn without a double borrow.
+            let task = self.tasks.borrow_mut()[index].take();
+            let Some(mut task) = task else {
+                continue;
+            };
+            if task.shared.cancelled.get() {
+                continue;
+            }
+            if !task.shared.woken.replace(false) {
+                self.tasks.borrow_mut()[index] = Some(task);
+                continue;
+            }
+            let waker = task_waker(task.shared.clone());
+            let mut context = Context::from_waker(&waker);
+            if task.future.as_mut().poll(&mut context).is_ready() {
+                task.shared.finished.set(true);
+            } else {
+                self.tasks.borrow_mut()[index] = Some(task);
+            }
+        }
+        Ok(())
+    }
+
+    fn wake_next_frame(&self, waker: &Waker) {
+        self.frame_waiters.borrow_mut().push(waker.clone());
+    }
+}
+
+fn task_waker(shared: Rc<TaskShared>) -> Waker {
+    unsafe { Waker::from_raw(raw_task_waker(Rc::into_raw(shared))) }
+}
+
+fn raw_task_waker(shared: *const TaskShared) -> RawWaker {
+    RawWaker::new(shared as *const (), &TASK_WAKER_VTABLE)
+}
+
+// Everything runs on the game's thread, so an Rc is enough to back the waker.
+static TASK_WAKER_VTABLE: RawWakerVTable = RawWakerVTable::new(
+    |data| unsafe {
+        Rc::increment_strong_count(data as *const TaskShared);
+        raw_task_waker(data as *const TaskShared)
+    },
+    |data| unsafe {
+        let shared = Rc::from_raw(data as *const TaskShared);
+        shared.woken.set(true);
+    },
+    |data| unsafe {
+        (*(data as *const TaskShared)).woken.set(true);
+    },
+    |data| unsafe {
+        drop(Rc::from_raw(data as *const TaskShared));
+    },
+);
+
+/// Completes on the next frame.
+pub fn next_frame() -> NextFrame {
+    NextFrame { target: None }
+}
+
+pub struct NextFrame {
+    target: Option<u32>,
+}
+
+impl Future for NextFrame {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let executor = Executor::get();
+        let frame = executor.frame.get();
+        let target = *self.target.get_or_insert(frame.wrapping_add(1));
+        if frame == target {
+            Poll::Ready(())
+        } else {
+            executor.wake_next_frame(cx.waker());
+            Poll::Pending
+        }
+    }
+}
+
+/// Completes once `ms` milliseconds have passed, checked once per frame.
+pub fn sleep(ms: usize) -> Sleep {
+    Sleep { ms, deadline: None }
+}
+
+pub struct Sleep {
+    ms: usize,
+    deadline: Option<usize>,
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let executor = Executor::get();
+        let now = executor.now_ms.get();
+        let ms = self.ms;
+        let deadline = *self.deadline.get_or_insert(now + ms);
+        if now >= deadline {
+            Poll::Ready(())
+        } else {
+            executor.wake_next_frame(cx.waker());
+            Poll::Pending
+        }
+    }
+}
+
+/// Completes on a later frame where any of the given buttons is pushed.  Presses during the frame
+/// the future is first polled are ignored, so awaiting the same button twice needs two presses.
+pub fn button_pressed(buttons: PDButtons) -> ButtonPressed {
+    ButtonPressed {
+        buttons,
+        start_frame: None,
+    }
+}
+
+pub struct ButtonPressed {
+    buttons: PDButtons,
+    start_frame: Option<u32>,
+}
+
+impl Future for ButtonPressed {
+    type Output = PDButtons;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<PDButtons> {
+        let executor = Executor::get();
+        let frame = executor.frame.get();
+        let start_frame = *self.start_frame.get_or_insert(frame);
+        let pressed = executor.pushed.get() & self.buttons;
+        if frame != start_frame && pressed.0 != 0 {
+            Poll::Ready(pressed)
+        } else {
+            executor.wake_next_frame(cx.waker());
+            Poll::Pending
+        }
+    }
+}
+
+#[derive(Default)]
+struct HttpCompleteState {
+    done: bool,
+    waker: Option<Waker>,
+}
+
+/// Completes when the connection's request completes, with an error if the connection reports
+/// one.  This replaces any callback set with `HttpConnection::on_request_complete`.
+pub fn http_request_complete(connection: &HttpConnection) -> Result<HttpRequestComplete, Error> {
+    let state = Rc::new(RefCell::new(HttpCompleteState::default()));
+    let callback_state = state.clone();
+    connection.on_request_complete(Some(move |_: &HttpConnection| {
+        let mut state = callback_state.borrow_mut();
+        state.done = true;
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    }))?;
+    Ok(HttpRequestComplete {
+        connection: connection.clone(),
+        state,
+    })
+}
+
+pub struct HttpRequestComplete {
+    connection: HttpConnection,
+    state: Rc<RefCell<HttpCompleteState>>,
+}
+
+impl Future for HttpRequestComplete {
+    type Output = Result<(), Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.state.borrow_mut();
+        if !state.done {
+            state.waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+        drop(state);
+        Poll::Ready(match self.connection.error() {
+            Ok(PDNetErr::NET_OK) => Ok(()),
+            Ok(err) => Err(anyhow!("HTTP request failed with {:?}", err)),
+            Err(err) => Err(err),
+        })
+    }
+}
@@ -9,7 +9,7 @@ use {
     alloc::{format, rc::Rc, vec::Vec},
     anyhow::{anyhow, ensure, Error},
     core::{cell::RefCell, ops::RangeInclusive, ptr, slice},
-    crankstart_sys::{ctypes::c_int, LCDBitmapTable, LCDPattern},
+    crankstart_sys::{ctypes::c_int, LCDBitmapTable},
     cstr_core::{CStr, CString},
     euclid::default::{Point2D, Vector2D},
     hashbrown::HashMap,
@@ -19,7 +19,20 @@ pub use crankstart_sys::{
     LCDBitmapDrawMode, LCDBitmapFlip, LCDLineCapStyle, LCDPolygonFillRule, LCDRect, LCDSolidColor,
     PDRect, PDStringEncoding, LCD_COLUMNS, LCD_ROWS, LCD_ROWSIZE,
 };
-use crankstart_sys::{PDTextAlignment, PDTextWrappingMode};
+pub use crankstart_sys::{PDTextAlignment, PDTextWrappingMode};
+
+pub mod pixels;
+pub use pixels::{BitPlane, BitPlaneMut, BitmapPixels, BitmapPixelsMut};
+pub mod pattern;
+pub use pattern::{AnimatedPattern, Pattern};
+pub mod dither;
+pub use dither::{dither_into, dither_to_bitmap, DitherMethod};
+pub mod font;
+pub use font::{Font, FontPage, Glyph};
+pub mod nine_slice;
+pub use nine_slice::{NineSlice, NineSliceSprite, SliceFill};
+pub mod text_layout;
+pub use text_layout::{FontFamily, TextLayout, TextLayoutOptions, TextLine, TextRun, TextStyle};
 
 pub fn rect_make(x: f32, y: f32, width: f32, height: f32) -> PDRect {
     PDRect {
@@ -33,21 +46,32 @@ pub fn rect_make(x: f32, y: f32, width: f32, height: f32) -> PDRect {
 #[derive(Clone, Debug)]
 pub enum LCDColor {
     Solid(LCDSolidColor),
-    Pattern(LCDPattern),
+    Pattern(Pattern),
 }
 
-impl From<LCDColor> for usize {
-    fn from(color: LCDColor) -> Self {
-        match color {
-            LCDColor::Solid(solid_color) => solid_color as usize,
-            LCDColor::Pattern(pattern) => {
-                let pattern_ptr = &pattern as *const u8;
-                pattern_ptr as usize
-            }
+impl LCDColor {
+    /// Returns the value the Playdate API expects.  For patterns this is a pointer, which is
+    /// only valid while `self` is alive, so keep `self` borrowed for the duration of the call.
+    pub fn as_raw(&self) -> usize {
+        match self {
+            LCDColor::Solid(solid_color) => *solid_color as usize,
+            LCDColor::Pattern(pattern) => pattern.as_raw_color(),
         }
     }
 }
 
+impl From<&LCDColor> for usize {
+    fn from(color: &LCDColor) -> Self {
+        color.as_raw()
+    }
+}
+
+impl From<LCDSolidColor> for LCDColor {
+    fn from(color: LCDSolidColor) -> Self {
+        LCDColor::Solid(color)
+    }
+}
+
 #[derive(Debug)]
 pub struct BitmapData {
     pub width: c_int,
@@ -171,7 +195,7 @@ impl BitmapInner {
         pd_func_caller!(
             (*Graphics::get_ptr()).clearBitmap,
             self.raw_bitmap,
-            color.into()
+            color.as_raw()
         )
     }
 
@@ -198,18 +222,11 @@ impl BitmapInner {
     }
 
     pub fn into_color(&self, bitmap: Bitmap, top_left: Point2D<i32>) -> Result<LCDColor, Error> {
-        let mut pattern = LCDPattern::default();
-        let pattern_ptr = pattern.as_mut_ptr();
-        let mut pattern_val = pattern_ptr as usize;
-        let graphics = Graphics::get();
-        pd_func_caller!(
-            (*graphics.0).setColorToPattern,
-            &mut pattern_val,
+        Ok(LCDColor::Pattern(Pattern::from_raw_bitmap(
             self.raw_bitmap,
             top_left.x,
-            top_left.y
-        )?;
-        Ok(LCDColor::Pattern(pattern))
+            top_left.y,
+        )?))
     }
 
     pub fn load(&self, path: &str) -> Result<(), Error> {
@@ -380,6 +397,17 @@ impl Bitmap {
         Ok(Bitmap::new(raw_bitmap, true))
     }
 
+    /// Returns the color of the pixel at `(x, y)`, taking the mask into account.  For many
+    /// pixels, `pixels()` is faster.
+    pub fn get_pixel(&self, x: i32, y: i32) -> Result<LCDSolidColor, Error> {
+        pd_func_caller!(
+            (*Graphics::get_ptr()).getBitmapPixel,
+            self.inner.borrow().raw_bitmap,
+            x,
+            y
+        )
+    }
+
     pub fn copy(&self) -> Result<Bitmap, Error> {
         let raw_bitmap = pd_func_caller!(
             (*Graphics::get_ptr()).copyBitmap,
@@ -399,21 +427,6 @@ fn raw_bitmap(bitmap: OptionalBitmap<'_>) -> *mut crankstart_sys::LCDBitmap {
     }
 }
 
-pub struct Font(*mut crankstart_sys::LCDFont);
-
-impl Font {
-    pub fn new(font: *mut crankstart_sys::LCDFont) -> Result<Self, Error> {
-        anyhow::ensure!(!font.is_null(), "Null pointer passed to Font::new");
-        Ok(Self(font))
-    }
-}
-
-impl Drop for Font {
-    fn drop(&mut self) {
-        log_to_console!("Leaking a font");
-    }
-}
-
 #[derive(Debug)]
 struct BitmapTableInner {
     raw_bitmap_table: *mut LCDBitmapTable,
@@ -500,6 +513,19 @@ impl BitmapTable {
     pub fn get_bitmap(&self, index: usize) -> Result<Bitmap, Error> {
         self.inner.borrow_mut().get_bitmap(index)
     }
+
+    /// The number of bitmaps in the table.
+    pub fn count(&self) -> Result<usize, Error> {
+        let mut count = 0;
+        let mut width = 0;
+        pd_func_caller!(
+            (*Graphics::get_ptr()).getBitmapTableInfo,
+            self.inner.borrow().raw_bitmap_table,
+            &mut count,
+            &mut width
+        )?;
+        Ok(count as usize)
+    }
 }
 
 static mut GRAPHICS: Graphics = Graphics(ptr::null_mut());
@@ -617,12 +643,28 @@ impl Graphics {
         pd_func_caller!((*self.0).setDrawOffset, offset.x, offset.y)
     }
 
+    /// Limits drawing to `rect`, which is in the current context's coordinates and is moved by
+    /// the draw offset.
+    pub fn set_clip_rect(&self, rect: ScreenRect) -> Result<(), Error> {
+        pd_func_caller!(
+            (*self.0).setClipRect,
+            rect.origin.x,
+            rect.origin.y,
+            rect.size.width,
+            rect.size.height
+        )
+    }
+
+    pub fn clear_clip_rect(&self) -> Result<(), Error> {
+        pd_func_caller!((*self.0).clearClipRect)
+    }
+
     pub fn new_bitmap(&self, size: ScreenSize, bg_color: LCDColor) -> Result<Bitmap, Error> {
         let raw_bitmap = pd_func_caller!(
             (*self.0).newBitmap,
             size.width,
             size.height,
-            bg_color.into()
+            bg_color.as_raw()
         )?;
         anyhow::ensure!(
             !raw_bitmap.is_null(),
@@ -680,7 +722,7 @@ impl Graphics {
     }
 
     pub fn clear(&self, color: LCDColor) -> Result<(), Error> {
-        pd_func_caller!((*self.0).clear, color.into())
+        pd_func_caller!((*self.0).clear, color.as_raw())
     }
 
     pub fn draw_line(
@@ -697,7 +739,7 @@ impl Graphics {
             p2.x,
             p2.y,
             width,
-            color.into(),
+            color.as_raw(),
         )
     }
 
@@ -717,7 +759,7 @@ impl Graphics {
             (*self.0).fillPolygon,
             n_pts as i32,
             coords_seq.as_mut_ptr(),
-            color.into(),
+            color.as_raw(),
             fillrule
         )?;
 
@@ -739,10 +781,15 @@ impl Graphics {
             p2.y,
             p3.x,
             p3.y,
-            color.into(),
+            color.as_raw(),
         )
     }
 
+    /// Sets a single pixel in the current drawing context.
+    pub fn set_pixel(&self, point: ScreenPoint, color: LCDColor) -> Result<(), Error> {
+        pd_func_caller!((*self.0).setPixel, point.x, point.y, color.as_raw())
+    }
+
     pub fn draw_rect(&self, rect: ScreenRect, color: LCDColor) -> Result<(), Error> {
         pd_func_caller!(
             (*self.0).drawRect,
@@ -750,7 +797,7 @@ impl Graphics {
             rect.origin.y,
             rect.size.width,
             rect.size.height,
-            color.into(),
+            color.as_raw(),
         )
     }
 
@@ -761,7 +808,7 @@ impl Graphics {
             rect.origin.y,
             rect.size.width,
             rect.size.height,
-            color.into(),
+            color.as_raw(),
         )
     }
 
@@ -783,7 +830,7 @@ impl Graphics {
             line_width,
             start_angle,
             end_angle,
-            color.into(),
+            color.as_raw(),
         )
     }
 
@@ -807,7 +854,7 @@ impl Graphics {
             size.height,
             start_angle,
             end_angle,
-            color.into(),
+            color.as_raw(),
         )
     }
 
@@ -830,7 +877,7 @@ impl Graphics {
     }
 
     pub fn set_font(&self, font: &Font) -> Result<(), Error> {
-        pd_func_caller_log!((*self.0).setFont, font.0);
+        pd_func_caller_log!((*self.0).setFont, font.raw());
         Ok(())
     }
 
@@ -872,7 +919,7 @@ impl Graphics {
         let c_text = CString::new(text).map_err(Error::msg)?;
         pd_func_caller!(
             (*self.0).getTextWidth,
-            font.0,
+            font.raw(),
             c_text.as_ptr() as *const core::ffi::c_void,
             text.len(),
             PDStringEncoding::kUTF8Encoding,
@@ -880,8 +927,33 @@ impl Graphics {
         )
     }
 
+    /// Returns the height `text` would take up if wrapped to `max_width`, as
+    /// `draw_text_in_rect` would lay it out.
+    pub fn get_text_height_for_max_width(
+        &self,
+        font: &Font,
+        text: &str,
+        max_width: i32,
+        wrapping_mode: PDTextWrappingMode,
+        tracking: i32,
+        extra_leading: i32,
+    ) -> Result<i32, Error> {
+        let c_text = CString::new(text).map_err(Error::msg)?;
+        pd_func_caller!(
+            (*self.0).getTextHeightForMaxWidth,
+            font.raw(),
+            c_text.as_ptr() as *const core::ffi::c_void,
+            text.len(),
+            max_width,
+            PDStringEncoding::kUTF8Encoding,
+            wrapping_mode,
+            tracking,
+            extra_leading,
+        )
+    }
+
     pub fn get_font_height(&self, font: &Font) -> Result<u8, Error> {
-        pd_func_caller!((*self.0).getFontHeight, font.0)
+        pd_func_caller!((*self.0).getFontHeight, font.raw())
     }
 
     pub fn get_system_text_width(&self, text: &str, tracking: i32) -> Result<i32, Error> {
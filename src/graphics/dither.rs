@@ -0,0 +1,314 @@
+//! Converts 8-bit grayscale images to 1-bit bitmaps.
+//!
+//! `dither_into` works on plain buffers and doesn't touch the Playdate API, so it can run on
+//! the host; `dither_to_bitmap` wraps it to produce a `Bitmap`, with an optional mask from an
+//! alpha channel.  In the grayscale input 0 is black and 255 is white.
+
+use {
+    super::{pattern::BAYER_8X8, Bitmap, Graphics, LCDColor, LCDSolidColor},
+    crate::geometry::ScreenSize,
+    alloc::vec,
+    anyhow::{ensure, Error},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DitherMethod {
+    /// Plain 50% threshold, with no dithering.
+    Threshold,
+    /// Ordered dithering with the 8x8 Bayer matrix; stable under animation, but with a visible
+    /// cross-hatch texture.
+    Bayer,
+    /// Error diffusion that spreads all of each pixel's error; the most accurate tones.
+    FloydSteinberg,
+    /// Error diffusion that spreads 3/4 of each pixel's error, as on the original Macintosh;
+    /// higher contrast, with cleaner highlights and shadows.
+    Atkinson,
+    /// Ordered dithering with a 16x16 blue noise matrix; stable under animation, with a less
+    /// regular texture than Bayer.
+    BlueNoise,
+}
+
+/// Returns the number of bytes per row for a 1-bit image of `width` pixels, padded to a
+/// multiple of 32 bits as Playdate bitmaps are.
+pub fn rowbytes_for_width(width: usize) -> usize {
+    width.div_ceil(32) * 4
+}
+
+/// Dithers the `width` by `height` grayscale image in `gray` into 1-bit rows in `out`, which
+/// must hold `height` rows of `rowbytes` bytes.  Set bits are white.
+pub fn dither_into(
+    gray: &[u8],
+    width: usize,
+    height: usize,
+    method: DitherMethod,
+    out: &mut [u8],
+    rowbytes: usize,
+) -> Result<(), Error> {
+    ensure!(
+        gray.len() >= width * height,
+        "Grayscale buffer holds {} bytes, but {}x{} needs {}",
+        gray.len(),
+        width,
+        height,
+        width * height
+    );
+    ensure!(rowbytes * 8 >= width, "rowbytes too small for width");
+    ensure!(
+        out.len() >= rowbytes * height,
+        "Output buffer too small for {} rows of {} bytes",
+        height,
+        rowbytes
+    );
+    out[..rowbytes * height].fill(0);
+    match method {
+        DitherMethod::Threshold => ordered(gray, width, height, out, rowbytes, 1, |_, _| 0),
+        DitherMethod::Bayer => ordered(gray, width, height, out, rowbytes, 64, |x, y| {
+            BAYER_8X8[y % 8][x % 8] as u32
+        }),
+        DitherMethod::BlueNoise => ordered(gray, width, height, out, rowbytes, 256, |x, y| {
+            BLUE_NOISE[(y % BLUE_NOISE_SIZE) * BLUE_NOISE_SIZE + x % BLUE_NOISE_SIZE] as u32
+        }),
+        DitherMethod::FloydSteinberg => diffuse(
+            gray,
+            width,
+            height,
+            out,
+            rowbytes,
+            16,
+            &[(1, 0, 7), (-1, 1, 3), (0, 1, 5), (1, 1, 1)],
+        ),
+        DitherMethod::Atkinson => diffuse(
+            gray,
+            width,
+            height,
+            out,
+            rowbytes,
+            8,
+            &[
+                (1, 0, 1),
+                (2, 0, 1),
+                (-1, 1, 1),
+                (0, 1, 1),
+                (1, 1, 1),
+                (0, 2, 1),
+            ],
+        ),
+    }
+    Ok(())
+}
+
+/// Dithers a grayscale image into a new bitmap.  If `alpha` is given (same size as `gray`, 0
+/// transparent to 255 opaque), it's dithered with the same method into the bitmap's mask.
+pub fn dither_to_bitmap(
+    gray: &[u8],
+    width: usize,
+    height: usize,
+    method: DitherMethod,
+    alpha: Option<&[u8]>,
+) -> Result<Bitmap, Error> {
+    let graphics = Graphics::get();
+    let size = ScreenSize::new(width as i32, height as i32);
+    let mut bitmap = graphics.new_bitmap(size, LCDColor::Solid(LCDSolidColor::kColorBlack))?;
+    if alpha.is_some() {
+        let mask = graphics.new_bitmap(size, LCDColor::Solid(LCDSolidColor::kColorWhite))?;
+        bitmap.set_mask(Some(mask))?;
+    }
+    let mut pixels = bitmap.pixels_mut()?;
+    let rowbytes = pixels.rowbytes();
+    dither_into(
+        gray,
+        width,
+        height,
+        method,
+        pixels.data_mut().as_bytes_mut(),
+        rowbytes,
+    )?;
+    if let Some(alpha) = alpha {
+        let mask = pixels
+            .mask_mut()
+            .ok_or_else(|| anyhow::anyhow!("Bitmap mask wasn't set"))?;
+        dither_into(alpha, width, height, method, mask.as_bytes_mut(), rowbytes)?;
+    }
+    drop(pixels);
+    Ok(bitmap)
+}
+
+// Turns a gray value into the number of matrix cells (out of `cells`) that should be white.
+fn level(gray: u8, cells: u32) -> u32 {
+    (gray as u32 * cells + 127) / 255
+}
+
+fn ordered(
+    gray: &[u8],
+    width: usize,
+    height: usize,
+    out: &mut [u8],
+    rowbytes: usize,
+    cells: u32,
+    rank: impl Fn(usize, usize) -> u32,
+) {
+    for y in 0..height {
+        let row = &mut out[y * rowbytes..(y + 1) * rowbytes];
+        for x in 0..width {
+            if rank(x, y) < level(gray[y * width + x], cells) {
+                row[x / 8] |= 0x80 >> (x % 8);
+            }
+        }
+    }
+}
+
+// Error diffusion; `weights` are (dx, dy, weight) over `divisor`, for dy of at most 2.
+fn diffuse(
+    gray: &[u8],
+    width: usize,
+    height: usize,
+    out: &mut [u8],
+    rowbytes: usize,
+    divisor: i32,
+    weights: &[(isize, usize, i32)],
+) {
+    // Three rows of accumulated error, with two columns of padding on each side.
+    let stride = width + 4;
+    let mut errors = vec![0i32; stride * 3];
+    for y in 0..height {
+        for x in 0..width {
+            let value = gray[y * width + x] as i32 + errors[x + 2];
+            let white = value >= 128;
+            if white {
+                out[y * rowbytes + x / 8] |= 0x80 >> (x % 8);
+            }
+            let error = value - if white { 255 } else { 0 };
+            for &(dx, dy, weight) in weights {
+                let index = dy * stride + (x as isize + 2 + dx) as usize;
+                errors[index] += error * weight / divisor;
+            }
+        }
+        // Shift the error rows up by one.
+        errors.copy_within(stride.., 0);
+        errors[stride * 2..].fill(0);
+    }
+}
+
+const BLUE_NOISE_SIZE: usize = 16;
+
+// A 16x16 blue noise threshold matrix, ranks 0 to 255, made with Ulichney's void-and-cluster
+// method (Gaussian sigma 1.5, on a torus so it tiles).
+#[rustfmt::skip]
+const BLUE_NOISE: [u8; BLUE_NOISE_SIZE * BLUE_NOISE_SIZE] = [
+    203,  13,  93, 180,  29, 124, 249,  44, 110, 231,  25, 184, 132,   6, 143,  53,
+    167, 225,  62, 136, 232, 159,  64, 172,  19, 193,  78,  48, 253,  83, 217,  34,
+     77, 118, 186,  23, 204,  85,   5, 221, 141, 116, 211, 150, 173, 119, 194, 100,
+    151,   0, 240, 103,  49, 148, 192,  99,  70, 241,  10,  94,  31,  63,  17, 245,
+     41, 207, 160,  74, 218, 121, 255,  32, 165,  43, 182, 222, 142, 234, 178, 129,
+     95,  58, 134,  30, 170,  12,  59, 133, 205, 105, 128,  55, 199, 107,  73, 214,
+    171, 251, 113, 198, 237,  92, 185, 227,  76,   1, 250,  86,  15,  40, 157,   8,
+     35, 190,  16,  80,  50, 156, 111,  22, 149, 174, 213, 144, 169, 242, 117, 229,
+     69, 101, 145, 228, 123, 212,  37, 246, 120,  52,  33, 109,  65, 187,  89, 138,
+    168, 216,  45, 181,   4, 166,  66,  88, 189, 236,  82, 201, 224,  26,  51, 208,
+    125,  24, 248,  75,  97, 233, 140, 209,  14, 137, 163,   9, 127, 152, 254,   2,
+     96,  60, 153, 202, 131,  20, 175,  39, 102,  67, 230,  46,  98, 179,  79, 196,
+    235, 177, 115,  36, 239,  56, 112, 219, 158, 188, 122, 197, 238,  57,  28, 135,
+     42, 215,   7,  87, 162, 191,  81, 244,  54,   3,  84,  21, 147, 114, 220, 161,
+    104,  71, 195, 139, 223,  11, 146,  27, 130, 206, 247, 164,  38, 200,  90,  18,
+    126, 155, 252,  47, 108,  72, 210, 183,  91, 154,  61, 106, 226,  68, 176, 243,
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const METHODS: [DitherMethod; 5] = [
+        DitherMethod::Threshold,
+        DitherMethod::Bayer,
+        DitherMethod::FloydSteinberg,
+        DitherMethod::Atkinson,
+        DitherMethod::BlueNoise,
+    ];
+
+    fn dither(gray: u8, width: usize, height: usize, method: DitherMethod) -> Vec<u8> {
+        let rowbytes = rowbytes_for_width(width);
+        let mut out = vec![0xaa; rowbytes * height];
+        dither_into(
+            &vec![gray; width * height],
+            width,
+            height,
+            method,
+            &mut out,
+            rowbytes,
+        )
+        .unwrap();
+        out
+    }
+
+    fn white_count(out: &[u8]) -> u32 {
+        out.iter().map(|byte| byte.count_ones()).sum()
+    }
+
+    #[test]
+    fn rowbytes_pad_to_32_bits() {
+        assert_eq!(rowbytes_for_width(0), 0);
+        assert_eq!(rowbytes_for_width(1), 4);
+        assert_eq!(rowbytes_for_width(32), 4);
+        assert_eq!(rowbytes_for_width(33), 8);
+        assert_eq!(rowbytes_for_width(400), 52);
+    }
+
+    #[test]
+    fn black_and_white_are_exact_for_every_method() {
+        for &method in &METHODS {
+            assert_eq!(dither(0, 10, 3, method), vec![0; 12], "{:?}", method);
+            // White fills the first 10 bits of each row and leaves the padding clear.
+            let white = dither(255, 10, 3, method);
+            for row in white.chunks(4) {
+                assert_eq!(row, &[0xff, 0xc0, 0, 0], "{:?}", method);
+            }
+        }
+    }
+
+    #[test]
+    fn threshold_splits_at_128() {
+        assert_eq!(white_count(&dither(127, 32, 2, DitherMethod::Threshold)), 0);
+        assert_eq!(
+            white_count(&dither(128, 32, 2, DitherMethod::Threshold)),
+            64
+        );
+    }
+
+    #[test]
+    fn ordered_methods_match_the_gray_level() {
+        // 50% gray lights half of each 8x8 Bayer tile.
+        assert_eq!(white_count(&dither(128, 8, 8, DitherMethod::Bayer)), 32);
+        // 25% gray lights a quarter of the 16x16 blue noise tile.
+        assert_eq!(
+            white_count(&dither(64, 16, 16, DitherMethod::BlueNoise)),
+            64
+        );
+    }
+
+    #[test]
+    fn error_diffusion_keeps_the_average() {
+        for &method in &[DitherMethod::FloydSteinberg, DitherMethod::Atkinson] {
+            let count = white_count(&dither(128, 32, 32, method));
+            assert!((460..=564).contains(&count), "{:?}: {}", method, count);
+        }
+    }
+
+    #[test]
+    fn blue_noise_ranks_each_level_once() {
+        let mut seen = [false; 256];
+        for &rank in BLUE_NOISE.iter() {
+            assert!(!seen[rank as usize], "rank {} repeated", rank);
+            seen[rank as usize] = true;
+        }
+    }
+
+    #[test]
+    fn rejects_short_buffers() {
+        let mut out = [0u8; 8];
+        let method = DitherMethod::Bayer;
+        assert!(dither_into(&[0; 15], 4, 4, method, &mut out, 2).is_err());
+        assert!(dither_into(&[0; 16], 16, 1, method, &mut out, 1).is_err());
+        assert!(dither_into(&[0; 16], 4, 4, method, &mut out[..7], 2).is_err());
+        assert!(dither_into(&[0; 16], 4, 4, method, &mut out, 2).is_ok());
+    }
+}
@@ -0,0 +1,220 @@
+//! Fonts, per-glyph metrics and text spacing.
+
+use {
+    super::{Bitmap, Graphics},
+    crate::{log_to_console, pd_func_caller},
+    alloc::{rc::Rc, vec::Vec},
+    anyhow::{anyhow, ensure, Error},
+    core::ptr,
+    crankstart_sys::{LCDFont, LCDFontData, LCDFontGlyph, LCDFontPage},
+};
+
+// Uncompressed .pft files start with this magic and a little-endian u32 of flags.
+const PFT_MAGIC: &[u8] = b"Playdate FNT";
+const PFT_HEADER_LEN: usize = 16;
+const PFT_FLAG_WIDE: u32 = 0x0000_0001;
+const PFT_FLAG_COMPRESSED: u32 = 0x8000_0000;
+
+// The API can set leading but not read it, so remember the last value set.  This is global,
+// not per drawing context.
+static mut TEXT_LEADING: i32 = 0;
+
+#[derive(Debug)]
+struct FontInner {
+    raw_font: *mut LCDFont,
+}
+
+impl Drop for FontInner {
+    fn drop(&mut self) {
+        log_to_console!("Leaking a font");
+    }
+}
+
+/// A loaded font.  Clones share the same font.
+#[derive(Clone, Debug)]
+pub struct Font {
+    inner: Rc<FontInner>,
+}
+
+impl Font {
+    pub fn new(font: *mut LCDFont) -> Result<Self, Error> {
+        ensure!(!font.is_null(), "Null pointer passed to Font::new");
+        Ok(Self {
+            inner: Rc::new(FontInner { raw_font: font }),
+        })
+    }
+
+    pub fn raw(&self) -> *mut LCDFont {
+        self.inner.raw_font
+    }
+
+    pub fn height(&self) -> Result<u8, Error> {
+        pd_func_caller!((*Graphics::get_ptr()).getFontHeight, self.raw())
+    }
+
+    /// Returns the width of `text` in this font with the given tracking.
+    pub fn text_width(&self, text: &str, tracking: i32) -> Result<i32, Error> {
+        Graphics::get().get_text_width(self, text, tracking)
+    }
+
+    /// Returns the page of glyphs containing `c`.  Pages hold 256 consecutive code points.
+    pub fn page(&self, c: char) -> Result<FontPage, Error> {
+        let raw_page = pd_func_caller!((*Graphics::get_ptr()).getFontPage, self.raw(), c as u32)?;
+        ensure!(!raw_page.is_null(), "Font has no page for {:?}", c);
+        Ok(FontPage {
+            font: self.clone(),
+            raw_page,
+        })
+    }
+
+    /// Returns the glyph for `c`, or None if the font doesn't have one.
+    pub fn glyph(&self, c: char) -> Result<Option<Glyph>, Error> {
+        match self.page(c) {
+            Ok(page) => page.glyph(c),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Returns the kerning adjustment between `c` and `next`, or 0 if the font has no glyph
+    /// for `c`.
+    pub fn kerning(&self, c: char, next: char) -> Result<i32, Error> {
+        match self.glyph(c)? {
+            Some(glyph) => glyph.kerning(next),
+            None => Ok(0),
+        }
+    }
+}
+
+/// A page of up to 256 glyphs in a font; see `Font::page`.
+#[derive(Clone, Debug)]
+pub struct FontPage {
+    font: Font,
+    raw_page: *mut LCDFontPage,
+}
+
+impl FontPage {
+    pub fn font(&self) -> &Font {
+        &self.font
+    }
+
+    /// Returns the glyph for `c`, or None if the page doesn't have one.
+    pub fn glyph(&self, c: char) -> Result<Option<Glyph>, Error> {
+        let mut raw_bitmap = ptr::null_mut();
+        let mut advance = 0;
+        let raw_glyph = pd_func_caller!(
+            (*Graphics::get_ptr()).getPageGlyph,
+            self.raw_page,
+            c as u32,
+            &mut raw_bitmap,
+            &mut advance
+        )?;
+        if raw_glyph.is_null() {
+            return Ok(None);
+        }
+        // The glyph bitmap belongs to the font, which is never freed.
+        let bitmap = (!raw_bitmap.is_null()).then(|| Bitmap::new(raw_bitmap, false));
+        Ok(Some(Glyph {
+            font: self.font.clone(),
+            raw_glyph,
+            code: c,
+            bitmap,
+            advance,
+        }))
+    }
+}
+
+/// A single character's image and metrics; see `Font::glyph`.
+#[derive(Clone, Debug)]
+pub struct Glyph {
+    font: Font,
+    raw_glyph: *mut LCDFontGlyph,
+    code: char,
+    bitmap: Option<Bitmap>,
+    advance: i32,
+}
+
+impl Glyph {
+    pub fn font(&self) -> &Font {
+        &self.font
+    }
+
+    pub fn code(&self) -> char {
+        self.code
+    }
+
+    /// The glyph's image, or None for glyphs with nothing to draw, like space.
+    pub fn bitmap(&self) -> Option<&Bitmap> {
+        self.bitmap.as_ref()
+    }
+
+    /// How far to move right after drawing this glyph, before tracking and kerning.
+    pub fn advance(&self) -> i32 {
+        self.advance
+    }
+
+    /// Returns the kerning adjustment to apply when `next` follows this glyph.
+    pub fn kerning(&self, next: char) -> Result<i32, Error> {
+        pd_func_caller!(
+            (*Graphics::get_ptr()).getGlyphKerning,
+            self.raw_glyph,
+            self.code as u32,
+            next as u32
+        )
+    }
+}
+
+impl Graphics {
+    /// Creates a font from the contents of an uncompressed `.pft` file, e.g. from
+    /// `include_bytes!` or `FileSystem`.  The font keeps using `data`, so it's leaked along
+    /// with the font.
+    pub fn make_font_from_data(&self, data: Vec<u8>) -> Result<Font, Error> {
+        ensure!(
+            data.len() > PFT_HEADER_LEN && data.starts_with(PFT_MAGIC),
+            "Font data isn't a .pft file"
+        );
+        let mut flags = [0u8; 4];
+        flags.copy_from_slice(&data[PFT_MAGIC.len()..PFT_HEADER_LEN]);
+        let flags = u32::from_le_bytes(flags);
+        ensure!(
+            flags & PFT_FLAG_COMPRESSED == 0,
+            "Compressed .pft data isn't supported; compile the font uncompressed"
+        );
+        let wide = flags & PFT_FLAG_WIDE != 0;
+        let data = data.leak();
+        let raw_font = pd_func_caller!(
+            (*Graphics::get_ptr()).makeFontFromData,
+            data[PFT_HEADER_LEN..].as_mut_ptr() as *mut LCDFontData,
+            wide as i32
+        )?;
+        if raw_font.is_null() {
+            return Err(anyhow!("makeFontFromData failed"));
+        }
+        Font::new(raw_font)
+    }
+
+    /// Sets the extra space added between characters when drawing text.
+    pub fn set_text_tracking(&self, tracking: i32) -> Result<(), Error> {
+        pd_func_caller!((*Graphics::get_ptr()).setTextTracking, tracking)
+    }
+
+    pub fn get_text_tracking(&self) -> Result<i32, Error> {
+        pd_func_caller!((*Graphics::get_ptr()).getTextTracking)
+    }
+
+    /// Sets the extra space added between lines when drawing text.
+    pub fn set_text_leading(&self, leading: i32) -> Result<(), Error> {
+        pd_func_caller!((*Graphics::get_ptr()).setTextLeading, leading)?;
+        unsafe {
+            TEXT_LEADING = leading;
+        }
+        Ok(())
+    }
+
+    /// Returns the leading last set with `set_text_leading`, in any drawing context; the
+    /// Playdate API has no way to read it back.  Leading is part of each context's state, so
+    /// after `Graphics::with_context` returns this may not match the restored context's
+    /// leading; set it again after switching contexts if it matters.
+    pub fn get_text_leading(&self) -> i32 {
+        unsafe { TEXT_LEADING }
+    }
+}
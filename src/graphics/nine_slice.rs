@@ -0,0 +1,301 @@
+//! Stretchable frames drawn from a bitmap split into nine parts.
+//!
+//! The corners are always drawn at their natural size, the edges are stretched or tiled along
+//! their length, and the center fills what's left, as with the Lua SDK's
+//! `playdate.graphics.nineSlice`.
+
+use {
+    super::{Bitmap, Graphics, LCDBitmapFlip, LCDColor, LCDSolidColor},
+    crate::{
+        geometry::{ScreenPoint, ScreenRect, ScreenSize},
+        sprite::{Sprite, SpriteManager},
+    },
+    alloc::vec::Vec,
+    anyhow::{ensure, Error},
+    core::cell::RefCell,
+    crankstart_sys::PDRect,
+    euclid::{point2, size2, vec2},
+};
+
+/// How an edge or the center fills the space between the corners.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SliceFill {
+    Stretch,
+    Tile,
+}
+
+#[derive(Debug)]
+pub struct NineSlice {
+    // Slices in row-major order: top left, top, top right, left, center, ...
+    slices: Vec<Bitmap>,
+    source_size: ScreenSize,
+    center: ScreenRect,
+    edge_fill: SliceFill,
+    center_fill: SliceFill,
+    cache: RefCell<Vec<(ScreenSize, Bitmap)>>,
+    cache_capacity: usize,
+}
+
+// Each clone gets its own cache, since the fills can be changed independently and the cache
+// is only keyed by size.
+impl Clone for NineSlice {
+    fn clone(&self) -> Self {
+        Self {
+            slices: self.slices.clone(),
+            source_size: self.source_size,
+            center: self.center,
+            edge_fill: self.edge_fill,
+            center_fill: self.center_fill,
+            cache: RefCell::new(Vec::new()),
+            cache_capacity: self.cache_capacity,
+        }
+    }
+}
+
+impl NineSlice {
+    /// Splits `bitmap` around `center`, the part that fills the middle of the frame; the areas
+    /// around it become the corners and edges.
+    pub fn new(bitmap: &Bitmap, center: ScreenRect) -> Result<Self, Error> {
+        let data = bitmap.get_data()?;
+        let source_size = size2(data.width, data.height);
+        ensure!(
+            ScreenRect::new(point2(0, 0), source_size).contains_rect(&center),
+            "Nine-slice center {:?} isn't inside the {}x{} bitmap",
+            center,
+            data.width,
+            data.height
+        );
+        let graphics = Graphics::get();
+        let xs = [0, center.min_x(), center.max_x(), data.width];
+        let ys = [0, center.min_y(), center.max_y(), data.height];
+        let mut slices = Vec::with_capacity(9);
+        for row in 0..3 {
+            for column in 0..3 {
+                let size: ScreenSize = size2(xs[column + 1] - xs[column], ys[row + 1] - ys[row]);
+                // Empty slices still need a bitmap; they're never drawn.
+                let slice = graphics.new_bitmap(
+                    size2(size.width.max(1), size.height.max(1)),
+                    LCDColor::Solid(LCDSolidColor::kColorClear),
+                )?;
+                if !size.is_empty() {
+                    graphics.with_context(Some(&slice), || {
+                        bitmap.draw(
+                            point2(-xs[column], -ys[row]),
+                            LCDBitmapFlip::kBitmapUnflipped,
+                        )
+                    })?;
+                }
+                slices.push(slice);
+            }
+        }
+        Ok(Self {
+            slices,
+            source_size,
+            center,
+            edge_fill: SliceFill::Stretch,
+            center_fill: SliceFill::Stretch,
+            cache: RefCell::new(Vec::new()),
+            cache_capacity: 4,
+        })
+    }
+
+    /// Loads a bitmap from `path` and splits it; see `new`.
+    pub fn load(path: &str, center: ScreenRect) -> Result<Self, Error> {
+        Self::new(&Graphics::get().load_bitmap(path)?, center)
+    }
+
+    pub fn set_edge_fill(&mut self, fill: SliceFill) {
+        self.edge_fill = fill;
+        self.clear_cache();
+    }
+
+    pub fn set_center_fill(&mut self, fill: SliceFill) {
+        self.center_fill = fill;
+        self.clear_cache();
+    }
+
+    /// Sets how many sizes of composed frame to keep; 0 turns caching off.
+    pub fn set_cache_capacity(&mut self, capacity: usize) {
+        self.cache_capacity = capacity;
+        self.cache.borrow_mut().truncate(capacity);
+    }
+
+    pub fn clear_cache(&self) {
+        self.cache.borrow_mut().clear();
+    }
+
+    /// The smallest size the frame can be drawn at without the corners overlapping.
+    pub fn minimum_size(&self) -> ScreenSize {
+        size2(
+            self.source_size.width - self.center.size.width,
+            self.source_size.height - self.center.size.height,
+        )
+    }
+
+    /// The area inside the frame's edges when it's drawn into `rect`.
+    pub fn content_rect(&self, rect: ScreenRect) -> ScreenRect {
+        let minimum = self.minimum_size();
+        ScreenRect::new(
+            rect.origin + vec2(self.center.min_x(), self.center.min_y()),
+            size2(
+                (rect.size.width - minimum.width).max(0),
+                (rect.size.height - minimum.height).max(0),
+            ),
+        )
+    }
+
+    /// Draws the frame to fill `rect` in the current drawing context, using a cached image if
+    /// this size has been drawn recently.
+    pub fn draw(&self, rect: ScreenRect) -> Result<(), Error> {
+        if self.cache_capacity == 0 {
+            return self.draw_slices(rect);
+        }
+        self.image(rect.size)?
+            .draw(rect.origin, LCDBitmapFlip::kBitmapUnflipped)
+    }
+
+    /// Returns the frame composed at `size`, from the cache if possible.
+    pub fn image(&self, size: ScreenSize) -> Result<Bitmap, Error> {
+        let mut cache = self.cache.borrow_mut();
+        if let Some(index) = cache.iter().position(|(cached, _)| *cached == size) {
+            // Keep the most recently used size at the front.
+            let entry = cache.remove(index);
+            let bitmap = entry.1.clone();
+            cache.insert(0, entry);
+            return Ok(bitmap);
+        }
+        let graphics = Graphics::get();
+        let bitmap = graphics.new_bitmap(size, LCDColor::Solid(LCDSolidColor::kColorClear))?;
+        graphics.with_context(Some(&bitmap), || {
+            self.draw_slices(ScreenRect::new(point2(0, 0), size))
+        })?;
+        if self.cache_capacity > 0 {
+            cache.insert(0, (size, bitmap.clone()));
+            cache.truncate(self.cache_capacity);
+        }
+        Ok(bitmap)
+    }
+
+    fn draw_slices(&self, rect: ScreenRect) -> Result<(), Error> {
+        let minimum = self.minimum_size();
+        let middle: ScreenSize = size2(
+            (rect.size.width - minimum.width).max(0),
+            (rect.size.height - minimum.height).max(0),
+        );
+        let widths = [
+            self.center.min_x(),
+            middle.width,
+            self.source_size.width - self.center.max_x(),
+        ];
+        let heights = [
+            self.center.min_y(),
+            middle.height,
+            self.source_size.height - self.center.max_y(),
+        ];
+        let mut y = rect.min_y();
+        for (row, &height) in heights.iter().enumerate() {
+            let mut x = rect.min_x();
+            for (column, &width) in widths.iter().enumerate() {
+                let slice = &self.slices[row * 3 + column];
+                let source = self.slice_size(row, column);
+                let dest = size2(width, height);
+                if !source.is_empty() && !dest.is_empty() {
+                    let fill = match (row, column) {
+                        (1, 1) => Some(self.center_fill),
+                        (1, _) | (_, 1) => Some(self.edge_fill),
+                        _ => None,
+                    };
+                    draw_slice(slice, source, point2(x, y), dest, fill)?;
+                }
+                x += width;
+            }
+            y += height;
+        }
+        Ok(())
+    }
+
+    fn slice_size(&self, row: usize, column: usize) -> ScreenSize {
+        let xs = [
+            0,
+            self.center.min_x(),
+            self.center.max_x(),
+            self.source_size.width,
+        ];
+        let ys = [
+            0,
+            self.center.min_y(),
+            self.center.max_y(),
+            self.source_size.height,
+        ];
+        size2(xs[column + 1] - xs[column], ys[row + 1] - ys[row])
+    }
+}
+
+fn draw_slice(
+    slice: &Bitmap,
+    source: ScreenSize,
+    location: ScreenPoint,
+    dest: ScreenSize,
+    fill: Option<SliceFill>,
+) -> Result<(), Error> {
+    match fill {
+        _ if source == dest => slice.draw(location, LCDBitmapFlip::kBitmapUnflipped),
+        Some(SliceFill::Tile) => slice.tile(location, dest, LCDBitmapFlip::kBitmapUnflipped),
+        _ => slice.draw_scaled(
+            location,
+            vec2(
+                dest.width as f32 / source.width as f32,
+                dest.height as f32 / source.height as f32,
+            ),
+        ),
+    }
+}
+
+/// A sprite showing a `NineSlice` frame, redrawn whenever its bounds change.
+///
+/// After creation with `new`, use `set_bounds` to move or resize it, and `get_sprite` or
+/// `get_sprite_mut` for other operations like `set_z_index`.
+#[derive(Clone, Debug)]
+pub struct NineSliceSprite {
+    sprite: Sprite,
+    nine_slice: NineSlice,
+}
+
+impl NineSliceSprite {
+    /// Creates a `NineSliceSprite` filling `bounds`, and adds the underlying sprite to the
+    /// `SpriteManager`.
+    pub fn new(nine_slice: NineSlice, bounds: PDRect) -> Result<Self, Error> {
+        let sprite_manager = SpriteManager::get_mut();
+        let sprite = sprite_manager.new_sprite()?;
+        let mut nine_slice_sprite = Self { sprite, nine_slice };
+        nine_slice_sprite.set_bounds(bounds)?;
+        sprite_manager.add_sprite(&nine_slice_sprite.sprite)?;
+        Ok(nine_slice_sprite)
+    }
+
+    pub fn get_sprite(&self) -> &Sprite {
+        &self.sprite
+    }
+
+    pub fn get_sprite_mut(&mut self) -> &mut Sprite {
+        &mut self.sprite
+    }
+
+    pub fn get_nine_slice(&self) -> &NineSlice {
+        &self.nine_slice
+    }
+
+    /// Moves and resizes the sprite, redrawing the frame at the new size if it changed.
+    pub fn set_bounds(&mut self, bounds: PDRect) -> Result<(), Error> {
+        let size = size2(bounds.width as i32, bounds.height as i32);
+        let current = self.sprite.get_bounds()?;
+        if self.sprite.get_image()?.is_none()
+            || size != size2(current.width as i32, current.height as i32)
+        {
+            let image = self.nine_slice.image(size)?;
+            self.sprite
+                .set_image(image, LCDBitmapFlip::kBitmapUnflipped)?;
+        }
+        self.sprite.set_bounds(&bounds)
+    }
+}
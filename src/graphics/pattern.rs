@@ -0,0 +1,368 @@
+//! 8x8 fill patterns for `LCDColor::Pattern`.
+//!
+//! A pattern is 8 bytes of pixel rows (set bits are white) followed by 8 bytes of mask rows
+//! (set bits are opaque).  The Playdate API takes patterns by pointer, so `Pattern` keeps its
+//! bytes on the heap, where the address stays valid for as long as any clone is alive.
+
+use {
+    super::{Bitmap, Graphics, LCDColor, LCDSolidColor},
+    crate::pd_func_caller,
+    alloc::rc::Rc,
+    anyhow::{ensure, Error},
+    crankstart_sys::LCDPattern,
+};
+
+/// The 8x8 Bayer ordered dither matrix, with thresholds 0 to 63.
+pub const BAYER_8X8: [[u8; 8]; 8] = [
+    [0, 32, 8, 40, 2, 34, 10, 42],
+    [48, 16, 56, 24, 50, 18, 58, 26],
+    [12, 44, 4, 36, 14, 46, 6, 38],
+    [60, 28, 52, 20, 62, 30, 54, 22],
+    [3, 35, 11, 43, 1, 33, 9, 41],
+    [51, 19, 59, 27, 49, 17, 57, 25],
+    [15, 47, 7, 39, 13, 45, 5, 37],
+    [63, 31, 55, 23, 61, 29, 53, 21],
+];
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Pattern(Rc<LCDPattern>);
+
+impl Pattern {
+    /// Creates a pattern from raw pixel and mask bytes.
+    pub fn from_raw(raw: LCDPattern) -> Self {
+        Self(Rc::new(raw))
+    }
+
+    /// Creates an opaque pattern from 8 rows of pixels.
+    pub fn new(rows: [u8; 8]) -> Self {
+        Self::with_mask(rows, [0xff; 8])
+    }
+
+    /// Creates a pattern from 8 rows of pixels and 8 rows of mask.
+    pub fn with_mask(rows: [u8; 8], mask: [u8; 8]) -> Self {
+        let mut raw = [0u8; 16];
+        raw[..8].copy_from_slice(&rows);
+        raw[8..].copy_from_slice(&mask);
+        Self::from_raw(raw)
+    }
+
+    pub fn raw(&self) -> &LCDPattern {
+        &self.0
+    }
+
+    pub fn rows(&self) -> [u8; 8] {
+        let mut rows = [0u8; 8];
+        rows.copy_from_slice(&self.0[..8]);
+        rows
+    }
+
+    pub fn mask(&self) -> [u8; 8] {
+        let mut mask = [0u8; 8];
+        mask.copy_from_slice(&self.0[8..]);
+        mask
+    }
+
+    /// Returns the pattern's address, as the Playdate API expects for an `LCDColor`.
+    pub(crate) fn as_raw_color(&self) -> usize {
+        self.0.as_ptr() as usize
+    }
+
+    /// An opaque gray made by ordered (Bayer) dithering, from 0 (black) to 100 (white) percent.
+    pub fn gray(white_percent: u8) -> Self {
+        Self::new(bayer_rows(white_percent))
+    }
+
+    /// A solid color that's partly transparent, with the mask dithered from 0 (clear) to 100
+    /// (opaque) percent.  Useful for fades and shadows.
+    pub fn dithered(color: LCDSolidColor, opacity_percent: u8) -> Self {
+        let rows = if color == LCDSolidColor::kColorWhite {
+            [0xff; 8]
+        } else {
+            [0; 8]
+        };
+        Self::with_mask(rows, bayer_rows(opacity_percent))
+    }
+
+    /// Alternating black and white squares `size` pixels across (1, 2 or 4).
+    pub fn checkerboard(size: u8) -> Result<Self, Error> {
+        ensure!(
+            matches!(size, 1 | 2 | 4),
+            "Checkerboard size must be 1, 2 or 4, not {}",
+            size
+        );
+        let size = size as usize;
+        Ok(Self::from_fn(|x, y| {
+            (x / size + y / size).is_multiple_of(2)
+        }))
+    }
+
+    /// Horizontal white lines `on` pixels tall, separated by `off` black rows.  `on + off`
+    /// should divide 8 for the pattern to tile seamlessly.
+    pub fn horizontal_stripes(on: u8, off: u8) -> Self {
+        let period = (on as usize + off as usize).max(1);
+        Self::from_fn(|_, y| y % period < on as usize)
+    }
+
+    /// Vertical white lines `on` pixels wide, separated by `off` black columns.
+    pub fn vertical_stripes(on: u8, off: u8) -> Self {
+        let period = (on as usize + off as usize).max(1);
+        Self::from_fn(|x, _| x % period < on as usize)
+    }
+
+    /// Diagonal black lines running from bottom left to top right, one every `spacing` pixels
+    /// (1, 2, 4 or 8), on white.
+    pub fn diagonal_stripes(spacing: u8) -> Result<Self, Error> {
+        ensure!(
+            matches!(spacing, 1 | 2 | 4 | 8),
+            "Diagonal stripe spacing must be 1, 2, 4 or 8, not {}",
+            spacing
+        );
+        let spacing = spacing as usize;
+        Ok(Self::from_fn(|x, y| (x + y) % spacing != 0))
+    }
+
+    /// A grid of black lines every `spacing` pixels (2, 4 or 8), on white.
+    pub fn crosshatch(spacing: u8) -> Result<Self, Error> {
+        ensure!(
+            matches!(spacing, 2 | 4 | 8),
+            "Crosshatch spacing must be 2, 4 or 8, not {}",
+            spacing
+        );
+        let spacing = spacing as usize;
+        Ok(Self::from_fn(|x, y| x % spacing != 0 && y % spacing != 0))
+    }
+
+    /// Creates an opaque pattern where `white(x, y)` picks each pixel.
+    pub fn from_fn(white: impl Fn(usize, usize) -> bool) -> Self {
+        let mut rows = [0u8; 8];
+        for (y, row) in rows.iter_mut().enumerate() {
+            for x in 0..8 {
+                if white(x, y) {
+                    *row |= 0x80 >> x;
+                }
+            }
+        }
+        Self::new(rows)
+    }
+
+    /// Copies the 8x8 area of `bitmap` with its top left at `(x, y)`, including its mask.
+    pub fn from_bitmap(bitmap: &Bitmap, x: i32, y: i32) -> Result<Self, Error> {
+        Self::from_raw_bitmap(bitmap.inner.borrow().raw_bitmap, x, y)
+    }
+
+    pub(crate) fn from_raw_bitmap(
+        raw_bitmap: *mut crankstart_sys::LCDBitmap,
+        x: i32,
+        y: i32,
+    ) -> Result<Self, Error> {
+        let mut color: crankstart_sys::LCDColor = 0;
+        pd_func_caller!(
+            (*Graphics::get_ptr()).setColorToPattern,
+            &mut color,
+            raw_bitmap,
+            x,
+            y
+        )?;
+        ensure!(color != 0, "setColorToPattern returned no pattern");
+        // The result points at a buffer owned by the Playdate; copy it out.
+        let raw = unsafe { *(color as *const LCDPattern) };
+        Ok(Self::from_raw(raw))
+    }
+
+    /// Returns this pattern shifted right by `dx` and down by `dy`, wrapping around.  Shifting
+    /// by a frame counter animates the pattern, e.g. for water or marching ants.
+    pub fn offset(&self, dx: i32, dy: i32) -> Self {
+        let dx = dx.rem_euclid(8) as u32;
+        let dy = dy.rem_euclid(8) as usize;
+        let mut raw = [0u8; 16];
+        for y in 0..8 {
+            let source = (y + 8 - dy) % 8;
+            raw[y] = self.0[source].rotate_right(dx);
+            raw[y + 8] = self.0[source + 8].rotate_right(dx);
+        }
+        Self::from_raw(raw)
+    }
+
+    /// Returns the pattern with black and white swapped, keeping the mask.
+    pub fn inverted(&self) -> Self {
+        let mut raw = *self.0;
+        for byte in &mut raw[..8] {
+            *byte = !*byte;
+        }
+        Self::from_raw(raw)
+    }
+}
+
+fn bayer_rows(percent: u8) -> [u8; 8] {
+    let threshold = (percent.min(100) as u32 * 64 + 50) / 100;
+    let mut rows = [0u8; 8];
+    for (row, bayer) in rows.iter_mut().zip(BAYER_8X8.iter()) {
+        for (x, &level) in bayer.iter().enumerate() {
+            if (level as u32) < threshold {
+                *row |= 0x80 >> x;
+            }
+        }
+    }
+    rows
+}
+
+impl From<Pattern> for LCDColor {
+    fn from(pattern: Pattern) -> Self {
+        LCDColor::Pattern(pattern)
+    }
+}
+
+/// A pattern that scrolls by a fixed amount each time it's advanced, e.g. once per frame.
+#[derive(Clone, Debug)]
+pub struct AnimatedPattern {
+    base: Pattern,
+    current: Pattern,
+    velocity: (i32, i32),
+    offset: (i32, i32),
+}
+
+impl AnimatedPattern {
+    pub fn new(base: Pattern, dx: i32, dy: i32) -> Self {
+        Self {
+            current: base.clone(),
+            base,
+            velocity: (dx, dy),
+            offset: (0, 0),
+        }
+    }
+
+    /// Moves the pattern by its velocity.
+    pub fn advance(&mut self) {
+        self.offset = (
+            (self.offset.0 + self.velocity.0).rem_euclid(8),
+            (self.offset.1 + self.velocity.1).rem_euclid(8),
+        );
+        self.current = self.base.offset(self.offset.0, self.offset.1);
+    }
+
+    pub fn current(&self) -> &Pattern {
+        &self.current
+    }
+
+    pub fn color(&self) -> LCDColor {
+        LCDColor::Pattern(self.current.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HALF: [u8; 8] = [0xaa, 0x55, 0xaa, 0x55, 0xaa, 0x55, 0xaa, 0x55];
+
+    #[test]
+    fn gray_levels_follow_the_bayer_matrix() {
+        assert_eq!(bayer_rows(0), [0; 8]);
+        assert_eq!(bayer_rows(100), [0xff; 8]);
+        assert_eq!(bayer_rows(200), [0xff; 8]);
+        assert_eq!(bayer_rows(50), HALF);
+        assert_eq!(bayer_rows(25), [0xaa, 0, 0xaa, 0, 0xaa, 0, 0xaa, 0]);
+
+        let gray = Pattern::gray(50);
+        assert_eq!(gray.rows(), HALF);
+        assert_eq!(gray.mask(), [0xff; 8]);
+    }
+
+    #[test]
+    fn dithered_masks_a_solid_color() {
+        let white = Pattern::dithered(LCDSolidColor::kColorWhite, 50);
+        assert_eq!(white.rows(), [0xff; 8]);
+        assert_eq!(white.mask(), HALF);
+
+        let black = Pattern::dithered(LCDSolidColor::kColorBlack, 100);
+        assert_eq!(black.rows(), [0; 8]);
+        assert_eq!(black.mask(), [0xff; 8]);
+    }
+
+    #[test]
+    fn checkerboards() {
+        assert_eq!(Pattern::checkerboard(1).unwrap().rows(), HALF);
+        assert_eq!(
+            Pattern::checkerboard(2).unwrap().rows(),
+            [0xcc, 0xcc, 0x33, 0x33, 0xcc, 0xcc, 0x33, 0x33]
+        );
+        assert_eq!(
+            Pattern::checkerboard(4).unwrap().rows(),
+            [0xf0, 0xf0, 0xf0, 0xf0, 0x0f, 0x0f, 0x0f, 0x0f]
+        );
+        assert!(Pattern::checkerboard(3).is_err());
+    }
+
+    #[test]
+    fn stripes_and_grids() {
+        assert_eq!(
+            Pattern::horizontal_stripes(2, 2).rows(),
+            [0xff, 0xff, 0, 0, 0xff, 0xff, 0, 0]
+        );
+        assert_eq!(Pattern::vertical_stripes(1, 1).rows(), [0xaa; 8]);
+        assert_eq!(Pattern::vertical_stripes(2, 2).rows(), [0xcc; 8]);
+        assert_eq!(
+            Pattern::diagonal_stripes(8).unwrap().rows(),
+            [0x7f, 0xfe, 0xfd, 0xfb, 0xf7, 0xef, 0xdf, 0xbf]
+        );
+        assert!(Pattern::diagonal_stripes(3).is_err());
+        assert_eq!(
+            Pattern::crosshatch(4).unwrap().rows(),
+            [0, 0x77, 0x77, 0x77, 0, 0x77, 0x77, 0x77]
+        );
+        assert!(Pattern::crosshatch(1).is_err());
+    }
+
+    #[test]
+    fn from_fn_sets_bits_from_the_left() {
+        let diagonal = Pattern::from_fn(|x, y| x == y);
+        assert_eq!(
+            diagonal.rows(),
+            [0x80, 0x40, 0x20, 0x10, 0x08, 0x04, 0x02, 0x01]
+        );
+        assert_eq!(diagonal.mask(), [0xff; 8]);
+    }
+
+    #[test]
+    fn offset_wraps_rows_and_mask() {
+        let mut rows = [0u8; 8];
+        rows[0] = 0x80;
+        let mut mask = [0xff; 8];
+        mask[0] = 0x0f;
+        let dot = Pattern::with_mask(rows, mask);
+
+        let moved = dot.offset(1, 2);
+        assert_eq!(moved.rows(), [0, 0, 0x40, 0, 0, 0, 0, 0]);
+        assert_eq!(
+            moved.mask(),
+            [0xff, 0xff, 0x87, 0xff, 0xff, 0xff, 0xff, 0xff]
+        );
+
+        let back = dot.offset(-1, -1);
+        assert_eq!(back.rows(), [0, 0, 0, 0, 0, 0, 0, 0x01]);
+        assert_eq!(back.mask()[7], 0x1e);
+
+        assert_eq!(dot.offset(9, 8), dot.offset(1, 0));
+        assert_eq!(dot.offset(0, 0), dot);
+    }
+
+    #[test]
+    fn inverted_keeps_the_mask() {
+        let pattern = Pattern::with_mask([0x0f; 8], [0xf0; 8]);
+        let inverted = pattern.inverted();
+        assert_eq!(inverted.rows(), [0xf0; 8]);
+        assert_eq!(inverted.mask(), [0xf0; 8]);
+        assert_eq!(inverted.inverted(), pattern);
+    }
+
+    #[test]
+    fn animated_pattern_advances_by_its_velocity() {
+        let mut animated = AnimatedPattern::new(Pattern::from_fn(|x, y| x == y), 3, -1);
+        animated.advance();
+        assert_eq!(animated.current(), &animated.base.offset(3, -1));
+        for _ in 0..7 {
+            animated.advance();
+        }
+        assert_eq!(animated.current(), &animated.base);
+    }
+}
@@ -0,0 +1,338 @@
+//! Bounds-checked access to a bitmap's pixel and mask data.
+//!
+//! Playdate bitmaps store one bit per pixel, most significant bit first, with each row padded
+//! to `rowbytes` bytes.  A set bit is white in the pixel data and opaque in the mask.
+
+use {
+    super::{Bitmap, BitmapInner, LCDSolidColor},
+    anyhow::{ensure, Error},
+    core::{
+        cell::{Ref, RefMut},
+        slice,
+    },
+};
+
+fn bit_index(width: usize, height: usize, rowbytes: usize, x: i32, y: i32) -> Option<(usize, u8)> {
+    if x < 0 || y < 0 || x as usize >= width || y as usize >= height {
+        return None;
+    }
+    let (x, y) = (x as usize, y as usize);
+    Some((y * rowbytes + x / 8, 0x80 >> (x % 8)))
+}
+
+fn color_from_bits(pixel: bool, opaque: bool) -> LCDSolidColor {
+    match (opaque, pixel) {
+        (false, _) => LCDSolidColor::kColorClear,
+        (true, true) => LCDSolidColor::kColorWhite,
+        (true, false) => LCDSolidColor::kColorBlack,
+    }
+}
+
+/// A read-only view of one bit plane (pixels or mask) of a bitmap.
+#[derive(Clone, Copy, Debug)]
+pub struct BitPlane<'a> {
+    width: usize,
+    height: usize,
+    rowbytes: usize,
+    data: &'a [u8],
+}
+
+impl<'a> BitPlane<'a> {
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn rowbytes(&self) -> usize {
+        self.rowbytes
+    }
+
+    /// Returns the bit at `(x, y)`, or None if it's outside the bitmap.
+    pub fn get(&self, x: i32, y: i32) -> Option<bool> {
+        let (index, bit) = bit_index(self.width, self.height, self.rowbytes, x, y)?;
+        Some(self.data[index] & bit != 0)
+    }
+
+    /// Returns row `y`, including any padding bytes at the end.
+    pub fn row(&self, y: usize) -> Option<&'a [u8]> {
+        let start = y.checked_mul(self.rowbytes)?;
+        if y >= self.height {
+            return None;
+        }
+        Some(&self.data[start..start + self.rowbytes])
+    }
+
+    pub fn rows(&self) -> slice::Chunks<'a, u8> {
+        self.data.chunks(self.rowbytes.max(1))
+    }
+
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.data
+    }
+}
+
+/// A mutable view of one bit plane (pixels or mask) of a bitmap.
+#[derive(Debug)]
+pub struct BitPlaneMut<'a> {
+    width: usize,
+    height: usize,
+    rowbytes: usize,
+    data: &'a mut [u8],
+}
+
+impl<'a> BitPlaneMut<'a> {
+    pub fn as_plane(&self) -> BitPlane<'_> {
+        BitPlane {
+            width: self.width,
+            height: self.height,
+            rowbytes: self.rowbytes,
+            data: self.data,
+        }
+    }
+
+    pub fn get(&self, x: i32, y: i32) -> Option<bool> {
+        self.as_plane().get(x, y)
+    }
+
+    /// Sets the bit at `(x, y)`.  Coordinates outside the bitmap are ignored.
+    pub fn set(&mut self, x: i32, y: i32, value: bool) {
+        if let Some((index, bit)) = bit_index(self.width, self.height, self.rowbytes, x, y) {
+            if value {
+                self.data[index] |= bit;
+            } else {
+                self.data[index] &= !bit;
+            }
+        }
+    }
+
+    /// Sets every bit, including row padding.
+    pub fn fill(&mut self, value: bool) {
+        self.data.fill(if value { 0xff } else { 0 });
+    }
+
+    pub fn row_mut(&mut self, y: usize) -> Option<&mut [u8]> {
+        let start = y.checked_mul(self.rowbytes)?;
+        if y >= self.height {
+            return None;
+        }
+        Some(&mut self.data[start..start + self.rowbytes])
+    }
+
+    pub fn rows_mut(&mut self) -> slice::ChunksMut<'_, u8> {
+        self.data.chunks_mut(self.rowbytes.max(1))
+    }
+
+    pub fn as_bytes_mut(&mut self) -> &mut [u8] {
+        self.data
+    }
+}
+
+/// A read-only view of a bitmap's pixels; see `Bitmap::pixels`.  The bitmap can't be drawn
+/// into or modified while this exists.
+pub struct BitmapPixels<'a> {
+    _inner: Ref<'a, BitmapInner>,
+    pixels: BitPlane<'a>,
+    mask: Option<BitPlane<'a>>,
+}
+
+impl<'a> BitmapPixels<'a> {
+    pub fn width(&self) -> usize {
+        self.pixels.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.pixels.height
+    }
+
+    pub fn rowbytes(&self) -> usize {
+        self.pixels.rowbytes
+    }
+
+    /// Returns the color at `(x, y)`: black or white, or clear where the mask is unset.
+    /// Coordinates outside the bitmap are clear.
+    pub fn get(&self, x: i32, y: i32) -> LCDSolidColor {
+        match self.pixels.get(x, y) {
+            Some(pixel) => {
+                let opaque = self
+                    .mask
+                    .map(|mask| mask.get(x, y).unwrap_or(false))
+                    .unwrap_or(true);
+                color_from_bits(pixel, opaque)
+            }
+            None => LCDSolidColor::kColorClear,
+        }
+    }
+
+    pub fn row(&self, y: usize) -> Option<&[u8]> {
+        self.pixels.row(y)
+    }
+
+    pub fn rows(&self) -> slice::Chunks<'_, u8> {
+        self.pixels.rows()
+    }
+
+    pub fn data(&self) -> BitPlane<'_> {
+        self.pixels
+    }
+
+    /// Returns the mask, if the bitmap has one.
+    pub fn mask(&self) -> Option<BitPlane<'_>> {
+        self.mask
+    }
+}
+
+/// A mutable view of a bitmap's pixels; see `Bitmap::pixels_mut`.
+pub struct BitmapPixelsMut<'a> {
+    _inner: RefMut<'a, BitmapInner>,
+    pixels: BitPlaneMut<'a>,
+    mask: Option<BitPlaneMut<'a>>,
+}
+
+impl<'a> BitmapPixelsMut<'a> {
+    pub fn width(&self) -> usize {
+        self.pixels.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.pixels.height
+    }
+
+    pub fn rowbytes(&self) -> usize {
+        self.pixels.rowbytes
+    }
+
+    /// Returns the color at `(x, y)`; see `BitmapPixels::get`.
+    pub fn get(&self, x: i32, y: i32) -> LCDSolidColor {
+        match self.pixels.get(x, y) {
+            Some(pixel) => {
+                let opaque = self
+                    .mask
+                    .as_ref()
+                    .map(|mask| mask.get(x, y).unwrap_or(false))
+                    .unwrap_or(true);
+                color_from_bits(pixel, opaque)
+            }
+            None => LCDSolidColor::kColorClear,
+        }
+    }
+
+    /// Sets the pixel at `(x, y)`.  `kColorXOR` inverts it, and `kColorClear` needs a mask.
+    /// Coordinates outside the bitmap are ignored, as with `Graphics::set_pixel`.
+    pub fn set(&mut self, x: i32, y: i32, color: LCDSolidColor) -> Result<(), Error> {
+        match color {
+            LCDSolidColor::kColorBlack | LCDSolidColor::kColorWhite => {
+                self.pixels.set(x, y, color == LCDSolidColor::kColorWhite);
+                if let Some(mask) = self.mask.as_mut() {
+                    mask.set(x, y, true);
+                }
+            }
+            LCDSolidColor::kColorClear => {
+                let mask = self.mask.as_mut();
+                ensure!(mask.is_some(), "Bitmap has no mask to clear pixels in");
+                if let Some(mask) = mask {
+                    mask.set(x, y, false);
+                }
+            }
+            LCDSolidColor::kColorXOR => {
+                if let Some(pixel) = self.pixels.get(x, y) {
+                    self.pixels.set(x, y, !pixel);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn row_mut(&mut self, y: usize) -> Option<&mut [u8]> {
+        self.pixels.row_mut(y)
+    }
+
+    pub fn rows_mut(&mut self) -> slice::ChunksMut<'_, u8> {
+        self.pixels.rows_mut()
+    }
+
+    pub fn data(&self) -> BitPlane<'_> {
+        self.pixels.as_plane()
+    }
+
+    pub fn data_mut(&mut self) -> &mut BitPlaneMut<'a> {
+        &mut self.pixels
+    }
+
+    pub fn mask(&self) -> Option<BitPlane<'_>> {
+        self.mask.as_ref().map(|mask| mask.as_plane())
+    }
+
+    /// Returns the mask, if the bitmap has one.
+    pub fn mask_mut(&mut self) -> Option<&mut BitPlaneMut<'a>> {
+        self.mask.as_mut()
+    }
+}
+
+impl Bitmap {
+    /// Returns a read-only view of the pixel and mask data.
+    pub fn pixels(&self) -> Result<BitmapPixels<'_>, Error> {
+        let inner = self.inner.try_borrow().map_err(Error::msg)?;
+        let data = inner.get_data()?;
+        ensure!(!data.pixel_data.is_null(), "Bitmap has no pixel data");
+        let (width, height, rowbytes) = plane_size(data.width, data.height, data.rowbytes)?;
+        let len = rowbytes * height;
+        // The slices live as long as the borrow of `inner`, which keeps the bitmap alive and
+        // unmodified through this crate's API.
+        let pixels = BitPlane {
+            width,
+            height,
+            rowbytes,
+            data: unsafe { slice::from_raw_parts(data.pixel_data, len) },
+        };
+        let mask = (!data.mask_data.is_null()).then(|| BitPlane {
+            width,
+            height,
+            rowbytes,
+            data: unsafe { slice::from_raw_parts(data.mask_data, len) },
+        });
+        Ok(BitmapPixels {
+            _inner: inner,
+            pixels,
+            mask,
+        })
+    }
+
+    /// Returns a mutable view of the pixel and mask data.
+    pub fn pixels_mut(&mut self) -> Result<BitmapPixelsMut<'_>, Error> {
+        let inner = self.inner.try_borrow_mut().map_err(Error::msg)?;
+        let data = inner.get_data()?;
+        ensure!(!data.pixel_data.is_null(), "Bitmap has no pixel data");
+        let (width, height, rowbytes) = plane_size(data.width, data.height, data.rowbytes)?;
+        let len = rowbytes * height;
+        let pixels = BitPlaneMut {
+            width,
+            height,
+            rowbytes,
+            data: unsafe { slice::from_raw_parts_mut(data.pixel_data, len) },
+        };
+        let mask = (!data.mask_data.is_null()).then(|| BitPlaneMut {
+            width,
+            height,
+            rowbytes,
+            data: unsafe { slice::from_raw_parts_mut(data.mask_data, len) },
+        });
+        Ok(BitmapPixelsMut {
+            _inner: inner,
+            pixels,
+            mask,
+        })
+    }
+}
+
+fn plane_size(width: i32, height: i32, rowbytes: i32) -> Result<(usize, usize, usize), Error> {
+    ensure!(
+        width >= 0 && height >= 0 && rowbytes >= 0,
+        "Bitmap data has negative dimensions"
+    );
+    let (width, height, rowbytes) = (width as usize, height as usize, rowbytes as usize);
+    ensure!(rowbytes * 8 >= width, "Bitmap rowbytes too small for width");
+    Ok((width, height, rowbytes))
+}
@@ -0,0 +1,671 @@
+//! Measures, wraps and draws text that mixes regular, bold and italic fonts.
+//!
+//! With markup on, `*` toggles bold and `_` toggles italic, as in the Lua SDK's
+//! `drawText`; write `**` or `__` for a literal `*` or `_`.  Ranges in lines and runs are byte
+//! ranges into `TextLayout::text`, which has the markup removed.
+//!
+//! Word wrapping breaks lines at spaces and after hyphens.  Runs in different fonts share a
+//! baseline: shorter fonts sit at the bottom of the line.
+
+use {
+    super::{Bitmap, Font, Graphics, PDTextAlignment, PDTextWrappingMode},
+    crate::geometry::{ScreenPoint, ScreenRect, ScreenSize},
+    alloc::{string::String, vec::Vec},
+    anyhow::Error,
+    core::ops::Range,
+    euclid::{point2, size2},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TextStyle {
+    Regular,
+    Bold,
+    Italic,
+}
+
+/// The fonts used for each `TextStyle`.  Styles without a font use the regular one.
+#[derive(Clone, Debug)]
+pub struct FontFamily {
+    regular: Font,
+    bold: Option<Font>,
+    italic: Option<Font>,
+}
+
+impl FontFamily {
+    pub fn new(regular: Font) -> Self {
+        Self {
+            regular,
+            bold: None,
+            italic: None,
+        }
+    }
+
+    pub fn with_bold(mut self, bold: Font) -> Self {
+        self.bold = Some(bold);
+        self
+    }
+
+    pub fn with_italic(mut self, italic: Font) -> Self {
+        self.italic = Some(italic);
+        self
+    }
+
+    pub fn font(&self, style: TextStyle) -> &Font {
+        let font = match style {
+            TextStyle::Regular => None,
+            TextStyle::Bold => self.bold.as_ref(),
+            TextStyle::Italic => self.italic.as_ref(),
+        };
+        font.unwrap_or(&self.regular)
+    }
+}
+
+impl From<Font> for FontFamily {
+    fn from(regular: Font) -> Self {
+        Self::new(regular)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TextLayoutOptions {
+    /// The width to wrap at; None only breaks lines at newlines.
+    pub max_width: Option<i32>,
+    pub wrapping: PDTextWrappingMode,
+    /// Lines are aligned within `max_width`, or within the widest line if there's no limit.
+    pub alignment: PDTextAlignment,
+    /// Extra space between characters.
+    pub tracking: i32,
+    /// Extra space between lines.
+    pub leading: i32,
+    /// Whether to treat `*` and `_` as style markup.
+    pub markup: bool,
+}
+
+impl Default for TextLayoutOptions {
+    fn default() -> Self {
+        Self {
+            max_width: None,
+            wrapping: PDTextWrappingMode::kWrapWord,
+            alignment: PDTextAlignment::kAlignTextLeft,
+            tracking: 0,
+            leading: 0,
+            markup: true,
+        }
+    }
+}
+
+/// A stretch of one line drawn in one style.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TextRun {
+    pub style: TextStyle,
+    pub range: Range<usize>,
+    /// Relative to the layout's top left, at the height of the run's font.
+    pub rect: ScreenRect,
+}
+
+/// One line of a layout.  `rect` covers the line's text after alignment, at the full line
+/// height.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TextLine {
+    pub range: Range<usize>,
+    pub rect: ScreenRect,
+    pub runs: Vec<TextRun>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum PieceKind {
+    Word,
+    Space,
+    Newline,
+}
+
+#[derive(Clone, Debug)]
+struct Piece {
+    kind: PieceKind,
+    style: TextStyle,
+    range: Range<usize>,
+}
+
+/// Text laid out into lines; see the module docs for markup.
+#[derive(Clone, Debug)]
+pub struct TextLayout {
+    text: String,
+    family: FontFamily,
+    options: TextLayoutOptions,
+    lines: Vec<TextLine>,
+    size: ScreenSize,
+}
+
+impl TextLayout {
+    pub fn new(
+        text: &str,
+        family: impl Into<FontFamily>,
+        options: TextLayoutOptions,
+    ) -> Result<Self, Error> {
+        let family = family.into();
+        let (text, spans) = if options.markup {
+            parse_markup(text)
+        } else {
+            (
+                String::from(text),
+                alloc::vec![(TextStyle::Regular, 0..text.len())],
+            )
+        };
+        let mut layout = Self {
+            text,
+            family,
+            options,
+            lines: Vec::new(),
+            size: ScreenSize::zero(),
+        };
+        let pieces = split_pieces(&layout.text, &spans);
+        let lines = layout.break_lines(pieces)?;
+        layout.place_lines(lines)?;
+        Ok(layout)
+    }
+
+    /// The text without markup.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn lines(&self) -> &[TextLine] {
+        &self.lines
+    }
+
+    /// The size of the laid out text; the width is `max_width` if there is one.
+    pub fn size(&self) -> ScreenSize {
+        self.size
+    }
+
+    /// Returns the index of the line at `point`, relative to the layout's top left.
+    pub fn line_at(&self, point: ScreenPoint) -> Option<usize> {
+        if point.x < 0 || point.x >= self.size.width {
+            return None;
+        }
+        self.lines
+            .iter()
+            .position(|line| point.y >= line.rect.min_y() && point.y < line.rect.max_y())
+    }
+
+    /// Returns the byte offset in `text` of the caret position nearest `point`, relative to the
+    /// layout's top left, or None if it's outside every line.
+    pub fn index_at(&self, point: ScreenPoint) -> Result<Option<usize>, Error> {
+        let line = match self.line_at(point) {
+            Some(index) => &self.lines[index],
+            None => return Ok(None),
+        };
+        for run in &line.runs {
+            if point.x >= run.rect.max_x() {
+                continue;
+            }
+            if point.x < run.rect.min_x() {
+                return Ok(Some(run.range.start));
+            }
+            let font = self.family.font(run.style);
+            let run_text = &self.text[run.range.clone()];
+            let mut previous = 0;
+            for (offset, c) in run_text.char_indices() {
+                let end = offset + c.len_utf8();
+                let width = font.text_width(&run_text[..end], self.options.tracking)?;
+                if point.x < run.rect.min_x() + (previous + width) / 2 {
+                    return Ok(Some(run.range.start + offset));
+                }
+                previous = width;
+            }
+            return Ok(Some(run.range.end));
+        }
+        Ok(Some(line.range.end))
+    }
+
+    /// Draws the layout with its top left at `origin` in the current drawing context.  This
+    /// leaves that context's font and text tracking set to the layout's.
+    pub fn draw(&self, origin: ScreenPoint) -> Result<(), Error> {
+        self.draw_until(origin, self.text.len())
+    }
+
+    /// Draws only the text before byte `end`, e.g. to reveal it a character at a time.
+    pub fn draw_until(&self, origin: ScreenPoint, end: usize) -> Result<(), Error> {
+        self.draw_runs(origin, end)
+    }
+
+    /// Draws the layout into `bitmap` with its top left at `origin`.
+    pub fn draw_into(&self, bitmap: &Bitmap, origin: ScreenPoint) -> Result<(), Error> {
+        Graphics::get().with_context(Some(bitmap), || self.draw_runs(origin, self.text.len()))
+    }
+
+    // Sets fonts and tracking in the current drawing context as it goes.
+    fn draw_runs(&self, origin: ScreenPoint, end: usize) -> Result<(), Error> {
+        let graphics = Graphics::get();
+        graphics.set_text_tracking(self.options.tracking)?;
+        let mut current_style = None;
+        for run in self.lines.iter().flat_map(|line| line.runs.iter()) {
+            if run.range.start >= end {
+                break;
+            }
+            if current_style != Some(run.style) {
+                graphics.set_font(self.family.font(run.style))?;
+                current_style = Some(run.style);
+            }
+            let run_end = run.range.end.min(end);
+            let text = self
+                .text
+                .get(run.range.start..run_end)
+                .ok_or_else(|| anyhow::anyhow!("{} isn't on a character boundary", end))?;
+            graphics.draw_text(text, origin + run.rect.origin.to_vector())?;
+        }
+        Ok(())
+    }
+
+    fn measure(&self, style: TextStyle, range: Range<usize>) -> Result<i32, Error> {
+        if range.is_empty() {
+            return Ok(0);
+        }
+        self.family
+            .font(style)
+            .text_width(&self.text[range], self.options.tracking)
+    }
+
+    // Splits pieces into lines of pieces, breaking words if the wrapping mode allows.
+    fn break_lines(&self, pieces: Vec<Piece>) -> Result<Vec<(usize, Vec<Piece>)>, Error> {
+        let tracking = self.options.tracking;
+        let max_width = match self.options.wrapping {
+            PDTextWrappingMode::kWrapClip => None,
+            _ => self.options.max_width,
+        };
+        let mut lines = Vec::new();
+        let mut line: Vec<Piece> = Vec::new();
+        let mut line_start = 0;
+        let mut x = 0;
+        // Spaces at the start of a wrapped line are dropped, but not after a newline.
+        let mut wrapped = false;
+        let finish = |line: &mut Vec<Piece>, lines: &mut Vec<(usize, Vec<Piece>)>, start| {
+            while line.last().map(|p| p.kind == PieceKind::Space) == Some(true) {
+                line.pop();
+            }
+            lines.push((start, core::mem::take(line)));
+        };
+
+        for piece in pieces {
+            match piece.kind {
+                PieceKind::Newline => {
+                    finish(&mut line, &mut lines, line_start);
+                    line_start = piece.range.end;
+                    x = 0;
+                    wrapped = false;
+                }
+                PieceKind::Space => {
+                    if line.is_empty() && wrapped {
+                        continue;
+                    }
+                    let gap = if line.is_empty() { 0 } else { tracking };
+                    x += gap + self.measure(piece.style, piece.range.clone())?;
+                    line.push(piece);
+                }
+                PieceKind::Word => {
+                    let mut piece = piece;
+                    loop {
+                        let gap = if line.is_empty() { 0 } else { tracking };
+                        let width = self.measure(piece.style, piece.range.clone())?;
+                        let max_width = match max_width {
+                            Some(max_width) if x + gap + width > max_width => max_width,
+                            _ => {
+                                x += gap + width;
+                                line.push(piece);
+                                break;
+                            }
+                        };
+                        let has_word = line.iter().any(|p| p.kind == PieceKind::Word);
+                        let by_character = self.options.wrapping
+                            == PDTextWrappingMode::kWrapCharacter
+                            || width > max_width;
+                        if has_word && !by_character {
+                            // Move the whole word to the next line.
+                            finish(&mut line, &mut lines, line_start);
+                            line_start = piece.range.start;
+                            x = 0;
+                            wrapped = true;
+                            continue;
+                        }
+                        // Fill the rest of this line with as much of the word as fits.
+                        let fits = self.longest_prefix(&piece, max_width - x - gap)?;
+                        let split = if fits == piece.range.start && line.is_empty() {
+                            // Always make progress, even if one character is too wide.
+                            let first = self.text[piece.range.clone()].chars().next();
+                            piece.range.start + first.map(char::len_utf8).unwrap_or(0)
+                        } else {
+                            fits
+                        };
+                        if split > piece.range.start {
+                            line.push(Piece {
+                                kind: PieceKind::Word,
+                                style: piece.style,
+                                range: piece.range.start..split,
+                            });
+                        }
+                        finish(&mut line, &mut lines, line_start);
+                        line_start = split;
+                        x = 0;
+                        wrapped = true;
+                        piece.range.start = split;
+                        if piece.range.is_empty() {
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        finish(&mut line, &mut lines, line_start);
+        Ok(lines)
+    }
+
+    // Returns the end of the longest prefix of `piece` that fits in `width`.  Prefixes only get
+    // wider as they get longer, so this binary searches the character boundaries.
+    fn longest_prefix(&self, piece: &Piece, width: i32) -> Result<usize, Error> {
+        let ends: Vec<usize> = self.text[piece.range.clone()]
+            .char_indices()
+            .map(|(offset, c)| piece.range.start + offset + c.len_utf8())
+            .collect();
+        // The prefixes ending at ends[..low] fit and those ending at ends[high..] don't.
+        let (mut low, mut high) = (0, ends.len());
+        while low < high {
+            let middle = (low + high) / 2;
+            if self.measure(piece.style, piece.range.start..ends[middle])? > width {
+                high = middle;
+            } else {
+                low = middle + 1;
+            }
+        }
+        Ok(match low {
+            0 => piece.range.start,
+            _ => ends[low - 1],
+        })
+    }
+
+    // Merges each line's pieces into runs and positions everything.
+    fn place_lines(&mut self, lines: Vec<(usize, Vec<Piece>)>) -> Result<(), Error> {
+        let tracking = self.options.tracking;
+        let mut placed = Vec::with_capacity(lines.len());
+        let mut widest = 0;
+        let mut y = 0;
+        for (start, pieces) in &lines {
+            let mut runs: Vec<TextRun> = Vec::new();
+            for piece in pieces {
+                match runs.last_mut() {
+                    Some(run) if run.style == piece.style && run.range.end == piece.range.start => {
+                        run.range.end = piece.range.end;
+                    }
+                    _ => runs.push(TextRun {
+                        style: piece.style,
+                        range: piece.range.clone(),
+                        rect: ScreenRect::zero(),
+                    }),
+                }
+            }
+            let mut height = 0;
+            let mut x = 0;
+            for run in &mut runs {
+                if x > 0 {
+                    x += tracking;
+                }
+                let width = self.measure(run.style, run.range.clone())?;
+                let font_height = self.family.font(run.style).height()? as i32;
+                run.rect = ScreenRect::new(point2(x, y), size2(width, font_height));
+                x += width;
+                height = height.max(font_height);
+            }
+            if runs.is_empty() {
+                height = self.family.font(TextStyle::Regular).height()? as i32;
+            }
+            // Line up the bottoms of runs in shorter fonts with the tallest.
+            for run in &mut runs {
+                run.rect.origin.y += height - run.rect.size.height;
+            }
+            let range = match (pieces.first(), pieces.last()) {
+                (Some(first), Some(last)) => first.range.start..last.range.end,
+                _ => *start..*start,
+            };
+            widest = widest.max(x);
+            placed.push(TextLine {
+                range,
+                rect: ScreenRect::new(point2(0, y), size2(x, height)),
+                runs,
+            });
+            y += height + self.options.leading;
+        }
+        if !placed.is_empty() {
+            y -= self.options.leading;
+        }
+
+        let width = self.options.max_width.unwrap_or(widest);
+        for line in &mut placed {
+            let offset = match self.options.alignment {
+                PDTextAlignment::kAlignTextLeft => 0,
+                PDTextAlignment::kAlignTextCenter => (width - line.rect.size.width) / 2,
+                PDTextAlignment::kAlignTextRight => width - line.rect.size.width,
+            };
+            line.rect.origin.x += offset;
+            for run in &mut line.runs {
+                run.rect.origin.x += offset;
+            }
+        }
+        self.lines = placed;
+        self.size = size2(width, y);
+        Ok(())
+    }
+}
+
+// Strips markup, returning the plain text and its styled byte ranges.
+fn parse_markup(text: &str) -> (String, Vec<(TextStyle, Range<usize>)>) {
+    let mut plain = String::with_capacity(text.len());
+    let mut spans = Vec::new();
+    let (mut bold, mut italic) = (false, false);
+    let mut start = 0;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '*' && c != '_' {
+            plain.push(c);
+            continue;
+        }
+        if chars.peek() == Some(&c) {
+            chars.next();
+            plain.push(c);
+            continue;
+        }
+        let style = span_style(bold, italic);
+        if plain.len() > start {
+            spans.push((style, start..plain.len()));
+        }
+        start = plain.len();
+        if c == '*' {
+            bold = !bold;
+        } else {
+            italic = !italic;
+        }
+    }
+    if plain.len() > start {
+        spans.push((span_style(bold, italic), start..plain.len()));
+    }
+    (plain, spans)
+}
+
+// There are no bold italic fonts, so bold wins.
+fn span_style(bold: bool, italic: bool) -> TextStyle {
+    if bold {
+        TextStyle::Bold
+    } else if italic {
+        TextStyle::Italic
+    } else {
+        TextStyle::Regular
+    }
+}
+
+// Splits styled spans into words, runs of spaces and newlines.  Words are also split after
+// hyphens, so lines can break there.
+fn split_pieces(text: &str, spans: &[(TextStyle, Range<usize>)]) -> Vec<Piece> {
+    let mut pieces: Vec<Piece> = Vec::new();
+    for (style, range) in spans {
+        for (offset, c) in text[range.clone()].char_indices() {
+            let start = range.start + offset;
+            let end = start + c.len_utf8();
+            let kind = match c {
+                '\n' => PieceKind::Newline,
+                c if c.is_whitespace() => PieceKind::Space,
+                _ => PieceKind::Word,
+            };
+            match pieces.last_mut() {
+                Some(last)
+                    if kind != PieceKind::Newline
+                        && last.kind == kind
+                        && last.style == *style
+                        && last.range.end == start
+                        && !(kind == PieceKind::Word
+                            && c != '-'
+                            && breaks_after(&text[last.range.clone()])) =>
+                {
+                    last.range.end = end;
+                }
+                _ => pieces.push(Piece {
+                    kind,
+                    style: *style,
+                    range: start..end,
+                }),
+            }
+        }
+    }
+    pieces
+}
+
+// Whether a line can break after `word`: it ends in a hyphen that follows something else.
+fn breaks_after(word: &str) -> bool {
+    word.ends_with('-') && !word.trim_end_matches('-').is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, alloc::string::ToString};
+
+    fn styled(text: &str) -> (String, Vec<(TextStyle, String)>) {
+        let (plain, spans) = parse_markup(text);
+        let spans = spans
+            .into_iter()
+            .map(|(style, range)| (style, plain[range].to_string()))
+            .collect();
+        (plain, spans)
+    }
+
+    fn pieces(markup: &str) -> Vec<(PieceKind, TextStyle, String)> {
+        let (plain, spans) = parse_markup(markup);
+        split_pieces(&plain, &spans)
+            .into_iter()
+            .map(|piece| (piece.kind, piece.style, plain[piece.range].to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn doubled_markers_are_literal() {
+        let (plain, spans) = styled("2**3 is a__b");
+        assert_eq!(plain, "2*3 is a_b");
+        assert_eq!(spans, [(TextStyle::Regular, "2*3 is a_b".to_string())]);
+    }
+
+    #[test]
+    fn markers_toggle_styles() {
+        use TextStyle::*;
+        let (plain, spans) = styled("*bold _both_ bold* _it_ plain");
+        assert_eq!(plain, "bold both bold it plain");
+        assert_eq!(
+            spans,
+            [
+                (Bold, "bold ".to_string()),
+                // There's no bold italic, so bold wins.
+                (Bold, "both".to_string()),
+                (Bold, " bold".to_string()),
+                (Regular, " ".to_string()),
+                (Italic, "it".to_string()),
+                (Regular, " plain".to_string()),
+            ]
+        );
+
+        let (plain, spans) = styled("_a *b_ c*");
+        assert_eq!(plain, "a b c");
+        assert_eq!(
+            spans,
+            [
+                (Italic, "a ".to_string()),
+                (Bold, "b".to_string()),
+                (Bold, " c".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn unterminated_markers_run_to_the_end() {
+        let (plain, spans) = styled("a *b c");
+        assert_eq!(plain, "a b c");
+        assert_eq!(
+            spans,
+            [
+                (TextStyle::Regular, "a ".to_string()),
+                (TextStyle::Bold, "b c".to_string()),
+            ]
+        );
+
+        assert_eq!(styled("_"), (String::new(), Vec::new()));
+        assert_eq!(styled("*_*_"), (String::new(), Vec::new()));
+    }
+
+    #[test]
+    fn splits_at_spaces_and_newlines() {
+        use {PieceKind::*, TextStyle::*};
+        assert_eq!(
+            pieces("one  two\n\nthree\tfour"),
+            [
+                (Word, Regular, "one".to_string()),
+                (Space, Regular, "  ".to_string()),
+                (Word, Regular, "two".to_string()),
+                (Newline, Regular, "\n".to_string()),
+                (Newline, Regular, "\n".to_string()),
+                (Word, Regular, "three".to_string()),
+                (Space, Regular, "\t".to_string()),
+                (Word, Regular, "four".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn splits_after_hyphens() {
+        use {PieceKind::*, TextStyle::*};
+        assert_eq!(
+            pieces("well-known x--y"),
+            [
+                (Word, Regular, "well-".to_string()),
+                (Word, Regular, "known".to_string()),
+                (Space, Regular, " ".to_string()),
+                (Word, Regular, "x--".to_string()),
+                (Word, Regular, "y".to_string()),
+            ]
+        );
+        // A leading hyphen, as in a negative number, isn't a break.
+        assert_eq!(pieces("-5"), [(Word, Regular, "-5".to_string())]);
+        assert_eq!(pieces("a-"), [(Word, Regular, "a-".to_string())]);
+    }
+
+    #[test]
+    fn splits_where_the_style_changes() {
+        use {PieceKind::*, TextStyle::*};
+        assert_eq!(
+            pieces("*bo*ld _x_ y"),
+            [
+                (Word, Bold, "bo".to_string()),
+                (Word, Regular, "ld".to_string()),
+                (Space, Regular, " ".to_string()),
+                (Word, Italic, "x".to_string()),
+                (Space, Regular, " ".to_string()),
+                (Word, Regular, "y".to_string()),
+            ]
+        );
+        // Adjacent spans in the same style merge.
+        assert_eq!(pieces("*a_b_c*"), [(Word, Bold, "abc".to_string())]);
+    }
+}
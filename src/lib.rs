@@ -1,19 +1,25 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 #![allow(internal_features)]
 #![feature(lang_items, alloc_error_handler, core_intrinsics)]
 #![allow(unused_variables, dead_code, unused_imports)]
 
 extern crate alloc;
 
+pub mod animator;
 pub mod display;
+pub mod easing;
+pub mod executor;
 pub mod file;
 pub mod geometry;
 pub mod graphics;
 pub mod lua;
 pub mod network;
+pub mod scene;
 pub mod sound;
 pub mod sprite;
 pub mod system;
+pub mod timer;
+pub mod ui;
 
 use crankstart_sys::{ctypes, PDSystemEvent};
 use talc::{Span, Talc, Talck};
@@ -21,6 +27,7 @@ use talc::{Span, Talc, Talck};
 use {
     crate::{
         display::Display,
+        executor::Executor,
         file::FileSystem,
         graphics::{Graphics, PDRect},
         lua::Lua,
@@ -30,6 +37,7 @@ use {
             Sprite, SpriteCollideFunction, SpriteDrawFunction, SpriteManager, SpriteUpdateFunction,
         },
         system::System,
+        timer::Timers,
     },
     alloc::boxed::Box,
     anyhow::Error,
@@ -167,6 +175,12 @@ impl<T: 'static + Game> GameRunner<T> {
         }
 
         if let Some(game) = self.game.as_mut() {
+            if let Err(err) = Executor::get().run_frame() {
+                log_to_console!("Error from executor.run_frame: {err:#}")
+            }
+            if let Err(err) = Timers::get().run_frame() {
+                log_to_console!("Error from timers.run_frame: {err:#}")
+            }
             if let Err(err) = game.update(&mut self.playdate) {
                 log_to_console!("Error in update: {err:#}")
             }
@@ -187,6 +201,9 @@ impl<T: 'static + Game> GameRunner<T> {
     }
 
     pub fn handle_event(&mut self, event: PDSystemEvent) {
+        if let Err(err) = Timers::get().handle_event(event) {
+            log_to_console!("Error from timers.handle_event: {err:#}")
+        }
         if let Some(game) = self.game.as_mut() {
             if let Err(err) = game.handle_event(event) {
                 log_to_console!("Error in handle_event: {err:#}")
@@ -298,6 +315,7 @@ macro_rules! crankstart_game {
                 };
             }
 
+            #[cfg(not(test))]
             #[no_mangle]
             extern "C" fn eventHandler(
                 playdate: *mut PlaydateAPI,
@@ -357,6 +375,7 @@ pub static CLEANUP_FUNCTION: core::sync::atomic::AtomicUsize =
 
 pub type CleanupFunction = fn(&str);
 
+#[cfg(not(test))]
 #[panic_handler]
 fn panic(#[allow(unused)] panic_info: &::core::panic::PanicInfo) -> ! {
     use alloc::string::ToString;
@@ -445,11 +464,13 @@ impl talc::OomHandler for PlaydateAllocator {
     }
 }
 
+#[cfg(not(test))]
 #[global_allocator]
 pub(crate) static mut A: Talck<talc::locking::AssumeUnlockable, PlaydateAllocator> =
     Talck::new(Talc::new(PlaydateAllocator));
 
 // define what happens in an Out Of Memory (OOM) condition
+#[cfg(not(test))]
 #[alloc_error_handler]
 fn alloc_error(_layout: Layout) -> ! {
     System::log_to_console("Out of Memory\0");
@@ -457,6 +478,7 @@ fn alloc_error(_layout: Layout) -> ! {
 }
 
 #[cfg(target_os = "macos")]
+#[cfg(not(test))]
 #[no_mangle]
 pub unsafe extern "C" fn memcpy(dest: *mut u8, src: *const u8, n: usize) -> *mut u8 {
     let mut i = 0;
@@ -468,6 +490,7 @@ pub unsafe extern "C" fn memcpy(dest: *mut u8, src: *const u8, n: usize) -> *mut
 }
 
 #[cfg(target_os = "macos")]
+#[cfg(not(test))]
 #[no_mangle]
 pub unsafe extern "C" fn memmove(dest: *mut u8, src: *const u8, n: usize) -> *mut u8 {
     if src < dest as *const u8 {
@@ -489,6 +512,7 @@ pub unsafe extern "C" fn memmove(dest: *mut u8, src: *const u8, n: usize) -> *mu
 }
 
 #[cfg(target_os = "macos")]
+#[cfg(not(test))]
 #[no_mangle]
 pub unsafe extern "C" fn memcmp(s1: *const u8, s2: *const u8, n: usize) -> i32 {
     let mut i = 0;
@@ -504,6 +528,7 @@ pub unsafe extern "C" fn memcmp(s1: *const u8, s2: *const u8, n: usize) -> i32 {
 }
 
 #[cfg(target_os = "macos")]
+#[cfg(not(test))]
 #[no_mangle]
 pub unsafe extern "C" fn bcmp(s1: *const u8, s2: *const u8, n: usize) -> i32 {
     memcmp(s1, s2, n)
@@ -520,71 +545,88 @@ pub unsafe fn memset_internal(s: *mut u8, c: crankstart_sys::ctypes::c_int, n: u
 }
 
 #[cfg(target_os = "macos")]
+#[cfg(not(test))]
 #[no_mangle]
 pub unsafe extern "C" fn memset(s: *mut u8, c: crankstart_sys::ctypes::c_int, n: usize) -> *mut u8 {
     memset_internal(s, c, n)
 }
 
 #[cfg(target_os = "macos")]
+#[cfg(not(test))]
 #[no_mangle]
 pub unsafe extern "C" fn __bzero(s: *mut u8, n: usize) {
     memset_internal(s, 0, n);
 }
 
+#[cfg(not(test))]
 #[no_mangle]
 pub extern "C" fn _sbrk() {}
 
 #[cfg(not(target_os = "windows"))]
+#[cfg(not(test))]
 #[no_mangle]
 pub extern "C" fn _write() {}
 
 #[cfg(not(target_os = "windows"))]
+#[cfg(not(test))]
 #[no_mangle]
 pub extern "C" fn _close() {}
 
 #[cfg(not(target_os = "windows"))]
+#[cfg(not(test))]
 #[no_mangle]
 pub extern "C" fn _lseek() {}
 
 #[cfg(not(target_os = "windows"))]
+#[cfg(not(test))]
 #[no_mangle]
 pub extern "C" fn _read() {}
 
+#[cfg(not(test))]
 #[no_mangle]
 pub extern "C" fn _fstat() {}
 
+#[cfg(not(test))]
 #[no_mangle]
 pub extern "C" fn _isatty() {}
 
 #[cfg(not(target_os = "windows"))]
+#[cfg(not(test))]
 #[no_mangle]
 pub extern "C" fn _exit() {}
 
+#[cfg(not(test))]
 #[no_mangle]
 pub extern "C" fn _open() {}
 
+#[cfg(not(test))]
 #[no_mangle]
 pub extern "C" fn _kill() {}
 
+#[cfg(not(test))]
 #[no_mangle]
 pub extern "C" fn _getpid() {}
 
+#[cfg(not(test))]
 #[no_mangle]
 pub extern "C" fn rust_eh_personality() {
     unimplemented!();
 }
 
 #[cfg(target_os = "macos")]
+#[cfg(not(test))]
 #[no_mangle]
 extern "C" fn _Unwind_Resume() {
     unimplemented!();
 }
 
+#[cfg(not(test))]
 #[no_mangle]
 extern "C" fn __exidx_start() {
     unimplemented!();
 }
 
+#[cfg(not(test))]
 #[no_mangle]
 extern "C" fn __exidx_end() {
     unimplemented!();
@@ -14,6 +14,17 @@ use crankstart_sys::{
 };
 use cstr_core::{CStr, CString};
 
+pub mod client;
+pub use client::{HttpClient, RequestBuilder, Response, Url};
+pub mod download;
+pub use download::{Crc32, Download, Downloader};
+pub mod online;
+pub use online::{OnlineOptions, OnlineOutcome};
+pub mod tcp;
+pub use tcp::{Tcp, TcpConnection};
+pub mod websocket;
+pub use websocket::{WebSocket, WebSocketBuilder, WebSocketEvent, WebSocketState};
+
 #[derive(Clone, Debug)]
 pub struct Network {
     raw_network: *const playdate_network,
@@ -23,6 +34,14 @@ pub struct Network {
 
 static mut NETWORK: Network = Network::null();
 
+// The number of HTTP and TCP connections that haven't been released, so idle disconnect can tell
+// whether Wi-Fi is still in use.
+static mut LIVE_CONNECTIONS: usize = 0;
+
+pub(crate) fn live_connection_count() -> usize {
+    unsafe { LIVE_CONNECTIONS }
+}
+
 type EnableCallback = dyn FnMut(PDNetErr) + 'static;
 static mut NETWORK_ENABLE_CALLBACK: Option<Box<EnableCallback>> = None;
 
@@ -75,6 +94,10 @@ impl Network {
         }
     }
 
+    pub fn tcp(&self) -> Tcp {
+        Tcp::new(self.raw_tcp)
+    }
+
     fn http_api_ref() -> Option<&'static playdate_http> {
         unsafe { NETWORK.raw_http.as_ref() }
     }
@@ -140,40 +163,14 @@ impl Http {
     where
         F: FnMut(bool) + 'static,
     {
-        let server_c = optional_cstring(server)?;
-        let purpose_c = optional_cstring(purpose)?;
-        let server_ptr = server_c.as_ref().map(|s| s.as_ptr()).unwrap_or(ptr::null());
-        let purpose_ptr = purpose_c
-            .as_ref()
-            .map(|s| s.as_ptr())
-            .unwrap_or(ptr::null());
-        let mut callback_userdata = ptr::null_mut();
-        let mut callback_state: *mut AccessRequestState = ptr::null_mut();
-        let callback_fn = if let Some(cb) = callback {
-            let state = Box::new(AccessRequestState {
-                callback: Some(Box::new(cb)),
-            });
-            callback_state = Box::into_raw(state);
-            callback_userdata = callback_state as *mut ctypes::c_void;
-            Some(http_access_request_callback as unsafe extern "C" fn(bool, *mut ctypes::c_void))
-        } else {
-            None
-        };
-        let reply = pd_func_caller!(
+        request_access(
             self.api().requestAccess,
-            server_ptr,
+            server,
             port,
             use_ssl,
-            purpose_ptr,
-            callback_fn,
-            callback_userdata
-        )?;
-        if reply != accessReply::kAccessAsk && !callback_state.is_null() {
-            unsafe {
-                drop(Box::from_raw(callback_state));
-            }
-        }
-        Ok(reply)
+            purpose,
+            callback,
+        )
     }
 
     pub fn new_connection(&self, server: &str, port: i32, use_ssl: bool) -> Result<HttpConnection> {
@@ -192,13 +189,70 @@ impl Http {
     }
 }
 
+type RequestAccessFn = unsafe extern "C" fn(
+    *const ctypes::c_char,
+    ctypes::c_int,
+    bool,
+    *const ctypes::c_char,
+    crankstart_sys::AccessRequestCallback,
+    *mut ctypes::c_void,
+) -> accessReply;
+
+/// Shared by `Http::request_access` and `Tcp::request_access`, which have the same signature.
+pub(crate) fn request_access<F>(
+    raw_request_access: Option<RequestAccessFn>,
+    server: Option<&str>,
+    port: i32,
+    use_ssl: bool,
+    purpose: Option<&str>,
+    callback: Option<F>,
+) -> Result<accessReply>
+where
+    F: FnMut(bool) + 'static,
+{
+    let server_c = optional_cstring(server)?;
+    let purpose_c = optional_cstring(purpose)?;
+    let server_ptr = server_c.as_ref().map(|s| s.as_ptr()).unwrap_or(ptr::null());
+    let purpose_ptr = purpose_c
+        .as_ref()
+        .map(|s| s.as_ptr())
+        .unwrap_or(ptr::null());
+    let mut callback_userdata = ptr::null_mut();
+    let mut callback_state: *mut AccessRequestState = ptr::null_mut();
+    let callback_fn = if let Some(cb) = callback {
+        let state = Box::new(AccessRequestState {
+            callback: Some(Box::new(cb)),
+        });
+        callback_state = Box::into_raw(state);
+        callback_userdata = callback_state as *mut ctypes::c_void;
+        Some(access_request_callback as unsafe extern "C" fn(bool, *mut ctypes::c_void))
+    } else {
+        None
+    };
+    let reply = pd_func_caller!(
+        raw_request_access,
+        server_ptr,
+        port,
+        use_ssl,
+        purpose_ptr,
+        callback_fn,
+        callback_userdata
+    )?;
+    if reply != accessReply::kAccessAsk && !callback_state.is_null() {
+        unsafe {
+            drop(Box::from_raw(callback_state));
+        }
+    }
+    Ok(reply)
+}
+
 type AccessRequestClosure = dyn FnMut(bool) + 'static;
 
 struct AccessRequestState {
     callback: Option<Box<AccessRequestClosure>>,
 }
 
-extern "C" fn http_access_request_callback(allowed: bool, userdata: *mut ctypes::c_void) {
+extern "C" fn access_request_callback(allowed: bool, userdata: *mut ctypes::c_void) {
     if userdata.is_null() {
         return;
     }
@@ -233,6 +287,9 @@ struct HttpConnectionInner {
 
 impl Drop for HttpConnectionInner {
     fn drop(&mut self) {
+        unsafe {
+            LIVE_CONNECTIONS -= 1;
+        }
         fn do_drop(conn: &mut HttpConnectionInner) -> Result<()> {
             unsafe {
                 let userdata = pd_func_caller!((*conn.raw_http).getUserdata, conn.raw_connection)?;
@@ -378,6 +435,9 @@ impl HttpConnection {
             raw_connection,
             callbacks: RefCell::new(HttpCallbackSlots::default()),
         });
+        unsafe {
+            LIVE_CONNECTIONS += 1;
+        }
         let userdata_ptr = Weak::into_raw(Rc::downgrade(&inner)) as *mut ctypes::c_void;
         pd_func_caller!((*raw_http).setUserdata, raw_connection, userdata_ptr)?;
         Ok(Self { inner })
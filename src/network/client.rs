@@ -0,0 +1,983 @@
+//! A higher-level HTTP client on top of `HttpConnection`, which takes care of URL parsing,
+//! accumulating the response body, and following redirects.
+//!
+//! ```rust
+//! HttpClient::get("https://example.com/levels.json")?
+//!     .header("Accept", "application/json")
+//!     .timeout(10_000)
+//!     .send(|result| match result {
+//!         Ok(response) => log_to_console!("{} bytes", response.body.len()),
+//!         Err(err) => log_to_console!("request failed: {err:#}"),
+//!     })?;
+//! ```
+//!
+//! Or, from a task on the `executor`:
+//!
+//! ```rust
+//! let response = HttpClient::get("https://example.com/levels.json")?.fetch()?.await?;
+//! ```
+
+use {
+    super::{HttpConnection, Network},
+    alloc::{
+        boxed::Box,
+        format,
+        rc::Rc,
+        string::{String, ToString},
+        vec::Vec,
+    },
+    anyhow::{anyhow, bail, ensure, Error, Result},
+    core::{
+        cell::RefCell,
+        future::Future,
+        pin::Pin,
+        task::{Context, Poll, Waker},
+    },
+    crankstart_sys::PDNetErr,
+};
+
+/// The parts of an http or https URL that `HttpConnection` needs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Url {
+    pub use_ssl: bool,
+    /// The host name or address; IPv6 addresses are given without brackets.
+    pub server: String,
+    pub port: i32,
+    /// The path, including any query string; always starts with '/'.
+    pub path: String,
+}
+
+impl Url {
+    /// Parses an absolute http or https URL, e.g. `https://example.com:8443/a/b?c=d` or
+    /// `http://[::1]:8080/`.
+    pub fn parse(url: &str) -> Result<Self> {
+        let (use_ssl, rest) = if let Some(rest) = strip_prefix_ignore_case(url, "https://") {
+            (true, rest)
+        } else if let Some(rest) = strip_prefix_ignore_case(url, "http://") {
+            (false, rest)
+        } else {
+            bail!("URL must start with http:// or https://: {}", url);
+        };
+        // Fragments are never sent to the server.
+        let rest = rest.split('#').next().unwrap_or("");
+        let authority_end = rest.find(['/', '?']).unwrap_or(rest.len());
+        let (authority, path) = rest.split_at(authority_end);
+        ensure!(
+            !authority.contains('@'),
+            "URLs with credentials aren't supported"
+        );
+        // IPv6 addresses are bracketed, since they contain colons themselves.
+        let (server, port) = if let Some(bracketed) = authority.strip_prefix('[') {
+            let (server, after) = bracketed
+                .split_once(']')
+                .ok_or_else(|| anyhow!("Unclosed '[' in URL: {}", url))?;
+            let port = match after {
+                "" => None,
+                after => Some(
+                    after
+                        .strip_prefix(':')
+                        .ok_or_else(|| anyhow!("Invalid server in URL: {}", url))?,
+                ),
+            };
+            (server, port)
+        } else {
+            match authority.rsplit_once(':') {
+                Some((server, port)) => (server, Some(port)),
+                None => (authority, None),
+            }
+        };
+        let port = match port {
+            Some(port) => port
+                .parse::<i32>()
+                .map_err(|_| anyhow!("Invalid port in URL: {}", url))?,
+            None => {
+                if use_ssl {
+                    443
+                } else {
+                    80
+                }
+            }
+        };
+        ensure!(!server.is_empty(), "URL has no server: {}", url);
+        let path = if path.is_empty() {
+            "/".to_string()
+        } else if path.starts_with('?') {
+            format!("/{path}")
+        } else {
+            path.to_string()
+        };
+        Ok(Self {
+            use_ssl,
+            server: server.to_string(),
+            port,
+            path,
+        })
+    }
+
+    /// Resolves a `Location` header value, which may be relative, against this URL.
+    pub fn join(&self, location: &str) -> Result<Self> {
+        if location.contains("://") {
+            Url::parse(location)
+        } else if let Some(rest) = location.strip_prefix("//") {
+            let scheme = if self.use_ssl { "https" } else { "http" };
+            Url::parse(&format!("{scheme}://{rest}"))
+        } else if location.starts_with('/') {
+            Ok(Self {
+                path: location.to_string(),
+                ..self.clone()
+            })
+        } else {
+            let path = self.path.split('?').next().unwrap_or("/");
+            let dir = &path[..path.rfind('/').map(|i| i + 1).unwrap_or(0)];
+            Ok(Self {
+                path: format!("{dir}{location}"),
+                ..self.clone()
+            })
+        }
+    }
+}
+
+fn strip_prefix_ignore_case<'a>(s: &'a str, prefix: &str) -> Option<&'a str> {
+    if s.len() >= prefix.len() && s[..prefix.len()].eq_ignore_ascii_case(prefix) {
+        Some(&s[prefix.len()..])
+    } else {
+        None
+    }
+}
+
+/// A completed HTTP response.
+#[derive(Clone, Debug, Default)]
+pub struct Response {
+    pub status: i32,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    /// The URL the response came from, after any redirects.
+    pub url: Option<Url>,
+}
+
+impl Response {
+    /// Returns the first header with the given name, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    /// Returns the body as UTF-8 text.
+    pub fn text(&self) -> Result<&str> {
+        core::str::from_utf8(&self.body).map_err(Error::msg)
+    }
+}
+
+/// Entry points for building requests; see `RequestBuilder`.
+pub struct HttpClient;
+
+impl HttpClient {
+    pub fn get(url: &str) -> Result<RequestBuilder> {
+        RequestBuilder::new("GET", url)
+    }
+
+    pub fn post(url: &str, body: Vec<u8>) -> Result<RequestBuilder> {
+        Ok(RequestBuilder::new("POST", url)?.body(body))
+    }
+
+    /// Builds a request with any method, e.g. "PUT" or "DELETE".
+    pub fn request(method: &str, url: &str) -> Result<RequestBuilder> {
+        RequestBuilder::new(method, url)
+    }
+}
+
+type ResponseCallback = Box<dyn FnOnce(Result<Response>) + 'static>;
+
+/// An HTTP request waiting to be sent.
+#[derive(Clone, Debug)]
+pub struct RequestBuilder {
+    method: String,
+    url: Url,
+    headers: Vec<(String, String)>,
+    body: Option<Vec<u8>>,
+    connect_timeout_ms: Option<u32>,
+    read_timeout_ms: Option<u32>,
+    max_redirects: usize,
+}
+
+impl RequestBuilder {
+    fn new(method: &str, url: &str) -> Result<Self> {
+        Ok(Self {
+            method: method.to_string(),
+            url: Url::parse(url)?,
+            headers: Vec::new(),
+            body: None,
+            connect_timeout_ms: None,
+            read_timeout_ms: None,
+            max_redirects: 5,
+        })
+    }
+
+    pub fn header(mut self, key: &str, value: &str) -> Self {
+        self.headers.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn body(mut self, body: Vec<u8>) -> Self {
+        self.body = Some(body);
+        self
+    }
+
+    /// Sets both the connect and read timeouts, in milliseconds.
+    pub fn timeout(mut self, timeout_ms: u32) -> Self {
+        self.connect_timeout_ms = Some(timeout_ms);
+        self.read_timeout_ms = Some(timeout_ms);
+        self
+    }
+
+    pub fn connect_timeout(mut self, timeout_ms: u32) -> Self {
+        self.connect_timeout_ms = Some(timeout_ms);
+        self
+    }
+
+    pub fn read_timeout(mut self, timeout_ms: u32) -> Self {
+        self.read_timeout_ms = Some(timeout_ms);
+        self
+    }
+
+    /// How many redirects to follow before giving up; 0 returns redirect responses as is.
+    pub fn max_redirects(mut self, max_redirects: usize) -> Self {
+        self.max_redirects = max_redirects;
+        self
+    }
+
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+
+    /// Sends the request, calling `callback` once with the full response or an error.
+    pub fn send<F>(self, callback: F) -> Result<()>
+    where
+        F: FnOnce(Result<Response>) + 'static,
+    {
+        self.send_with(Rc::new(open_http_connection), Box::new(callback))
+    }
+
+    /// Sends the request, returning a future for the response, for use with the `executor`.
+    pub fn fetch(self) -> Result<ResponseFuture> {
+        let shared = Rc::new(RefCell::new(ResponseFutureState::default()));
+        let callback_shared = shared.clone();
+        self.send(move |result| {
+            let mut state = callback_shared.borrow_mut();
+            state.result = Some(result);
+            if let Some(waker) = state.waker.take() {
+                waker.wake();
+            }
+        })?;
+        Ok(ResponseFuture { shared })
+    }
+
+    fn send_with(self, open: Rc<OpenConnection>, callback: ResponseCallback) -> Result<()> {
+        let state = Rc::new(RefCell::new(RequestState {
+            request: self,
+            open,
+            connection: None,
+            response: Response::default(),
+            callback: Some(callback),
+            redirects: 0,
+        }));
+        start_request(&state)
+    }
+
+    // Points the request at `location` after a redirect.  303s, and 301s and 302s from a POST,
+    // become a GET without a body, as browsers do.
+    fn redirect(&mut self, status: i32, location: &str) -> Result<()> {
+        self.url = self.url.join(location)?;
+        if status == 303 || (matches!(status, 301 | 302) && self.method == "POST") {
+            self.method = "GET".into();
+            self.body = None;
+        }
+        Ok(())
+    }
+
+    fn header_block(&self) -> Vec<u8> {
+        let mut block = String::new();
+        for (key, value) in &self.headers {
+            block.push_str(key);
+            block.push_str(": ");
+            block.push_str(value);
+            block.push_str("\r\n");
+        }
+        block.into_bytes()
+    }
+}
+
+// What the request state machine needs from a connection, so it doesn't depend on the
+// Playdate's HTTP stack directly.
+pub(crate) trait Connection {
+    fn send(
+        &self,
+        method: &str,
+        path: &str,
+        headers: Option<&[u8]>,
+        body: Option<&[u8]>,
+    ) -> Result<()>;
+    fn bytes_available(&self) -> Result<usize>;
+    fn read(&self, buffer: &mut [u8]) -> Result<usize>;
+    fn error(&self) -> Result<PDNetErr>;
+    fn response_status(&self) -> Result<i32>;
+}
+
+impl Connection for HttpConnection {
+    fn send(
+        &self,
+        method: &str,
+        path: &str,
+        headers: Option<&[u8]>,
+        body: Option<&[u8]>,
+    ) -> Result<()> {
+        match method {
+            "GET" => self.get(path, headers),
+            "POST" => self.post(path, headers, body),
+            method => self.query(method, path, headers, body),
+        }
+    }
+
+    fn bytes_available(&self) -> Result<usize> {
+        HttpConnection::bytes_available(self)
+    }
+
+    fn read(&self, buffer: &mut [u8]) -> Result<usize> {
+        HttpConnection::read(self, buffer)
+    }
+
+    fn error(&self) -> Result<PDNetErr> {
+        HttpConnection::error(self)
+    }
+
+    fn response_status(&self) -> Result<i32> {
+        HttpConnection::response_status(self)
+    }
+}
+
+// Opens a connection to the request's current URL, with its events wired to the state.
+type OpenConnection = dyn Fn(&Rc<RefCell<RequestState>>) -> Result<Box<dyn Connection>>;
+
+struct RequestState {
+    request: RequestBuilder,
+    open: Rc<OpenConnection>,
+    // Holds the connection open until the request finishes.  The connection's callbacks hold
+    // this state in turn, which keeps the request alive; clearing this breaks the cycle.
+    connection: Option<Box<dyn Connection>>,
+    response: Response,
+    callback: Option<ResponseCallback>,
+    redirects: usize,
+}
+
+fn open_http_connection(state: &Rc<RefCell<RequestState>>) -> Result<Box<dyn Connection>> {
+    let borrowed = state.borrow();
+    let request = &borrowed.request;
+    let url = &request.url;
+    let connection = Network::get()
+        .http()
+        .new_connection(&url.server, url.port, url.use_ssl)?;
+    if let Some(timeout) = request.connect_timeout_ms {
+        connection.set_connect_timeout(timeout)?;
+    }
+    if let Some(timeout) = request.read_timeout_ms {
+        connection.set_read_timeout(timeout)?;
+    }
+
+    let header_state = state.clone();
+    connection.on_header_received(Some(
+        move |_: &HttpConnection, key: &cstr_core::CStr, value: &cstr_core::CStr| {
+            header_received(
+                &header_state,
+                &key.to_string_lossy(),
+                &value.to_string_lossy(),
+            );
+        },
+    ))?;
+    let response_state = state.clone();
+    connection.on_response(Some(move |connection: &HttpConnection| {
+        response_available(&response_state, connection);
+    }))?;
+    let complete_state = state.clone();
+    connection.on_request_complete(Some(move |connection: &HttpConnection| {
+        request_complete(&complete_state, connection);
+    }))?;
+    let closed_state = state.clone();
+    connection.on_connection_closed(Some(move |connection: &HttpConnection| {
+        let err = connection
+            .error()
+            .unwrap_or(PDNetErr::NET_CONNECTION_CLOSED);
+        connection_closed(&closed_state, err);
+    }))?;
+    Ok(Box::new(connection))
+}
+
+fn start_request(state: &Rc<RefCell<RequestState>>) -> Result<()> {
+    let open = state.borrow().open.clone();
+    let connection = open(state)?;
+    let mut borrowed = state.borrow_mut();
+    borrowed.response = Response::default();
+    let request = &borrowed.request;
+    let headers = request.header_block();
+    let headers = if headers.is_empty() {
+        None
+    } else {
+        Some(headers.as_slice())
+    };
+    connection.send(
+        &request.method,
+        &request.url.path,
+        headers,
+        request.body.as_deref(),
+    )?;
+    borrowed.connection = Some(connection);
+    Ok(())
+}
+
+fn header_received(state: &Rc<RefCell<RequestState>>, key: &str, value: &str) {
+    state
+        .borrow_mut()
+        .response
+        .headers
+        .push((key.to_string(), value.to_string()));
+}
+
+fn response_available(state: &Rc<RefCell<RequestState>>, connection: &dyn Connection) {
+    if let Err(err) = read_available(state, connection) {
+        finish(state, Err(err));
+    }
+}
+
+fn read_available(state: &Rc<RefCell<RequestState>>, connection: &dyn Connection) -> Result<()> {
+    let mut buffer = [0u8; 512];
+    while connection.bytes_available()? > 0 {
+        let read = connection.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        state
+            .borrow_mut()
+            .response
+            .body
+            .extend_from_slice(&buffer[..read]);
+    }
+    Ok(())
+}
+
+fn request_complete(state: &Rc<RefCell<RequestState>>, connection: &dyn Connection) {
+    let result = (|| -> Result<Option<Response>> {
+        match connection.error()? {
+            PDNetErr::NET_OK => {}
+            err => bail!("HTTP request failed with {:?}", err),
+        }
+        read_available(state, connection)?;
+        let status = connection.response_status()?;
+        let mut borrowed = state.borrow_mut();
+        borrowed.response.status = status;
+        borrowed.response.url = Some(borrowed.request.url.clone());
+        let location = borrowed.response.header("Location").map(String::from);
+        match location {
+            Some(location)
+                if is_redirect(status) && borrowed.redirects < borrowed.request.max_redirects =>
+            {
+                borrowed.request.redirect(status, &location)?;
+                borrowed.redirects += 1;
+                Ok(None)
+            }
+            _ => {
+                if has_body(&borrowed.request.method, status) {
+                    check_content_length(&borrowed.response)?;
+                }
+                Ok(Some(core::mem::take(&mut borrowed.response)))
+            }
+        }
+    })();
+    match result {
+        Ok(Some(response)) => finish(state, Ok(response)),
+        Ok(None) => {
+            // Redirect: the old connection is released once this callback returns.
+            state.borrow_mut().connection = None;
+            if let Err(err) = start_request(state) {
+                finish(state, Err(err));
+            }
+        }
+        Err(err) => finish(state, Err(err)),
+    }
+}
+
+fn connection_closed(state: &Rc<RefCell<RequestState>>, err: PDNetErr) {
+    finish(
+        state,
+        Err(anyhow!(
+            "HTTP connection closed before completing: {:?}",
+            err
+        )),
+    );
+}
+
+// Responses to HEAD, and 204s and 304s, can have a `Content-Length` without a body.
+fn has_body(method: &str, status: i32) -> bool {
+    method != "HEAD" && !matches!(status, 204 | 304)
+}
+
+// Catches responses cut short, which the Playdate can report as complete.
+fn check_content_length(response: &Response) -> Result<()> {
+    if let Some(length) = response.header("Content-Length") {
+        let length = length
+            .trim()
+            .parse::<usize>()
+            .map_err(|_| anyhow!("Invalid Content-Length {}", length))?;
+        ensure!(
+            response.body.len() >= length,
+            "HTTP response ended after {} of {} bytes",
+            response.body.len(),
+            length
+        );
+    }
+    Ok(())
+}
+
+fn is_redirect(status: i32) -> bool {
+    matches!(status, 301 | 302 | 303 | 307 | 308)
+}
+
+fn finish(state: &Rc<RefCell<RequestState>>, result: Result<Response>) {
+    let mut borrowed = state.borrow_mut();
+    let callback = borrowed.callback.take();
+    // The connection stays alive until the current Playdate callback returns.
+    borrowed.connection = None;
+    drop(borrowed);
+    if let Some(callback) = callback {
+        callback(result);
+    }
+}
+
+#[derive(Default)]
+struct ResponseFutureState {
+    result: Option<Result<Response>>,
+    waker: Option<Waker>,
+}
+
+/// The response to a request sent with `RequestBuilder::fetch`.
+pub struct ResponseFuture {
+    shared: Rc<RefCell<ResponseFutureState>>,
+}
+
+impl Future for ResponseFuture {
+    type Output = Result<Response>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.shared.borrow_mut();
+        if let Some(result) = state.result.take() {
+            Poll::Ready(result)
+        } else {
+            state.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, alloc::collections::VecDeque, core::cell::Cell};
+
+    fn url(use_ssl: bool, server: &str, port: i32, path: &str) -> Url {
+        Url {
+            use_ssl,
+            server: server.into(),
+            port,
+            path: path.into(),
+        }
+    }
+
+    #[test]
+    fn parses_urls() {
+        assert_eq!(
+            Url::parse("https://example.com/a/b?c=d").unwrap(),
+            url(true, "example.com", 443, "/a/b?c=d")
+        );
+        assert_eq!(
+            Url::parse("HTTP://example.com:8080").unwrap(),
+            url(false, "example.com", 8080, "/")
+        );
+        assert_eq!(
+            Url::parse("http://example.com?q=1#top").unwrap(),
+            url(false, "example.com", 80, "/?q=1")
+        );
+    }
+
+    #[test]
+    fn parses_ipv6_hosts() {
+        assert_eq!(
+            Url::parse("http://[::1]/").unwrap(),
+            url(false, "::1", 80, "/")
+        );
+        assert_eq!(
+            Url::parse("https://[fe80::1]:8443/x").unwrap(),
+            url(true, "fe80::1", 8443, "/x")
+        );
+        assert!(Url::parse("http://[::1/").is_err());
+        assert!(Url::parse("http://[::1]x/").is_err());
+    }
+
+    #[test]
+    fn rejects_bad_urls() {
+        assert!(Url::parse("ftp://example.com/").is_err());
+        assert!(Url::parse("http:///path").is_err());
+        assert!(Url::parse("http://example.com:port/").is_err());
+        assert!(Url::parse("http://user@example.com/").is_err());
+    }
+
+    #[test]
+    fn joins_locations() {
+        let base = url(true, "example.com", 443, "/a/b?c=d");
+        assert_eq!(
+            base.join("http://other.com/x").unwrap(),
+            url(false, "other.com", 80, "/x")
+        );
+        assert_eq!(
+            base.join("//cdn.example.com/y").unwrap(),
+            url(true, "cdn.example.com", 443, "/y")
+        );
+        assert_eq!(
+            base.join("/root").unwrap(),
+            url(true, "example.com", 443, "/root")
+        );
+        assert_eq!(
+            base.join("sibling?e=f").unwrap(),
+            url(true, "example.com", 443, "/a/sibling?e=f")
+        );
+    }
+
+    #[test]
+    fn redirects_post_to_get() {
+        let mut request = HttpClient::post("http://example.com/form", b"x=1".to_vec()).unwrap();
+        request.redirect(302, "/done").unwrap();
+        assert_eq!(request.method, "GET");
+        assert_eq!(request.body, None);
+        assert_eq!(request.url.path, "/done");
+    }
+
+    #[test]
+    fn keeps_method_for_307() {
+        let mut request = HttpClient::post("http://example.com/form", b"x=1".to_vec()).unwrap();
+        request.redirect(307, "https://example.com/form").unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.body.as_deref(), Some(&b"x=1"[..]));
+        assert!(request.url.use_ssl);
+
+        let mut request = HttpClient::request("PUT", "http://example.com/a").unwrap();
+        request.redirect(303, "/b").unwrap();
+        assert_eq!(request.method, "GET");
+    }
+
+    #[test]
+    fn builds_header_block() {
+        let request = HttpClient::get("http://example.com/")
+            .unwrap()
+            .header("Accept", "application/json")
+            .header("X-Token", "abc");
+        assert_eq!(
+            request.header_block(),
+            b"Accept: application/json\r\nX-Token: abc\r\n"
+        );
+        assert!(HttpClient::get("http://example.com/")
+            .unwrap()
+            .header_block()
+            .is_empty());
+    }
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Sent {
+        method: String,
+        url: Url,
+        headers: Vec<u8>,
+        body: Option<Vec<u8>>,
+    }
+
+    struct FakeConnection {
+        url: Url,
+        sent: RefCell<Option<Sent>>,
+        status: Cell<i32>,
+        body: RefCell<VecDeque<u8>>,
+        error: Cell<PDNetErr>,
+    }
+
+    impl Connection for Rc<FakeConnection> {
+        fn send(
+            &self,
+            method: &str,
+            path: &str,
+            headers: Option<&[u8]>,
+            body: Option<&[u8]>,
+        ) -> Result<()> {
+            assert_eq!(path, self.url.path);
+            *self.sent.borrow_mut() = Some(Sent {
+                method: method.into(),
+                url: self.url.clone(),
+                headers: headers.unwrap_or_default().to_vec(),
+                body: body.map(<[u8]>::to_vec),
+            });
+            Ok(())
+        }
+
+        fn bytes_available(&self) -> Result<usize> {
+            Ok(self.body.borrow().len())
+        }
+
+        fn read(&self, buffer: &mut [u8]) -> Result<usize> {
+            let mut body = self.body.borrow_mut();
+            let count = buffer.len().min(body.len());
+            for (byte, value) in buffer.iter_mut().zip(body.drain(..count)) {
+                *byte = value;
+            }
+            Ok(count)
+        }
+
+        fn error(&self) -> Result<PDNetErr> {
+            Ok(self.error.get())
+        }
+
+        fn response_status(&self) -> Result<i32> {
+            Ok(self.status.get())
+        }
+    }
+
+    #[derive(Clone)]
+    struct Route {
+        status: i32,
+        headers: Vec<(&'static str, String)>,
+        body: &'static [u8],
+        // Sends this much of the body, then fails with `error` or closes.
+        error: PDNetErr,
+        closes: bool,
+    }
+
+    fn route(status: i32, headers: &[(&'static str, &str)], body: &'static [u8]) -> Route {
+        Route {
+            status,
+            headers: headers
+                .iter()
+                .map(|&(key, value)| (key, value.to_string()))
+                .collect(),
+            body,
+            error: PDNetErr::NET_OK,
+            closes: false,
+        }
+    }
+
+    type PendingConnection = (Rc<RefCell<RequestState>>, Rc<FakeConnection>);
+
+    // A local stand-in for an HTTP server, answering by path.  Connections the client opens
+    // wait until `serve`, which plays the Playdate's callbacks for each in turn: headers, the
+    // body a few bytes at a time, then completion.
+    struct StandIn {
+        routes: Vec<(&'static str, Route)>,
+        requests: RefCell<Vec<Sent>>,
+        pending: RefCell<Vec<PendingConnection>>,
+    }
+
+    impl StandIn {
+        fn new(routes: Vec<(&'static str, Route)>) -> Rc<Self> {
+            Rc::new(Self {
+                routes,
+                requests: RefCell::new(Vec::new()),
+                pending: RefCell::new(Vec::new()),
+            })
+        }
+
+        fn open(&self, state: &Rc<RefCell<RequestState>>) -> Result<Box<dyn Connection>> {
+            let connection = Rc::new(FakeConnection {
+                url: state.borrow().request.url.clone(),
+                sent: RefCell::new(None),
+                status: Cell::new(0),
+                body: RefCell::new(VecDeque::new()),
+                error: Cell::new(PDNetErr::NET_OK),
+            });
+            self.pending
+                .borrow_mut()
+                .push((state.clone(), connection.clone()));
+            Ok(Box::new(connection))
+        }
+
+        fn serve(&self) {
+            loop {
+                let next = self.pending.borrow_mut().pop();
+                let Some((state, connection)) = next else {
+                    break;
+                };
+                let sent = connection.sent.borrow_mut().take().expect("request sent");
+                let (_, route) = self
+                    .routes
+                    .iter()
+                    .find(|(path, _)| *path == sent.url.path)
+                    .expect("route for path");
+                self.requests.borrow_mut().push(sent);
+                for (key, value) in &route.headers {
+                    header_received(&state, key, value);
+                }
+                connection.status.set(route.status);
+                for chunk in route.body.chunks(5) {
+                    connection.body.borrow_mut().extend(chunk);
+                    response_available(&state, &connection);
+                }
+                if route.closes {
+                    connection_closed(&state, PDNetErr::NET_CONNECTION_CLOSED);
+                } else {
+                    connection.error.set(route.error);
+                    request_complete(&state, &connection);
+                }
+            }
+        }
+
+        fn fetch(self: &Rc<Self>, request: RequestBuilder) -> Result<Response> {
+            let result = Rc::new(RefCell::new(None));
+            let slot = result.clone();
+            let stand_in = self.clone();
+            let calls = Rc::new(Cell::new(0));
+            let counted = calls.clone();
+            request.send_with(
+                Rc::new(move |state: &Rc<RefCell<RequestState>>| stand_in.open(state)),
+                Box::new(move |response| {
+                    counted.set(counted.get() + 1);
+                    *slot.borrow_mut() = Some(response);
+                }),
+            )?;
+            self.serve();
+            assert_eq!(calls.get(), 1, "callback called once");
+            let response = result.borrow_mut().take().expect("response");
+            response
+        }
+    }
+
+    #[test]
+    fn collects_status_headers_and_body() {
+        let body = b"{\"levels\": [1, 2, 3, 4, 5]}";
+        let stand_in = StandIn::new(vec![(
+            "/levels.json",
+            route(
+                200,
+                &[
+                    ("Content-Type", "application/json"),
+                    ("Content-Length", "27"),
+                ],
+                body,
+            ),
+        )]);
+        let request = HttpClient::get("http://example.com/levels.json")
+            .unwrap()
+            .header("Accept", "application/json");
+        let response = stand_in.fetch(request).unwrap();
+        assert_eq!(response.status, 200);
+        assert!(response.is_success());
+        assert_eq!(response.header("content-type"), Some("application/json"));
+        assert_eq!(response.body, body);
+        assert_eq!(
+            response.url,
+            Some(url(false, "example.com", 80, "/levels.json"))
+        );
+        assert_eq!(
+            *stand_in.requests.borrow(),
+            [Sent {
+                method: "GET".into(),
+                url: url(false, "example.com", 80, "/levels.json"),
+                headers: b"Accept: application/json\r\n".to_vec(),
+                body: None,
+            }]
+        );
+    }
+
+    #[test]
+    fn follows_redirects() {
+        let stand_in = StandIn::new(vec![
+            ("/form", route(302, &[("Location", "/moved")], b"")),
+            (
+                "/moved",
+                route(307, &[("Location", "https://cdn.example.com/done")], b""),
+            ),
+            ("/done", route(200, &[("Content-Length", "2")], b"ok")),
+        ]);
+        let request = HttpClient::post("http://example.com/form", b"x=1".to_vec()).unwrap();
+        let response = stand_in.fetch(request).unwrap();
+        assert_eq!(response.text().unwrap(), "ok");
+        assert_eq!(
+            response.url,
+            Some(url(true, "cdn.example.com", 443, "/done"))
+        );
+        // Headers from the redirects aren't kept.
+        assert_eq!(response.headers.len(), 1);
+        let requests = stand_in.requests.borrow();
+        let sent: Vec<_> = requests
+            .iter()
+            .map(|sent| {
+                (
+                    sent.method.as_str(),
+                    sent.url.path.as_str(),
+                    sent.body.is_some(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            sent,
+            [
+                ("POST", "/form", true),
+                ("GET", "/moved", false),
+                ("GET", "/done", false)
+            ]
+        );
+    }
+
+    #[test]
+    fn returns_the_redirect_past_max_redirects() {
+        let stand_in = StandIn::new(vec![
+            ("/a", route(301, &[("Location", "/b")], b"")),
+            ("/b", route(301, &[("Location", "/c")], b"")),
+        ]);
+        let request = HttpClient::get("http://example.com/a")
+            .unwrap()
+            .max_redirects(1);
+        let response = stand_in.fetch(request).unwrap();
+        assert_eq!(response.status, 301);
+        assert_eq!(response.header("Location"), Some("/c"));
+        assert_eq!(stand_in.requests.borrow().len(), 2);
+    }
+
+    #[test]
+    fn checks_content_length() {
+        let stand_in = StandIn::new(vec![
+            ("/short", route(200, &[("Content-Length", "10")], b"abcd")),
+            ("/exact", route(200, &[("Content-Length", "4")], b"abcd")),
+            ("/bad", route(200, &[("Content-Length", "many")], b"abcd")),
+            ("/empty", route(204, &[("Content-Length", "10")], b"")),
+        ]);
+        let get = |path: &str| {
+            stand_in.fetch(HttpClient::get(&format!("http://example.com{}", path)).unwrap())
+        };
+        assert!(get("/short").is_err());
+        assert_eq!(get("/exact").unwrap().body, b"abcd");
+        assert!(get("/bad").is_err());
+        assert!(get("/empty").unwrap().body.is_empty());
+        let head = HttpClient::request("HEAD", "http://example.com/empty").unwrap();
+        assert_eq!(stand_in.fetch(head).unwrap().status, 204);
+    }
+
+    #[test]
+    fn reports_connection_failures() {
+        let mut failed = route(200, &[], b"partial");
+        failed.error = PDNetErr::NET_READ_ERROR;
+        let mut closed = route(200, &[], b"partial");
+        closed.closes = true;
+        let stand_in = StandIn::new(vec![("/failed", failed), ("/closed", closed)]);
+        assert!(stand_in
+            .fetch(HttpClient::get("http://example.com/failed").unwrap())
+            .is_err());
+        assert!(stand_in
+            .fetch(HttpClient::get("http://example.com/closed").unwrap())
+            .is_err());
+    }
+}
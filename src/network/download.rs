@@ -0,0 +1,424 @@
+//! Streams an HTTP response body straight into a file in the game's data folder, so large
+//! downloads (e.g. extra levels) don't have to fit in memory.
+//!
+//! ```rust
+//! Downloader::new("https://example.com/dlc/world2.pdz", "dlc/world2.pdz")?
+//!     .expected_size(183_412)
+//!     .expected_crc32(0x1c29_1ca3)
+//!     .on_progress(|downloaded, total| log_to_console!("{downloaded}/{total:?}"))
+//!     .start(|result| match result {
+//!         Ok(download) => log_to_console!("saved {} bytes", download.size),
+//!         Err(err) => log_to_console!("download failed: {err:#}"),
+//!     })?;
+//! ```
+//!
+//! The data is written to `<path>.part` first, and renamed to `path` once it's been verified.
+//! If a download is interrupted, starting it again resumes from the end of the partial file.
+
+use {
+    super::{client::Url, HttpConnection, Network},
+    crate::file::{File, FileSystem},
+    alloc::{boxed::Box, format, rc::Rc, string::String},
+    anyhow::{anyhow, bail, ensure, Result},
+    core::cell::RefCell,
+    crankstart_sys::{FileOptions, PDNetErr},
+};
+
+type ProgressCallback = Box<dyn FnMut(u32, Option<u32>) + 'static>;
+type DoneCallback = Box<dyn FnOnce(Result<Download>) + 'static>;
+
+/// A completed, verified download.
+#[derive(Clone, Debug)]
+pub struct Download {
+    pub path: String,
+    pub size: u32,
+    pub crc32: u32,
+}
+
+/// Downloads a URL into a file; see the module documentation.
+pub struct Downloader {
+    url: Url,
+    path: String,
+    expected_size: Option<u32>,
+    expected_crc32: Option<u32>,
+    connect_timeout_ms: Option<u32>,
+    read_timeout_ms: Option<u32>,
+    progress: Option<ProgressCallback>,
+}
+
+impl Downloader {
+    /// Downloads `url` to `path` in the data folder.
+    pub fn new(url: &str, path: &str) -> Result<Self> {
+        Ok(Self {
+            url: Url::parse(url)?,
+            path: path.into(),
+            expected_size: None,
+            expected_crc32: None,
+            connect_timeout_ms: None,
+            read_timeout_ms: None,
+            progress: None,
+        })
+    }
+
+    /// Fails the download if the finished file isn't this many bytes.
+    pub fn expected_size(mut self, size: u32) -> Self {
+        self.expected_size = Some(size);
+        self
+    }
+
+    /// Fails the download, and deletes the partial file, if the CRC-32 of the finished file
+    /// doesn't match.
+    pub fn expected_crc32(mut self, crc32: u32) -> Self {
+        self.expected_crc32 = Some(crc32);
+        self
+    }
+
+    /// Sets both the connect and read timeouts, in milliseconds.
+    pub fn timeout(mut self, timeout_ms: u32) -> Self {
+        self.connect_timeout_ms = Some(timeout_ms);
+        self.read_timeout_ms = Some(timeout_ms);
+        self
+    }
+
+    /// Calls `callback` with the bytes downloaded so far, including any resumed from a partial
+    /// file, and the total size if known.
+    pub fn on_progress<F>(mut self, callback: F) -> Self
+    where
+        F: FnMut(u32, Option<u32>) + 'static,
+    {
+        self.progress = Some(Box::new(callback));
+        self
+    }
+
+    fn part_path(&self) -> String {
+        format!("{}.part", self.path)
+    }
+
+    // Checks the finished partial file, which is `size` bytes with checksum `crc32`, and moves
+    // it into place.
+    fn install(&self, size: u32, crc32: u32) -> Result<Download> {
+        let fs = FileSystem::get();
+        let part_path = self.part_path();
+        if let Some(expected_size) = self.expected_size {
+            ensure!(
+                size == expected_size,
+                "Downloaded {} bytes but expected {}",
+                size,
+                expected_size
+            );
+        }
+        if let Some(expected_crc32) = self.expected_crc32 {
+            if crc32 != expected_crc32 {
+                // Resuming won't fix corrupt data, so start over next time.
+                let _ = fs.unlink(&part_path, false);
+                bail!(
+                    "Downloaded file has CRC-32 {:#010x} but expected {:#010x}",
+                    crc32,
+                    expected_crc32
+                );
+            }
+        }
+
+        if fs.stat(&self.path).is_ok() {
+            fs.unlink(&self.path, false)?;
+        }
+        fs.rename(&part_path, &self.path)?;
+        Ok(Download {
+            path: self.path.clone(),
+            size,
+            crc32,
+        })
+    }
+
+    /// Starts the download, calling `on_done` once it has finished and been verified, or failed.
+    /// If a partial file from an earlier attempt already has the expected size, it's verified
+    /// without connecting, and `on_done` is called before this returns.  One larger than the
+    /// expected size is deleted and the download starts over.
+    pub fn start<F>(self, on_done: F) -> Result<()>
+    where
+        F: FnOnce(Result<Download>) + 'static,
+    {
+        let fs = FileSystem::get();
+        let part_path = self.part_path();
+        let mut resume_from = fs.stat(&part_path).map(|stat| stat.size).unwrap_or(0);
+        if matches!(self.expected_size, Some(expected_size) if resume_from > expected_size) {
+            // Left over from a different file; resuming can't fix it, so start over.
+            fs.unlink(&part_path, false)?;
+            resume_from = 0;
+        }
+        // Checksum what we already have, so the final checksum covers the whole file.
+        let crc = if resume_from > 0 {
+            crc32_file(&part_path)?
+        } else {
+            Crc32::new()
+        };
+        if resume_from > 0 && self.expected_size == Some(resume_from) {
+            // Asking for the bytes after the end would be an invalid range.
+            on_done(self.install(resume_from, crc.finish()));
+            return Ok(());
+        }
+        let options = if resume_from > 0 {
+            FileOptions::kFileAppend
+        } else {
+            FileOptions::kFileWrite
+        };
+        let file = fs.open(&part_path, options)?;
+
+        let connection = Network::get().http().new_connection(
+            &self.url.server,
+            self.url.port,
+            self.url.use_ssl,
+        )?;
+        if let Some(timeout) = self.connect_timeout_ms {
+            connection.set_connect_timeout(timeout)?;
+        }
+        if let Some(timeout) = self.read_timeout_ms {
+            connection.set_read_timeout(timeout)?;
+        }
+        if resume_from > 0 {
+            let end = self
+                .expected_size
+                .map(|size| size.saturating_sub(1))
+                .unwrap_or(i32::MAX as u32);
+            connection.set_byte_range(resume_from, end)?;
+        }
+
+        let state = Rc::new(RefCell::new(DownloadState {
+            downloader: self,
+            connection: None,
+            file: Some(file),
+            crc,
+            resume_from,
+            written: resume_from,
+            checked_status: false,
+            skip_body: false,
+            on_done: Some(Box::new(on_done)),
+        }));
+
+        let response_state = state.clone();
+        connection.on_response(Some(move |connection: &HttpConnection| {
+            if let Err(err) = write_available(&response_state, connection) {
+                finish(&response_state, Err(err));
+            }
+        }))?;
+        let complete_state = state.clone();
+        connection.on_request_complete(Some(move |connection: &HttpConnection| {
+            let result = write_available(&complete_state, connection)
+                .and_then(|()| complete(&complete_state, connection));
+            finish(&complete_state, result);
+        }))?;
+        let closed_state = state.clone();
+        connection.on_connection_closed(Some(move |connection: &HttpConnection| {
+            let err = connection
+                .error()
+                .unwrap_or(PDNetErr::NET_CONNECTION_CLOSED);
+            finish(
+                &closed_state,
+                Err(anyhow!("Download connection closed early: {:?}", err)),
+            );
+        }))?;
+
+        let path = state.borrow().downloader.url.path.clone();
+        connection.get(&path, None)?;
+        state.borrow_mut().connection = Some(connection);
+        Ok(())
+    }
+}
+
+struct DownloadState {
+    downloader: Downloader,
+    // Holds the connection open until the download finishes; see client::RequestState.
+    connection: Option<HttpConnection>,
+    file: Option<File>,
+    crc: Crc32,
+    resume_from: u32,
+    written: u32,
+    checked_status: bool,
+    // Set when the partial file turned out to be complete already; the body isn't the file.
+    skip_body: bool,
+    on_done: Option<DoneCallback>,
+}
+
+fn write_available(state: &Rc<RefCell<DownloadState>>, connection: &HttpConnection) -> Result<()> {
+    let mut state = state.borrow_mut();
+    if !state.checked_status {
+        let status = connection.response_status()?;
+        if status == 0 {
+            // Headers haven't arrived yet.
+            return Ok(());
+        }
+        match status {
+            206 => {}
+            200 if state.resume_from > 0 => {
+                // The server ignored the byte range and is sending the whole file.
+                let part_path = state.downloader.part_path();
+                state.file = None;
+                state.file = Some(FileSystem::get().open(&part_path, FileOptions::kFileWrite)?);
+                state.crc = Crc32::new();
+                state.resume_from = 0;
+                state.written = 0;
+            }
+            200 => {}
+            // Without an expected size, a finished partial file asks for the bytes after its
+            // end, which the server refuses.
+            416 if state.resume_from > 0 => state.skip_body = true,
+            status => bail!("Download failed with HTTP status {}", status),
+        }
+        state.checked_status = true;
+    }
+
+    let mut buffer = [0u8; 1024];
+    let mut wrote_any = false;
+    while connection.bytes_available()? > 0 {
+        let read = connection.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        if state.skip_body {
+            continue;
+        }
+        let file = state
+            .file
+            .as_ref()
+            .ok_or_else(|| anyhow!("Download file already closed"))?;
+        let written = file.write(&buffer[..read])?;
+        ensure!(written == read, "Short write to download file");
+        state.crc.update(&buffer[..read]);
+        state.written += read as u32;
+        wrote_any = true;
+    }
+
+    if wrote_any {
+        let (_, total) = connection.progress()?;
+        let total = if total > 0 {
+            Some(state.resume_from + total as u32)
+        } else {
+            state.downloader.expected_size
+        };
+        let written = state.written;
+        if let Some(progress) = state.downloader.progress.as_mut() {
+            progress(written, total);
+        }
+    }
+    Ok(())
+}
+
+fn complete(state: &Rc<RefCell<DownloadState>>, connection: &HttpConnection) -> Result<Download> {
+    match connection.error()? {
+        PDNetErr::NET_OK => {}
+        err => bail!("Download failed with {:?}", err),
+    }
+    let mut state = state.borrow_mut();
+    ensure!(state.checked_status, "Download finished without a response");
+    if let Some(file) = state.file.take() {
+        file.flush()?;
+    }
+
+    state.downloader.install(state.written, state.crc.finish())
+}
+
+fn finish(state: &Rc<RefCell<DownloadState>>, result: Result<Download>) {
+    let mut borrowed = state.borrow_mut();
+    let on_done = borrowed.on_done.take();
+    // Close the file, keeping what we have so a later attempt can resume.
+    if let Some(file) = borrowed.file.take() {
+        let _ = file.flush();
+    }
+    // The connection stays alive until the current Playdate callback returns.
+    borrowed.connection = None;
+    drop(borrowed);
+    if let Some(on_done) = on_done {
+        on_done(result);
+    }
+}
+
+fn crc32_file(path: &str) -> Result<Crc32> {
+    let file = FileSystem::get().open(path, FileOptions::kFileReadData)?;
+    let mut crc = Crc32::new();
+    let mut buffer = [0u8; 1024];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        crc.update(&buffer[..read]);
+    }
+    Ok(crc)
+}
+
+/// The CRC-32 used by zip, gzip and PNG, computed incrementally.
+#[derive(Clone, Copy, Debug)]
+pub struct Crc32(u32);
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Crc32 {
+    pub fn new() -> Self {
+        Self(0xffff_ffff)
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        let mut crc = self.0;
+        for &byte in data {
+            crc = CRC32_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8);
+        }
+        self.0 = crc;
+    }
+
+    pub fn finish(&self) -> u32 {
+        !self.0
+    }
+
+    /// Returns the CRC-32 of `data`.
+    pub fn checksum(data: &[u8]) -> u32 {
+        let mut crc = Self::new();
+        crc.update(data);
+        crc.finish()
+    }
+}
+
+static CRC32_TABLE: [u32; 256] = crc32_table();
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                0xedb8_8320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(Crc32::checksum(b"123456789"), 0xcbf4_3926);
+        assert_eq!(Crc32::checksum(b""), 0);
+    }
+
+    #[test]
+    fn crc32_in_pieces() {
+        let mut crc = Crc32::new();
+        crc.update(b"1234");
+        crc.update(b"");
+        crc.update(b"56789");
+        assert_eq!(crc.finish(), 0xcbf4_3926);
+    }
+}
@@ -0,0 +1,277 @@
+//! A single flow for getting online: ask for network access, turn on Wi-Fi, wait for it to
+//! connect (retrying with backoff), and report a typed outcome.
+//!
+//! ```rust
+//! Network::get().ensure_online(Some("example.com"), Some("to upload high scores"), |outcome| {
+//!     match outcome {
+//!         Ok(OnlineOutcome::Ready) => upload_scores(),
+//!         Ok(outcome) => log_to_console!("can't upload: {outcome:?}"),
+//!         Err(err) => log_to_console!("network error: {err:#}"),
+//!     }
+//! })?;
+//! ```
+//!
+//! The flow runs as a task on the `executor`, so it makes progress once per frame.  With
+//! `Network::set_idle_disconnect`, Wi-Fi is turned off again once nothing has used it for a
+//! while, to save battery.
+
+use {
+    super::{live_connection_count, Network},
+    crate::{
+        executor::{next_frame, sleep, spawn, TaskHandle},
+        log_to_console,
+        system::System,
+    },
+    alloc::{rc::Rc, string::String},
+    anyhow::Result,
+    core::{cell::Cell, ptr::addr_of_mut},
+    crankstart_sys::{accessReply, PDNetErr, WifiStatus},
+};
+
+/// How `Network::ensure_online` finished.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OnlineOutcome {
+    /// Access was granted and Wi-Fi is connected.
+    Ready,
+    /// The player refused network access for this game.
+    Denied,
+    /// No configured access point was available.
+    NoWifi,
+    /// Wi-Fi didn't connect in time, after all retries.
+    TimedOut,
+}
+
+/// Tuning for `Network::ensure_online_with_options`.
+#[derive(Clone, Debug)]
+pub struct OnlineOptions {
+    pub port: i32,
+    pub use_ssl: bool,
+    /// How long to wait for Wi-Fi to connect on each attempt, in milliseconds.
+    pub attempt_timeout_ms: usize,
+    /// How many times to retry after the first attempt fails.
+    pub retries: u32,
+    /// The wait before the first retry, in milliseconds; it doubles on each later retry.
+    pub initial_backoff_ms: usize,
+}
+
+impl Default for OnlineOptions {
+    fn default() -> Self {
+        Self {
+            port: 443,
+            use_ssl: true,
+            attempt_timeout_ms: 10_000,
+            retries: 2,
+            initial_backoff_ms: 1_000,
+        }
+    }
+}
+
+// How long a NotAvailable status is ignored after enabling Wi-Fi, if the enable callback
+// hasn't said how it went.
+const NOT_AVAILABLE_GRACE_MS: usize = 2_000;
+
+struct IdleState {
+    timeout_ms: Option<usize>,
+    last_active_ms: usize,
+    pending_flows: usize,
+    watcher: Option<TaskHandle>,
+}
+
+static mut IDLE_STATE: IdleState = IdleState {
+    timeout_ms: None,
+    last_active_ms: 0,
+    pending_flows: 0,
+    watcher: None,
+};
+
+// Only used from the game's thread, and callers don't hold on to the reference.
+fn idle_state() -> &'static mut IdleState {
+    unsafe { &mut *addr_of_mut!(IDLE_STATE) }
+}
+
+fn now_ms() -> usize {
+    System::get().get_current_time_milliseconds().unwrap_or(0)
+}
+
+impl Network {
+    /// Gets network access for `server` (or any server if None) and waits for Wi-Fi to connect,
+    /// using the default `OnlineOptions`.  `purpose` is shown to the player if they're asked to
+    /// allow access.
+    pub fn ensure_online<F>(
+        &self,
+        server: Option<&str>,
+        purpose: Option<&str>,
+        on_done: F,
+    ) -> Result<()>
+    where
+        F: FnOnce(Result<OnlineOutcome>) + 'static,
+    {
+        self.ensure_online_with_options(server, purpose, OnlineOptions::default(), on_done)
+    }
+
+    /// Like `ensure_online`, with control over the port, timeouts and retries.
+    pub fn ensure_online_with_options<F>(
+        &self,
+        server: Option<&str>,
+        purpose: Option<&str>,
+        options: OnlineOptions,
+        on_done: F,
+    ) -> Result<()>
+    where
+        F: FnOnce(Result<OnlineOutcome>) + 'static,
+    {
+        let server: Option<String> = server.map(Into::into);
+        let purpose: Option<String> = purpose.map(Into::into);
+        self.mark_active();
+        idle_state().pending_flows += 1;
+        spawn(async move {
+            let result = ensure_online_flow(server, purpose, options).await;
+            idle_state().pending_flows -= 1;
+            Network::get().mark_active();
+            on_done(result);
+        });
+        Ok(())
+    }
+
+    /// Turns Wi-Fi off once it has been idle for `timeout_ms`, or never if None.  Wi-Fi counts as
+    /// in use while an `ensure_online` flow is running or any HTTP or TCP connection is alive, and
+    /// `mark_active` restarts the idle timer.
+    pub fn set_idle_disconnect(&self, timeout_ms: Option<usize>) {
+        let state = idle_state();
+        state.timeout_ms = timeout_ms;
+        if timeout_ms.is_none() {
+            if let Some(watcher) = state.watcher.take() {
+                watcher.cancel();
+            }
+        }
+        self.mark_active();
+    }
+
+    /// Restarts the idle disconnect timer.
+    pub fn mark_active(&self) {
+        let state = idle_state();
+        state.last_active_ms = now_ms();
+        let running = state
+            .watcher
+            .as_ref()
+            .map(|watcher| !watcher.is_finished())
+            .unwrap_or(false);
+        if state.timeout_ms.is_some() && !running {
+            state.watcher = Some(spawn(idle_watcher()));
+        }
+    }
+}
+
+async fn ensure_online_flow(
+    server: Option<String>,
+    purpose: Option<String>,
+    options: OnlineOptions,
+) -> Result<OnlineOutcome> {
+    let network = Network::get();
+
+    let allowed = Rc::new(Cell::new(None));
+    let callback_allowed = allowed.clone();
+    let reply = network.http().request_access(
+        server.as_deref(),
+        options.port,
+        options.use_ssl,
+        purpose.as_deref(),
+        Some(move |granted: bool| callback_allowed.set(Some(granted))),
+    )?;
+    let granted = match reply {
+        accessReply::kAccessAllow => true,
+        accessReply::kAccessDeny => false,
+        accessReply::kAccessAsk => loop {
+            if let Some(granted) = allowed.get() {
+                break granted;
+            }
+            next_frame().await;
+        },
+    };
+    if !granted {
+        return Ok(OnlineOutcome::Denied);
+    }
+
+    let mut backoff_ms = options.initial_backoff_ms;
+    let mut outcome = OnlineOutcome::TimedOut;
+    for attempt in 0..=options.retries {
+        if attempt > 0 {
+            sleep(backoff_ms).await;
+            backoff_ms = backoff_ms.saturating_mul(2);
+        }
+        outcome = connect_attempt(&network, options.attempt_timeout_ms).await?;
+        if outcome == OnlineOutcome::Ready {
+            break;
+        }
+    }
+    Ok(outcome)
+}
+
+async fn connect_attempt(network: &Network, timeout_ms: usize) -> Result<OnlineOutcome> {
+    if network.status()? == WifiStatus::kWifiConnected {
+        return Ok(OnlineOutcome::Ready);
+    }
+
+    let enabled: Rc<Cell<Option<PDNetErr>>> = Rc::new(Cell::new(None));
+    let callback_enabled = enabled.clone();
+    if network
+        .set_enabled_with_callback(true, move |err| callback_enabled.set(Some(err)))
+        .is_err()
+    {
+        // An earlier attempt's callback is still pending, so just poll the status.
+        network.set_enabled(true)?;
+    }
+
+    let started_ms = now_ms();
+    let deadline = started_ms + timeout_ms;
+    loop {
+        let status = network.status()?;
+        // The status can still say NotAvailable from before Wi-Fi was enabled, so only believe
+        // it once enabling has finished, or has had time to.
+        let settled = enabled.get().is_some()
+            || now_ms().saturating_sub(started_ms) >= NOT_AVAILABLE_GRACE_MS;
+        match (status, enabled.get()) {
+            (WifiStatus::kWifiConnected, _) => return Ok(OnlineOutcome::Ready),
+            (_, Some(err)) if err != PDNetErr::NET_OK => {
+                log_to_console!("Enabling Wi-Fi failed with {:?}", err);
+                return Ok(OnlineOutcome::NoWifi);
+            }
+            (WifiStatus::kWifiNotAvailable, _) if settled => return Ok(OnlineOutcome::NoWifi),
+            _ => {}
+        }
+        if now_ms() >= deadline {
+            return Ok(OnlineOutcome::TimedOut);
+        }
+        next_frame().await;
+    }
+}
+
+async fn idle_watcher() {
+    loop {
+        let (timeout_ms, last_active_ms, busy) = {
+            let state = idle_state();
+            (
+                state.timeout_ms,
+                state.last_active_ms,
+                state.pending_flows > 0,
+            )
+        };
+        let Some(timeout_ms) = timeout_ms else {
+            return;
+        };
+        if busy || live_connection_count() > 0 {
+            Network::get().mark_active();
+            sleep(timeout_ms).await;
+            continue;
+        }
+        let idle_ms = now_ms().saturating_sub(last_active_ms);
+        if idle_ms < timeout_ms {
+            sleep(timeout_ms - idle_ms).await;
+            continue;
+        }
+        if let Err(err) = Network::get().set_enabled(false) {
+            log_to_console!("Error disabling idle Wi-Fi: {err:#}");
+        }
+        return;
+    }
+}
@@ -0,0 +1,306 @@
+use {
+    super::{describe_net_err, ensure_net_ok, request_access, LIVE_CONNECTIONS},
+    crate::pd_func_caller,
+    alloc::{
+        boxed::Box,
+        rc::{Rc, Weak},
+    },
+    anyhow::{anyhow, ensure, Error, Result},
+    core::{cell::RefCell, convert::TryInto, mem::ManuallyDrop, ptr},
+    crankstart_sys::{accessReply, ctypes, playdate_tcp, PDNetErr, TCPConnection},
+    cstr_core::CString,
+};
+
+#[derive(Clone, Copy, Debug)]
+pub struct Tcp {
+    raw_tcp: *const playdate_tcp,
+}
+
+impl Tcp {
+    pub(crate) fn new(raw_tcp: *const playdate_tcp) -> Self {
+        Self { raw_tcp }
+    }
+
+    fn api(&self) -> &playdate_tcp {
+        unsafe { &*self.raw_tcp }
+    }
+
+    pub fn request_access<F>(
+        &self,
+        server: Option<&str>,
+        port: i32,
+        use_ssl: bool,
+        purpose: Option<&str>,
+        callback: Option<F>,
+    ) -> Result<accessReply>
+    where
+        F: FnMut(bool) + 'static,
+    {
+        request_access(
+            self.api().requestAccess,
+            server,
+            port,
+            use_ssl,
+            purpose,
+            callback,
+        )
+    }
+
+    pub fn new_connection(&self, server: &str, port: i32, use_ssl: bool) -> Result<TcpConnection> {
+        ensure!(
+            !server.is_empty(),
+            "TCP connections require a non-empty server"
+        );
+        let server_c = CString::new(server).map_err(Error::msg)?;
+        let raw_connection =
+            pd_func_caller!(self.api().newConnection, server_c.as_ptr(), port, use_ssl)?;
+        ensure!(
+            !raw_connection.is_null(),
+            "TCP connection creation returned null (permission denied?)"
+        );
+        TcpConnection::from_raw(self.raw_tcp, raw_connection)
+    }
+}
+
+type ClosedCallback = Box<dyn FnMut(&TcpConnection, PDNetErr) + 'static>;
+type ClosedCallbackPtr = *mut (dyn FnMut(&TcpConnection, PDNetErr) + 'static);
+type OpenCallback = Box<dyn FnOnce(PDNetErr) + 'static>;
+
+struct TcpConnectionInner {
+    raw_tcp: *const playdate_tcp,
+    raw_connection: *mut TCPConnection,
+    closed_callback: RefCell<Option<ClosedCallback>>,
+}
+
+impl Drop for TcpConnectionInner {
+    fn drop(&mut self) {
+        unsafe {
+            LIVE_CONNECTIONS -= 1;
+        }
+        fn do_drop(conn: &mut TcpConnectionInner) -> Result<()> {
+            unsafe {
+                let userdata = pd_func_caller!((*conn.raw_tcp).getUserdata, conn.raw_connection)?;
+                // Drop weak count; see HttpConnectionInner.
+                Weak::from_raw(userdata as *const TcpConnectionInner);
+                pd_func_caller!(
+                    (*conn.raw_tcp).setUserdata,
+                    conn.raw_connection,
+                    ptr::null_mut()
+                )?;
+                pd_func_caller!((*conn.raw_tcp).close, conn.raw_connection)?;
+                pd_func_caller!((*conn.raw_tcp).release, conn.raw_connection)?;
+            }
+            Ok(())
+        }
+        do_drop(self).unwrap();
+    }
+}
+
+/// A raw TCP connection, optionally over TLS.  Reads and writes never block; poll
+/// `bytes_available` (e.g. once a frame) to find out when there's data.
+#[derive(Clone)]
+pub struct TcpConnection {
+    inner: Rc<TcpConnectionInner>,
+}
+
+extern "C" fn tcp_open_trampoline(
+    _conn: *mut TCPConnection,
+    err: PDNetErr,
+    userdata: *mut ctypes::c_void,
+) {
+    if userdata.is_null() {
+        return;
+    }
+    let callback = unsafe { Box::from_raw(userdata as *mut OpenCallback) };
+    callback(err);
+}
+
+extern "C" fn tcp_connection_closed_trampoline(conn: *mut TCPConnection, err: PDNetErr) {
+    let Some(api) = (unsafe { super::NETWORK.raw_tcp.as_ref() }) else {
+        return;
+    };
+    let Some(get_userdata) = api.getUserdata else {
+        return;
+    };
+    let userdata = unsafe { get_userdata(conn) };
+    if userdata.is_null() {
+        return;
+    }
+    let weak = unsafe {
+        // Don't decrement the weak count when this goes out of scope.
+        ManuallyDrop::new(Weak::from_raw(userdata as *const TcpConnectionInner))
+    };
+    if let Some(inner) = weak.upgrade() {
+        let connection = TcpConnection { inner };
+        let callback_ptr = connection
+            .inner
+            .closed_callback
+            .borrow_mut()
+            .as_mut()
+            .map(|cb| &mut **cb as ClosedCallbackPtr);
+        if let Some(callback_ptr) = callback_ptr {
+            unsafe {
+                (*callback_ptr)(&connection, err);
+            }
+        }
+    }
+}
+
+impl TcpConnection {
+    fn from_raw(raw_tcp: *const playdate_tcp, raw_connection: *mut TCPConnection) -> Result<Self> {
+        let inner = Rc::new(TcpConnectionInner {
+            raw_tcp,
+            raw_connection,
+            closed_callback: RefCell::new(None),
+        });
+        unsafe {
+            LIVE_CONNECTIONS += 1;
+        }
+        let userdata_ptr = Weak::into_raw(Rc::downgrade(&inner)) as *mut ctypes::c_void;
+        pd_func_caller!((*raw_tcp).setUserdata, raw_connection, userdata_ptr)?;
+        Ok(Self { inner })
+    }
+
+    fn api(&self) -> &playdate_tcp {
+        unsafe { &*self.inner.raw_tcp }
+    }
+
+    pub fn raw_connection(&self) -> *mut TCPConnection {
+        self.inner.raw_connection
+    }
+
+    /// Starts connecting, calling `callback` with the result once connected or failed.
+    pub fn open<F>(&self, callback: F) -> Result<()>
+    where
+        F: FnOnce(PDNetErr) + 'static,
+    {
+        let userdata = Box::into_raw(Box::new(Box::new(callback) as OpenCallback));
+        let err = pd_func_caller!(
+            self.api().open,
+            self.raw_connection(),
+            Some(tcp_open_trampoline),
+            userdata as *mut ctypes::c_void
+        );
+        match err {
+            Ok(PDNetErr::NET_OK) => Ok(()),
+            other => {
+                // The callback won't be called, so free it here.
+                unsafe {
+                    drop(Box::from_raw(userdata));
+                }
+                ensure_net_ok(other?, "tcp.open")
+            }
+        }
+    }
+
+    pub fn close(&self) -> Result<()> {
+        let err = pd_func_caller!(self.api().close, self.raw_connection())?;
+        ensure_net_ok(err, "tcp.close")
+    }
+
+    pub fn error(&self) -> Result<PDNetErr> {
+        pd_func_caller!(self.api().getError, self.raw_connection())
+    }
+
+    pub fn set_connect_timeout(&self, timeout_ms: u32) -> Result<()> {
+        pd_func_caller!(
+            self.api().setConnectTimeout,
+            self.raw_connection(),
+            timeout_ms.try_into().map_err(Error::msg)?
+        )
+    }
+
+    pub fn set_read_timeout(&self, timeout_ms: u32) -> Result<()> {
+        pd_func_caller!(
+            self.api().setReadTimeout,
+            self.raw_connection(),
+            timeout_ms.try_into().map_err(Error::msg)?
+        )
+    }
+
+    pub fn set_read_buffer_size(&self, bytes: u32) -> Result<()> {
+        pd_func_caller!(
+            self.api().setReadBufferSize,
+            self.raw_connection(),
+            bytes.try_into().map_err(Error::msg)?
+        )
+    }
+
+    pub fn bytes_available(&self) -> Result<usize> {
+        pd_func_caller!(self.api().getBytesAvailable, self.raw_connection())
+    }
+
+    pub fn read(&self, buffer: &mut [u8]) -> Result<usize> {
+        assert!(
+            !buffer.is_empty(),
+            "Buffer must not be empty to distinguish from EOF"
+        );
+        let result = pd_func_caller!(
+            self.api().read,
+            self.raw_connection(),
+            buffer.as_mut_ptr() as *mut ctypes::c_void,
+            buffer.len()
+        )?;
+        if result >= 0 {
+            Ok(result as usize)
+        } else {
+            Err(anyhow!(
+                "tcp.read returned error {}",
+                describe_net_err(result)
+            ))
+        }
+    }
+
+    /// Writes as much of `data` as the connection will take, returning the number of bytes
+    /// written.
+    pub fn write(&self, data: &[u8]) -> Result<usize> {
+        let result = pd_func_caller!(
+            self.api().write,
+            self.raw_connection(),
+            data.as_ptr() as *const ctypes::c_void,
+            data.len()
+        )?;
+        if result >= 0 {
+            Ok(result as usize)
+        } else {
+            Err(anyhow!(
+                "tcp.write returned error {}",
+                describe_net_err(result)
+            ))
+        }
+    }
+
+    /// Writes as much of `data` as the connection will take without blocking, calling `write`
+    /// until it makes no progress, and returns the number of bytes written.  A full send
+    /// buffer isn't an error; write the rest later, e.g. on the next frame.
+    pub fn write_available(&self, data: &[u8]) -> Result<usize> {
+        let mut total = 0;
+        while total < data.len() {
+            let written = self.write(&data[total..])?;
+            if written == 0 {
+                break;
+            }
+            total += written;
+        }
+        Ok(total)
+    }
+
+    pub fn on_connection_closed<F>(&self, callback: Option<F>) -> Result<()>
+    where
+        F: FnMut(&TcpConnection, PDNetErr) + 'static,
+    {
+        let register = callback.is_some();
+        *self.inner.closed_callback.borrow_mut() =
+            callback.map(|cb| Box::new(cb) as ClosedCallback);
+        let callback_fn = if register {
+            Some(tcp_connection_closed_trampoline as unsafe extern "C" fn(_, _))
+        } else {
+            None
+        };
+        pd_func_caller!(
+            self.api().setConnectionClosedCallback,
+            self.raw_connection(),
+            callback_fn
+        )
+    }
+}
@@ -0,0 +1,1347 @@
+//! A WebSocket client (RFC 6455) over a `TcpConnection`.
+//!
+//! ```rust
+//! let socket = WebSocket::connect("wss://example.com/chat", |socket, event| match event {
+//!     WebSocketEvent::Open => socket.send_text("hello").unwrap(),
+//!     WebSocketEvent::Text(text) => log_to_console!("got {text}"),
+//!     WebSocketEvent::Closed { code, .. } => log_to_console!("closed with {code:?}"),
+//!     _ => {}
+//! })?;
+//! ```
+//!
+//! The socket is polled by a task on the `executor`, so events are delivered from
+//! `GameRunner::update`, before `Game::update`.  Pings are answered automatically.
+
+use {
+    super::{client::Url, Network, TcpConnection},
+    crate::{
+        executor::{next_frame, spawn},
+        system::System,
+    },
+    alloc::{boxed::Box, format, rc::Rc, string::String, vec::Vec},
+    anyhow::{anyhow, bail, ensure, Error, Result},
+    core::cell::{Cell, RefCell},
+    crankstart_sys::PDNetErr,
+};
+
+const OPCODE_CONTINUATION: u8 = 0x0;
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_BINARY: u8 = 0x2;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xa;
+
+const HANDSHAKE_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+const MAX_HANDSHAKE_SIZE: usize = 8 * 1024;
+const CLOSE_TIMEOUT_MS: usize = 2000;
+
+/// Close status codes from RFC 6455 section 7.4.1.
+pub const CLOSE_NORMAL: u16 = 1000;
+pub const CLOSE_PROTOCOL_ERROR: u16 = 1002;
+pub const CLOSE_TOO_BIG: u16 = 1009;
+
+type EventCallback = Box<dyn FnMut(&WebSocket, WebSocketEvent) + 'static>;
+
+#[derive(Debug)]
+pub enum WebSocketEvent {
+    /// The handshake finished; messages can now be sent.
+    Open,
+    Text(String),
+    Binary(Vec<u8>),
+    /// A reply to `WebSocket::ping`.
+    Pong(Vec<u8>),
+    /// Something went wrong; a `Closed` event follows.
+    Error(Error),
+    /// The connection is closed, and no more events will be delivered.  `clean` is true if
+    /// the closing handshake completed; `code` is the status the server sent, if any.
+    Closed {
+        code: Option<u16>,
+        reason: String,
+        clean: bool,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WebSocketState {
+    Connecting,
+    Open,
+    Closing,
+    Closed,
+}
+
+/// Configures a WebSocket connection; see `WebSocket::builder`.
+pub struct WebSocketBuilder {
+    url: Url,
+    headers: Vec<(String, String)>,
+    protocols: Vec<String>,
+    connect_timeout_ms: Option<u32>,
+    max_message_size: usize,
+}
+
+impl WebSocketBuilder {
+    /// Adds a header to the upgrade request.
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Adds a subprotocol to offer in `Sec-WebSocket-Protocol`.
+    pub fn protocol(mut self, protocol: &str) -> Self {
+        self.protocols.push(protocol.into());
+        self
+    }
+
+    pub fn connect_timeout(mut self, timeout_ms: u32) -> Self {
+        self.connect_timeout_ms = Some(timeout_ms);
+        self
+    }
+
+    /// Closes the connection if a message larger than this arrives.  Defaults to 64KiB.
+    pub fn max_message_size(mut self, bytes: usize) -> Self {
+        self.max_message_size = bytes;
+        self
+    }
+
+    /// Starts connecting, calling `on_event` with each event.
+    pub fn connect<F>(self, on_event: F) -> Result<WebSocket>
+    where
+        F: FnMut(&WebSocket, WebSocketEvent) + 'static,
+    {
+        let connection = Network::get().tcp().new_connection(
+            &self.url.server,
+            self.url.port,
+            self.url.use_ssl,
+        )?;
+        if let Some(timeout) = self.connect_timeout_ms {
+            connection.set_connect_timeout(timeout)?;
+        }
+
+        let shared = Rc::new(Shared::default());
+        let closed_shared = shared.clone();
+        connection.on_connection_closed(Some(move |_: &TcpConnection, err: PDNetErr| {
+            closed_shared.tcp_closed.set(Some(err));
+        }))?;
+        let open_shared = shared.clone();
+        connection.open(move |err| open_shared.opened.set(Some(err)))?;
+
+        let socket = self.socket(
+            Box::new(connection),
+            shared,
+            Rng::seeded(),
+            now_ms,
+            Box::new(on_event),
+        );
+        let task_socket = socket.clone();
+        spawn(async move {
+            while task_socket.poll() {
+                next_frame().await;
+            }
+        });
+        Ok(socket)
+    }
+
+    // Sets up a socket over `connection`, with the handshake queued for once it opens.
+    fn socket(
+        &self,
+        connection: Box<dyn Connection>,
+        shared: Rc<Shared>,
+        mut rng: Rng,
+        clock: fn() -> usize,
+        on_event: EventCallback,
+    ) -> WebSocket {
+        let mut key_bytes = [0u8; 16];
+        rng.fill(&mut key_bytes);
+        let key = base64_encode(&key_bytes);
+        WebSocket {
+            inner: Rc::new(RefCell::new(SocketState {
+                connection: Some(connection),
+                state: WebSocketState::Connecting,
+                handshake: Some(self.handshake_request(&key)),
+                accept_key: accept_key(&key),
+                buffer: Vec::new(),
+                outgoing: Vec::new(),
+                message: None,
+                max_message_size: self.max_message_size,
+                rng,
+                clock,
+                close_sent_at: None,
+                close_received: None,
+            })),
+            shared,
+            on_event: Rc::new(RefCell::new(Some(on_event))),
+        }
+    }
+
+    fn handshake_request(&self, key: &str) -> Vec<u8> {
+        let default_port = if self.url.use_ssl { 443 } else { 80 };
+        let host = if self.url.port == default_port {
+            self.url.server.clone()
+        } else {
+            format!("{}:{}", self.url.server, self.url.port)
+        };
+        let mut request = format!(
+            "GET {} HTTP/1.1\r\nHost: {}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+             Sec-WebSocket-Key: {}\r\nSec-WebSocket-Version: 13\r\n",
+            self.url.path, host, key
+        );
+        if !self.protocols.is_empty() {
+            request.push_str(&format!(
+                "Sec-WebSocket-Protocol: {}\r\n",
+                self.protocols.join(", ")
+            ));
+        }
+        for (name, value) in &self.headers {
+            request.push_str(&format!("{name}: {value}\r\n"));
+        }
+        request.push_str("\r\n");
+        request.into_bytes()
+    }
+}
+
+// Set from Playdate callbacks, which may run while the socket state is borrowed.
+#[derive(Default)]
+struct Shared {
+    opened: Cell<Option<PDNetErr>>,
+    tcp_closed: Cell<Option<PDNetErr>>,
+}
+
+// What the socket needs from its connection, so it doesn't depend on the Playdate's TCP
+// stack directly.
+pub(crate) trait Connection {
+    fn bytes_available(&self) -> Result<usize>;
+    fn read(&self, buffer: &mut [u8]) -> Result<usize>;
+    /// Writes what the connection will take now, returning how much that was.
+    fn write_available(&self, data: &[u8]) -> Result<usize>;
+    /// Closes the connection without reporting it as closed by the other end.
+    fn close(&self);
+}
+
+impl Connection for TcpConnection {
+    fn bytes_available(&self) -> Result<usize> {
+        TcpConnection::bytes_available(self)
+    }
+
+    fn read(&self, buffer: &mut [u8]) -> Result<usize> {
+        TcpConnection::read(self, buffer)
+    }
+
+    fn write_available(&self, data: &[u8]) -> Result<usize> {
+        TcpConnection::write_available(self, data)
+    }
+
+    fn close(&self) {
+        let _ = self.on_connection_closed(None::<fn(&TcpConnection, _)>);
+        let _ = TcpConnection::close(self);
+    }
+}
+
+struct SocketState {
+    connection: Option<Box<dyn Connection>>,
+    state: WebSocketState,
+    // The upgrade request, until it's been sent.
+    handshake: Option<Vec<u8>>,
+    accept_key: String,
+    buffer: Vec<u8>,
+    // Bytes the connection hasn't taken yet; they're written as it has room.
+    outgoing: Vec<u8>,
+    // The opcode and data of a fragmented message being reassembled.
+    message: Option<(u8, Vec<u8>)>,
+    max_message_size: usize,
+    rng: Rng,
+    clock: fn() -> usize,
+    close_sent_at: Option<usize>,
+    close_received: Option<(Option<u16>, String)>,
+}
+
+/// A WebSocket connection.  Cloning gives another handle to the same connection.
+#[derive(Clone)]
+pub struct WebSocket {
+    inner: Rc<RefCell<SocketState>>,
+    shared: Rc<Shared>,
+    on_event: Rc<RefCell<Option<EventCallback>>>,
+}
+
+impl WebSocket {
+    /// Returns a builder for a connection to a `ws://` or `wss://` URL.
+    pub fn builder(url: &str) -> Result<WebSocketBuilder> {
+        let url = if let Some(rest) = url.strip_prefix("wss://") {
+            format!("https://{rest}")
+        } else if let Some(rest) = url.strip_prefix("ws://") {
+            format!("http://{rest}")
+        } else {
+            url.into()
+        };
+        Ok(WebSocketBuilder {
+            url: Url::parse(&url)?,
+            headers: Vec::new(),
+            protocols: Vec::new(),
+            connect_timeout_ms: None,
+            max_message_size: 64 * 1024,
+        })
+    }
+
+    /// Connects to a `ws://` or `wss://` URL with the default options.
+    pub fn connect<F>(url: &str, on_event: F) -> Result<WebSocket>
+    where
+        F: FnMut(&WebSocket, WebSocketEvent) + 'static,
+    {
+        Self::builder(url)?.connect(on_event)
+    }
+
+    pub fn state(&self) -> WebSocketState {
+        self.inner.borrow().state
+    }
+
+    pub fn send_text(&self, text: &str) -> Result<()> {
+        self.send_frame(OPCODE_TEXT, text.as_bytes())
+    }
+
+    pub fn send_binary(&self, data: &[u8]) -> Result<()> {
+        self.send_frame(OPCODE_BINARY, data)
+    }
+
+    /// Sends a ping; the reply arrives as a `Pong` event.  `payload` must be at most 125 bytes.
+    pub fn ping(&self, payload: &[u8]) -> Result<()> {
+        ensure!(payload.len() <= 125, "WebSocket ping payload too long");
+        self.send_frame(OPCODE_PING, payload)
+    }
+
+    /// Starts the closing handshake.  A `Closed` event follows once the server replies, or after
+    /// a short timeout.
+    pub fn close(&self, code: u16, reason: &str) -> Result<()> {
+        let mut inner = self.inner.borrow_mut();
+        match inner.state {
+            WebSocketState::Open => {
+                inner.send_close(code, reason)?;
+                inner.state = WebSocketState::Closing;
+            }
+            WebSocketState::Connecting => {
+                // Nothing to shake hands with yet.
+                inner.state = WebSocketState::Closing;
+                inner.close_sent_at = Some(0);
+            }
+            WebSocketState::Closing | WebSocketState::Closed => {}
+        }
+        Ok(())
+    }
+
+    fn send_frame(&self, opcode: u8, payload: &[u8]) -> Result<()> {
+        let mut inner = self.inner.borrow_mut();
+        ensure!(
+            inner.state == WebSocketState::Open,
+            "WebSocket is not open ({:?})",
+            inner.state
+        );
+        inner.send_frame(opcode, payload)
+    }
+
+    fn emit(&self, event: WebSocketEvent) {
+        // Take the callback out while it runs, so it can use this socket.
+        let callback = self.on_event.borrow_mut().take();
+        if let Some(mut callback) = callback {
+            callback(self, event);
+            let mut slot = self.on_event.borrow_mut();
+            if slot.is_none() && self.state() != WebSocketState::Closed {
+                *slot = Some(callback);
+            }
+        }
+    }
+
+    // Runs once a frame; returns false once the socket is closed.
+    fn poll(&self) -> bool {
+        let (events, closed) = {
+            let mut inner = self.inner.borrow_mut();
+            let mut events = Vec::new();
+            let result = inner.poll(&self.shared, &mut events);
+            let closed = match result {
+                Ok(closed) => closed,
+                Err(err) => {
+                    if inner.state == WebSocketState::Open {
+                        let code = if err.is::<MessageTooBig>() {
+                            CLOSE_TOO_BIG
+                        } else {
+                            CLOSE_PROTOCOL_ERROR
+                        };
+                        let _ = inner.send_close(code, "");
+                    }
+                    events.push(WebSocketEvent::Error(err));
+                    Some((None, String::new(), false))
+                }
+            };
+            (events, closed)
+        };
+        for event in events {
+            self.emit(event);
+        }
+        match closed {
+            Some((code, reason, clean)) => {
+                {
+                    let mut inner = self.inner.borrow_mut();
+                    inner.state = WebSocketState::Closed;
+                    if let Some(connection) = inner.connection.take() {
+                        connection.close();
+                    }
+                }
+                self.emit(WebSocketEvent::Closed {
+                    code,
+                    reason,
+                    clean,
+                });
+                // Break the cycle if the callback holds a handle to this socket.
+                self.on_event.borrow_mut().take();
+                false
+            }
+            None => true,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct MessageTooBig;
+
+impl core::fmt::Display for MessageTooBig {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "WebSocket message too big")
+    }
+}
+
+type CloseInfo = (Option<u16>, String, bool);
+
+impl SocketState {
+    fn connection(&self) -> Result<&dyn Connection> {
+        self.connection
+            .as_deref()
+            .ok_or_else(|| anyhow!("WebSocket connection already closed"))
+    }
+
+    fn send_frame(&mut self, opcode: u8, payload: &[u8]) -> Result<()> {
+        let mut mask = [0u8; 4];
+        self.rng.fill(&mut mask);
+        self.outgoing
+            .extend_from_slice(&encode_frame(opcode, payload, mask));
+        self.flush()
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if !self.outgoing.is_empty() {
+            let written = self.connection()?.write_available(&self.outgoing)?;
+            self.outgoing.drain(..written);
+        }
+        Ok(())
+    }
+
+    fn send_close(&mut self, code: u16, reason: &str) -> Result<()> {
+        let mut payload = Vec::with_capacity(2 + reason.len());
+        payload.extend_from_slice(&code.to_be_bytes());
+        // Control frames are limited to 125 bytes.
+        let mut reason_len = reason.len().min(123);
+        while !reason.is_char_boundary(reason_len) {
+            reason_len -= 1;
+        }
+        payload.extend_from_slice(&reason.as_bytes()[..reason_len]);
+        self.close_sent_at = Some((self.clock)());
+        self.send_frame(OPCODE_CLOSE, &payload)
+    }
+
+    fn poll(
+        &mut self,
+        shared: &Shared,
+        events: &mut Vec<WebSocketEvent>,
+    ) -> Result<Option<CloseInfo>> {
+        if let Some(err) = shared.tcp_closed.get() {
+            // Frames that arrived before the connection dropped still count, such as the
+            // server's close.  Replying isn't possible any more, so errors are ignored.
+            if self.state != WebSocketState::Connecting {
+                if let Ok(Some(closed)) = self.read_frames(events) {
+                    return Ok(Some(closed));
+                }
+            }
+            let closed = self.close_info();
+            if !closed.2 && err != PDNetErr::NET_OK {
+                events.push(WebSocketEvent::Error(anyhow!(
+                    "WebSocket connection closed with {:?}",
+                    err
+                )));
+            }
+            return Ok(Some(closed));
+        }
+        if let Some(sent_at) = self.close_sent_at {
+            if self.state == WebSocketState::Closing
+                && (self.clock)().saturating_sub(sent_at) >= CLOSE_TIMEOUT_MS
+            {
+                // Servers are meant to drop the connection after the closing handshake, but
+                // don't always.
+                return Ok(Some(self.close_info()));
+            }
+        }
+
+        if self.state == WebSocketState::Connecting {
+            match shared.opened.get() {
+                None => return Ok(None),
+                Some(PDNetErr::NET_OK) => {}
+                Some(err) => bail!("WebSocket connection failed with {:?}", err),
+            }
+            if let Some(handshake) = self.handshake.take() {
+                self.outgoing.extend_from_slice(&handshake);
+            }
+        }
+
+        self.flush()?;
+
+        self.read_available()?;
+
+        if self.state == WebSocketState::Connecting {
+            let Some(end) = find_subslice(&self.buffer, b"\r\n\r\n") else {
+                ensure!(
+                    self.buffer.len() <= MAX_HANDSHAKE_SIZE,
+                    "WebSocket handshake response too large"
+                );
+                return Ok(None);
+            };
+            let response = core::str::from_utf8(&self.buffer[..end])
+                .map_err(|_| anyhow!("WebSocket handshake response is not UTF-8"))?;
+            check_handshake_response(response, &self.accept_key)?;
+            self.buffer.drain(..end + 4);
+            self.state = WebSocketState::Open;
+            events.push(WebSocketEvent::Open);
+        }
+
+        self.handle_frames(events)
+    }
+
+    fn read_frames(&mut self, events: &mut Vec<WebSocketEvent>) -> Result<Option<CloseInfo>> {
+        self.read_available()?;
+        self.handle_frames(events)
+    }
+
+    fn handle_frames(&mut self, events: &mut Vec<WebSocketEvent>) -> Result<Option<CloseInfo>> {
+        while let Some((frame, used)) = decode_frame(&self.buffer, self.max_message_size)? {
+            self.buffer.drain(..used);
+            if let Some(closed) = self.handle_frame(frame, events)? {
+                return Ok(Some(closed));
+            }
+        }
+        Ok(None)
+    }
+
+    // The close status the server sent, if any, and whether both sides sent a close.
+    fn close_info(&mut self) -> CloseInfo {
+        match self.close_received.take() {
+            Some((code, reason)) => (code, reason, self.close_sent_at.is_some()),
+            None => (None, String::new(), false),
+        }
+    }
+
+    fn read_available(&mut self) -> Result<()> {
+        let connection = self
+            .connection
+            .as_deref()
+            .ok_or_else(|| anyhow!("WebSocket connection already closed"))?;
+        let mut chunk = [0u8; 512];
+        while connection.bytes_available()? > 0 {
+            let read = connection.read(&mut chunk)?;
+            if read == 0 {
+                break;
+            }
+            self.buffer.extend_from_slice(&chunk[..read]);
+        }
+        Ok(())
+    }
+
+    fn handle_frame(
+        &mut self,
+        frame: Frame,
+        events: &mut Vec<WebSocketEvent>,
+    ) -> Result<Option<CloseInfo>> {
+        match frame.opcode {
+            OPCODE_PING => {
+                if self.state == WebSocketState::Open {
+                    self.send_frame(OPCODE_PONG, &frame.payload)?;
+                }
+            }
+            OPCODE_PONG => events.push(WebSocketEvent::Pong(frame.payload)),
+            OPCODE_CLOSE => {
+                let (code, reason) = if frame.payload.len() >= 2 {
+                    let code = u16::from_be_bytes([frame.payload[0], frame.payload[1]]);
+                    let reason = String::from_utf8_lossy(&frame.payload[2..]).into_owned();
+                    (Some(code), reason)
+                } else {
+                    (None, String::new())
+                };
+                if self.close_sent_at.is_none() {
+                    // Echo the close, then wait for the server to drop the connection.
+                    self.state = WebSocketState::Closing;
+                    self.close_received = Some((code, reason));
+                    self.send_close(code.unwrap_or(CLOSE_NORMAL), "")?;
+                    return Ok(None);
+                }
+                return Ok(Some((code, reason, true)));
+            }
+            OPCODE_TEXT | OPCODE_BINARY => {
+                ensure!(
+                    self.message.is_none(),
+                    "WebSocket data frame inside a fragmented message"
+                );
+                if frame.fin {
+                    self.push_message(frame.opcode, frame.payload, events)?;
+                } else {
+                    self.message = Some((frame.opcode, frame.payload));
+                }
+            }
+            OPCODE_CONTINUATION => {
+                let (opcode, mut data) = self
+                    .message
+                    .take()
+                    .ok_or_else(|| anyhow!("WebSocket continuation without a message"))?;
+                if data.len() + frame.payload.len() > self.max_message_size {
+                    return Err(Error::msg(MessageTooBig));
+                }
+                data.extend_from_slice(&frame.payload);
+                if frame.fin {
+                    self.push_message(opcode, data, events)?;
+                } else {
+                    self.message = Some((opcode, data));
+                }
+            }
+            opcode => bail!("Unknown WebSocket opcode {:#x}", opcode),
+        }
+        Ok(None)
+    }
+
+    fn push_message(
+        &mut self,
+        opcode: u8,
+        data: Vec<u8>,
+        events: &mut Vec<WebSocketEvent>,
+    ) -> Result<()> {
+        if opcode == OPCODE_TEXT {
+            let text = String::from_utf8(data)
+                .map_err(|_| anyhow!("WebSocket text message is not UTF-8"))?;
+            events.push(WebSocketEvent::Text(text));
+        } else {
+            events.push(WebSocketEvent::Binary(data));
+        }
+        Ok(())
+    }
+}
+
+fn now_ms() -> usize {
+    System::get().get_current_time_milliseconds().unwrap_or(0)
+}
+
+fn check_handshake_response(response: &str, accept_key: &str) -> Result<()> {
+    let mut lines = response.split("\r\n");
+    let status_line = lines.next().unwrap_or("");
+    let status = status_line.split(' ').nth(1).unwrap_or("");
+    ensure!(
+        status == "101",
+        "WebSocket upgrade refused: {}",
+        status_line
+    );
+    let mut upgraded = false;
+    let mut accepted = false;
+    for line in lines {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("upgrade") {
+            upgraded = value.eq_ignore_ascii_case("websocket");
+        } else if name.eq_ignore_ascii_case("sec-websocket-accept") {
+            accepted = value == accept_key;
+        }
+    }
+    ensure!(
+        upgraded,
+        "WebSocket upgrade response missing Upgrade header"
+    );
+    ensure!(
+        accepted,
+        "WebSocket upgrade response has the wrong accept key"
+    );
+    Ok(())
+}
+
+struct Frame {
+    fin: bool,
+    opcode: u8,
+    payload: Vec<u8>,
+}
+
+/// Encodes a client frame; client frames are always masked.
+fn encode_frame(opcode: u8, payload: &[u8], mask: [u8; 4]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(payload.len() + 14);
+    frame.push(0x80 | opcode);
+    match payload.len() {
+        len @ 0..=125 => frame.push(0x80 | len as u8),
+        len @ 126..=0xffff => {
+            frame.push(0x80 | 126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(0x80 | 127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(&mask);
+    frame.extend(
+        payload
+            .iter()
+            .enumerate()
+            .map(|(i, byte)| byte ^ mask[i % 4]),
+    );
+    frame
+}
+
+/// Decodes one frame from the start of `data`, returning it and the bytes used, or None if
+/// `data` doesn't hold a whole frame yet.
+fn decode_frame(data: &[u8], max_size: usize) -> Result<Option<(Frame, usize)>> {
+    if data.len() < 2 {
+        return Ok(None);
+    }
+    let fin = data[0] & 0x80 != 0;
+    ensure!(data[0] & 0x70 == 0, "WebSocket frame uses reserved bits");
+    let opcode = data[0] & 0x0f;
+    let masked = data[1] & 0x80 != 0;
+    let mut offset = 2;
+    let len = match data[1] & 0x7f {
+        126 => {
+            let Some(bytes) = data.get(2..4) else {
+                return Ok(None);
+            };
+            offset = 4;
+            u16::from_be_bytes([bytes[0], bytes[1]]) as u64
+        }
+        127 => {
+            let Some(bytes) = data.get(2..10) else {
+                return Ok(None);
+            };
+            offset = 10;
+            let mut len = [0u8; 8];
+            len.copy_from_slice(bytes);
+            u64::from_be_bytes(len)
+        }
+        len => len as u64,
+    };
+    if opcode & 0x8 != 0 {
+        ensure!(fin && len <= 125, "Invalid WebSocket control frame");
+    }
+    if len > max_size as u64 {
+        return Err(Error::msg(MessageTooBig));
+    }
+    let len = len as usize;
+    // Servers shouldn't mask frames, but unmask them if they do.
+    let mask = if masked {
+        let Some(mask) = data.get(offset..offset + 4) else {
+            return Ok(None);
+        };
+        offset += 4;
+        Some([mask[0], mask[1], mask[2], mask[3]])
+    } else {
+        None
+    };
+    let Some(payload) = data.get(offset..offset + len) else {
+        return Ok(None);
+    };
+    let payload = match mask {
+        Some(mask) => payload
+            .iter()
+            .enumerate()
+            .map(|(i, byte)| byte ^ mask[i % 4])
+            .collect(),
+        None => payload.to_vec(),
+    };
+    Ok(Some((
+        Frame {
+            fin,
+            opcode,
+            payload,
+        },
+        offset + len,
+    )))
+}
+
+fn find_subslice(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn accept_key(key: &str) -> String {
+    let mut input = Vec::with_capacity(key.len() + HANDSHAKE_GUID.len());
+    input.extend_from_slice(key.as_bytes());
+    input.extend_from_slice(HANDSHAKE_GUID.as_bytes());
+    base64_encode(&sha1(&input))
+}
+
+fn base64_encode(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        out.push(ALPHABET[(n >> 18) as usize & 63] as char);
+        out.push(ALPHABET[(n >> 12) as usize & 63] as char);
+        out.push(if chunk.len() > 1 {
+            ALPHABET[(n >> 6) as usize & 63] as char
+        } else {
+            '='
+        });
+        out.push(if chunk.len() > 2 {
+            ALPHABET[n as usize & 63] as char
+        } else {
+            '='
+        });
+    }
+    out
+}
+
+// Only used for the handshake's accept key, which isn't a security feature.
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+
+    for block in message.chunks(64) {
+        let mut w = [0u32; 80];
+        for (i, word) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5a827999),
+                20..=39 => (b ^ c ^ d, 0x6ed9eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+                _ => (b ^ c ^ d, 0xca62c1d6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (h, v) in h.iter_mut().zip([a, b, c, d, e]) {
+            *h = h.wrapping_add(v);
+        }
+    }
+
+    let mut out = [0u8; 20];
+    for (chunk, word) in out.chunks_mut(4).zip(h) {
+        chunk.copy_from_slice(&word.to_be_bytes());
+    }
+    out
+}
+
+// A xorshift generator for handshake keys and frame masks.  Masking only needs to be
+// unpredictable to the page, not cryptographically strong.
+struct Rng(u32);
+
+impl Rng {
+    fn seeded() -> Self {
+        let system = System::get();
+        let (seconds, millis) = system.get_seconds_since_epoch().unwrap_or((0, 0));
+        let seed = (seconds as u32) ^ (millis as u32).rotate_left(16) ^ now_ms() as u32;
+        Self(seed | 1)
+    }
+
+    fn next(&mut self) -> u32 {
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.0 = x;
+        x
+    }
+
+    fn fill(&mut self, bytes: &mut [u8]) {
+        for chunk in bytes.chunks_mut(4) {
+            let value = self.next().to_le_bytes();
+            chunk.copy_from_slice(&value[..chunk.len()]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use {super::*, alloc::collections::VecDeque};
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+    }
+
+    fn test_state() -> SocketState {
+        SocketState {
+            connection: None,
+            state: WebSocketState::Open,
+            handshake: None,
+            accept_key: String::new(),
+            buffer: Vec::new(),
+            outgoing: Vec::new(),
+            message: None,
+            max_message_size: 16,
+            rng: Rng(1),
+            clock: || 0,
+            close_sent_at: None,
+            close_received: None,
+        }
+    }
+
+    // An unmasked frame, as a server sends.
+    fn server_frame(fin: bool, opcode: u8, payload: &[u8]) -> Frame {
+        Frame {
+            fin,
+            opcode,
+            payload: payload.to_vec(),
+        }
+    }
+
+    #[test]
+    fn sha1_matches_known_digests() {
+        assert_eq!(hex(&sha1(b"")), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+        assert_eq!(
+            hex(&sha1(b"abc")),
+            "a9993e364706816aba3e25717850c26c9cd0d89d"
+        );
+        // Long enough to need a second block for the length.
+        assert_eq!(
+            hex(&sha1(
+                b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"
+            )),
+            "84983e441c3bd26ebaae4aa1f95129e5e54670f1"
+        );
+    }
+
+    #[test]
+    fn base64_pads() {
+        assert_eq!(base64_encode(b""), "");
+        assert_eq!(base64_encode(b"f"), "Zg==");
+        assert_eq!(base64_encode(b"fo"), "Zm8=");
+        assert_eq!(base64_encode(b"foo"), "Zm9v");
+        assert_eq!(base64_encode(b"foobar"), "Zm9vYmFy");
+        assert_eq!(base64_encode(&[0xfb, 0xff]), "+/8=");
+    }
+
+    #[test]
+    fn accept_key_matches_rfc_example() {
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn checks_handshake_response() {
+        let accept = "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=";
+        let response = format!(
+            "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\n\
+             Connection: Upgrade\r\nSec-WebSocket-Accept: {accept}"
+        );
+        assert!(check_handshake_response(&response, accept).is_ok());
+        assert!(check_handshake_response(&response, "wrong").is_err());
+        assert!(check_handshake_response("HTTP/1.1 200 OK", accept).is_err());
+    }
+
+    #[test]
+    fn encodes_masked_frames() {
+        let frame = encode_frame(OPCODE_TEXT, b"Hello", [0x37, 0xfa, 0x21, 0x3d]);
+        // The masked "Hello" example from RFC 6455 section 5.7.
+        assert_eq!(
+            frame,
+            [0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58]
+        );
+        let (decoded, used) = decode_frame(&frame, 1024).unwrap().unwrap();
+        assert_eq!(used, frame.len());
+        assert!(decoded.fin);
+        assert_eq!(decoded.opcode, OPCODE_TEXT);
+        assert_eq!(decoded.payload, b"Hello");
+    }
+
+    #[test]
+    fn encodes_extended_lengths() {
+        let medium = vec![7u8; 300];
+        let frame = encode_frame(OPCODE_BINARY, &medium, [1, 2, 3, 4]);
+        assert_eq!(&frame[1..4], &[0x80 | 126, 0x01, 0x2c]);
+        assert_eq!(
+            decode_frame(&frame, 1024).unwrap().unwrap().0.payload,
+            medium
+        );
+
+        let large = vec![9u8; 70_000];
+        let frame = encode_frame(OPCODE_BINARY, &large, [5, 6, 7, 8]);
+        assert_eq!(frame[1], 0x80 | 127);
+        assert_eq!(&frame[2..10], &70_000u64.to_be_bytes());
+        assert_eq!(
+            decode_frame(&frame, 100_000).unwrap().unwrap().0.payload,
+            large
+        );
+    }
+
+    #[test]
+    fn decodes_unmasked_frames_and_waits_for_more() {
+        let frame = [0x82, 0x03, 1, 2, 3, 0x89];
+        let (decoded, used) = decode_frame(&frame, 1024).unwrap().unwrap();
+        assert_eq!(used, 5);
+        assert_eq!(decoded.payload, [1, 2, 3]);
+        for len in 0..5 {
+            assert!(decode_frame(&frame[..len], 1024).unwrap().is_none());
+        }
+        assert!(decode_frame(&[0x82, 126, 0x01], 1024).unwrap().is_none());
+    }
+
+    #[test]
+    fn rejects_invalid_frames() {
+        // Reserved bits.
+        assert!(decode_frame(&[0xc1, 0x00], 1024).is_err());
+        // Fragmented control frame.
+        assert!(decode_frame(&[0x09, 0x00], 1024).is_err());
+        // Control frame over 125 bytes.
+        assert!(decode_frame(&[0x89, 126, 0x00, 0x7e], 1024).is_err());
+        // Over the size limit.
+        let err = decode_frame(&[0x82, 0x05, 0, 0, 0, 0, 0], 4).err().unwrap();
+        assert!(err.is::<MessageTooBig>());
+    }
+
+    #[test]
+    fn reassembles_fragmented_messages() {
+        let mut state = test_state();
+        let mut events = Vec::new();
+        state
+            .handle_frame(server_frame(false, OPCODE_TEXT, b"Hel"), &mut events)
+            .unwrap();
+        state
+            .handle_frame(
+                server_frame(false, OPCODE_CONTINUATION, b"lo, "),
+                &mut events,
+            )
+            .unwrap();
+        assert!(events.is_empty());
+        state
+            .handle_frame(server_frame(true, OPCODE_CONTINUATION, b"you"), &mut events)
+            .unwrap();
+        match events.as_slice() {
+            [WebSocketEvent::Text(text)] => assert_eq!(text, "Hello, you"),
+            events => panic!("unexpected events {:?}", events),
+        }
+    }
+
+    #[test]
+    fn rejects_bad_fragmentation() {
+        let mut events = Vec::new();
+        let mut state = test_state();
+        assert!(state
+            .handle_frame(server_frame(true, OPCODE_CONTINUATION, b"x"), &mut events)
+            .is_err());
+
+        let mut state = test_state();
+        state
+            .handle_frame(server_frame(false, OPCODE_BINARY, b"a"), &mut events)
+            .unwrap();
+        assert!(state
+            .handle_frame(server_frame(true, OPCODE_TEXT, b"b"), &mut events)
+            .is_err());
+
+        let mut state = test_state();
+        state
+            .handle_frame(server_frame(false, OPCODE_BINARY, &[0; 10]), &mut events)
+            .unwrap();
+        let err = state
+            .handle_frame(
+                server_frame(true, OPCODE_CONTINUATION, &[0; 10]),
+                &mut events,
+            )
+            .err()
+            .unwrap();
+        assert!(err.is::<MessageTooBig>());
+    }
+
+    #[test]
+    fn delivers_pongs_and_binary() {
+        let mut state = test_state();
+        let mut events = Vec::new();
+        state
+            .handle_frame(server_frame(true, OPCODE_PONG, b"p"), &mut events)
+            .unwrap();
+        state
+            .handle_frame(server_frame(true, OPCODE_BINARY, &[1, 2]), &mut events)
+            .unwrap();
+        match events.as_slice() {
+            [WebSocketEvent::Pong(pong), WebSocketEvent::Binary(data)] => {
+                assert_eq!(pong, b"p");
+                assert_eq!(data, &[1, 2]);
+            }
+            events => panic!("unexpected events {:?}", events),
+        }
+    }
+
+    std::thread_local! {
+        static NOW_MS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    fn test_clock() -> usize {
+        NOW_MS.with(Cell::get)
+    }
+
+    fn advance_clock(ms: usize) {
+        NOW_MS.with(|now| now.set(now.get() + ms));
+    }
+
+    // A local stand-in for an echo server: it answers the handshake, echoes data frames,
+    // answers pings and echoes a close before dropping the connection.  It takes a few bytes
+    // per write, so the socket has to queue.
+    struct EchoServer {
+        shared: Rc<Shared>,
+        from_client: RefCell<Vec<u8>>,
+        to_client: RefCell<VecDeque<u8>>,
+        handshake_done: Cell<bool>,
+        // Whether to keep the connection open after echoing a close.
+        lingers: bool,
+        received: RefCell<Vec<(u8, Vec<u8>)>>,
+        sent_close: Cell<bool>,
+        closed_by_client: Cell<bool>,
+    }
+
+    impl Connection for Rc<EchoServer> {
+        fn bytes_available(&self) -> Result<usize> {
+            Ok(self.to_client.borrow().len())
+        }
+
+        fn read(&self, buffer: &mut [u8]) -> Result<usize> {
+            let mut incoming = self.to_client.borrow_mut();
+            let count = buffer.len().min(incoming.len());
+            for (byte, value) in buffer.iter_mut().zip(incoming.drain(..count)) {
+                *byte = value;
+            }
+            Ok(count)
+        }
+
+        fn write_available(&self, data: &[u8]) -> Result<usize> {
+            let count = data.len().min(7);
+            self.from_client
+                .borrow_mut()
+                .extend_from_slice(&data[..count]);
+            Ok(count)
+        }
+
+        fn close(&self) {
+            self.closed_by_client.set(true);
+        }
+    }
+
+    impl EchoServer {
+        fn new(shared: Rc<Shared>, lingers: bool) -> Rc<Self> {
+            Rc::new(Self {
+                shared,
+                from_client: RefCell::new(Vec::new()),
+                to_client: RefCell::new(VecDeque::new()),
+                handshake_done: Cell::new(false),
+                lingers,
+                received: RefCell::new(Vec::new()),
+                sent_close: Cell::new(false),
+                closed_by_client: Cell::new(false),
+            })
+        }
+
+        // Sends an unmasked frame with a short payload.
+        fn send(&self, opcode: u8, payload: &[u8]) {
+            self.sent_close
+                .set(self.sent_close.get() || opcode == OPCODE_CLOSE);
+            let mut to_client = self.to_client.borrow_mut();
+            to_client.extend([0x80 | opcode, payload.len() as u8]);
+            to_client.extend(payload);
+        }
+
+        fn serve(&self) {
+            let mut input = self.from_client.borrow_mut();
+            if !self.handshake_done.get() {
+                let Some(end) = find_subslice(&input, b"\r\n\r\n") else {
+                    return;
+                };
+                let request = String::from_utf8(input.drain(..end + 4).collect()).unwrap();
+                assert!(request.starts_with("GET /chat HTTP/1.1\r\n"));
+                let key = request
+                    .split("\r\n")
+                    .find_map(|line| line.strip_prefix("Sec-WebSocket-Key: "))
+                    .unwrap();
+                let response = format!(
+                    "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\n\
+                     Connection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+                    accept_key(key)
+                );
+                self.to_client.borrow_mut().extend(response.as_bytes());
+                self.handshake_done.set(true);
+            }
+            while let Some((frame, used)) = decode_frame(&input, 1024).unwrap() {
+                assert!(input[1] & 0x80 != 0, "client frames must be masked");
+                input.drain(..used);
+                match frame.opcode {
+                    OPCODE_PING => self.send(OPCODE_PONG, &frame.payload),
+                    OPCODE_PONG => {}
+                    OPCODE_CLOSE => {
+                        if !self.sent_close.get() {
+                            self.send(OPCODE_CLOSE, &frame.payload);
+                        }
+                        if !self.lingers {
+                            self.shared.tcp_closed.set(Some(PDNetErr::NET_OK));
+                        }
+                    }
+                    opcode => self.send(opcode, &frame.payload),
+                }
+                self.received
+                    .borrow_mut()
+                    .push((frame.opcode, frame.payload));
+            }
+        }
+    }
+
+    fn describe(event: WebSocketEvent) -> String {
+        match event {
+            WebSocketEvent::Open => "open".into(),
+            WebSocketEvent::Text(text) => format!("text {}", text),
+            WebSocketEvent::Binary(data) => format!("binary {:?}", data),
+            WebSocketEvent::Pong(data) => format!("pong {:?}", data),
+            WebSocketEvent::Error(err) => format!("error {}", err),
+            WebSocketEvent::Closed {
+                code,
+                reason,
+                clean,
+            } => format!("closed {:?} {:?} {}", code, reason, clean),
+        }
+    }
+
+    fn echo_socket(lingers: bool) -> (WebSocket, Rc<EchoServer>, Rc<RefCell<Vec<String>>>) {
+        let shared = Rc::new(Shared::default());
+        let server = EchoServer::new(shared.clone(), lingers);
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let socket_events = events.clone();
+        let socket = WebSocket::builder("ws://example.com/chat").unwrap().socket(
+            Box::new(server.clone()),
+            shared,
+            Rng(1),
+            test_clock,
+            Box::new(move |_, event| socket_events.borrow_mut().push(describe(event))),
+        );
+        (socket, server, events)
+    }
+
+    // Polls the socket and lets the server answer, for a few frames.
+    fn run_frames(socket: &WebSocket, server: &EchoServer) -> bool {
+        let mut running = true;
+        for _ in 0..100 {
+            running = socket.poll();
+            server.serve();
+            if !running {
+                break;
+            }
+        }
+        running
+    }
+
+    fn take(events: &RefCell<Vec<String>>) -> Vec<String> {
+        core::mem::take(&mut *events.borrow_mut())
+    }
+
+    #[test]
+    fn talks_to_an_echo_server() {
+        let (socket, server, events) = echo_socket(false);
+        assert_eq!(socket.state(), WebSocketState::Connecting);
+        assert!(socket.send_text("too early").is_err());
+        assert!(run_frames(&socket, &server));
+        assert!(
+            take(&events).is_empty(),
+            "nothing happens until the TCP open"
+        );
+
+        socket.shared.opened.set(Some(PDNetErr::NET_OK));
+        assert!(run_frames(&socket, &server));
+        assert_eq!(take(&events), ["open"]);
+        assert_eq!(socket.state(), WebSocketState::Open);
+
+        socket.send_text("hello, echo").unwrap();
+        socket.send_binary(&[1, 2, 3]).unwrap();
+        socket.ping(b"are you there").unwrap();
+        assert!(run_frames(&socket, &server));
+        assert_eq!(
+            take(&events),
+            [
+                "text hello, echo",
+                "binary [1, 2, 3]",
+                "pong [97, 114, 101, 32, 121, 111, 117, 32, 116, 104, 101, 114, 101]",
+            ]
+        );
+
+        // Pings from the server are answered without an event.
+        server.send(OPCODE_PING, b"hi");
+        assert!(run_frames(&socket, &server));
+        assert!(take(&events).is_empty());
+        assert_eq!(
+            server.received.borrow().last(),
+            Some(&(OPCODE_PONG, b"hi".to_vec()))
+        );
+
+        socket.close(CLOSE_NORMAL, "bye").unwrap();
+        assert_eq!(socket.state(), WebSocketState::Closing);
+        assert!(!run_frames(&socket, &server));
+        assert_eq!(take(&events), ["closed Some(1000) \"bye\" true"]);
+        assert_eq!(socket.state(), WebSocketState::Closed);
+        assert!(server.closed_by_client.get());
+    }
+
+    #[test]
+    fn server_close_is_reported_after_timeout() {
+        let (socket, server, events) = echo_socket(true);
+        socket.shared.opened.set(Some(PDNetErr::NET_OK));
+        run_frames(&socket, &server);
+        assert_eq!(take(&events), ["open"]);
+
+        let mut payload = 1001u16.to_be_bytes().to_vec();
+        payload.extend_from_slice(b"going away");
+        server.send(OPCODE_CLOSE, &payload);
+        assert!(run_frames(&socket, &server));
+        assert_eq!(socket.state(), WebSocketState::Closing);
+        // The close was echoed, but the server keeps the connection open.
+        assert_eq!(
+            server.received.borrow().last(),
+            Some(&(OPCODE_CLOSE, 1001u16.to_be_bytes().to_vec()))
+        );
+        assert!(take(&events).is_empty());
+
+        advance_clock(CLOSE_TIMEOUT_MS);
+        assert!(!run_frames(&socket, &server));
+        assert_eq!(take(&events), ["closed Some(1001) \"going away\" true"]);
+        assert!(server.closed_by_client.get());
+    }
+
+    #[test]
+    fn connection_failure_closes_uncleanly() {
+        let (socket, server, events) = echo_socket(false);
+        socket
+            .shared
+            .opened
+            .set(Some(PDNetErr::NET_NOT_CONNECTED_TO_AP));
+        assert!(!run_frames(&socket, &server));
+        let events = take(&events);
+        assert_eq!(events.len(), 2);
+        assert!(events[0].starts_with("error WebSocket connection failed"));
+        assert_eq!(events[1], "closed None \"\" false");
+    }
+}
@@ -0,0 +1,489 @@
+//! A stack of scenes, such as a title screen, a level and a pause menu, with animated
+//! transitions between them.
+//!
+//! Keep a `SceneStack` in your `Game` and call its `update` from `Game::update`.  Only the top
+//! scene is updated.  Scenes register their sprites and timers with their `SceneContext`; when
+//! a scene is covered by another, its sprites leave the display list and its timers pause, and
+//! when it exits they're removed for good.
+//!
+//! Transitions capture the outgoing frame and draw it over the incoming scene as a sprite at
+//! the top of the z-order, fading it out with a dither pattern, wiping it away or sliding it off
+//! the screen.  Games that don't use sprites can call `SceneStack::draw_transition` after
+//! drawing instead.
+
+use {
+    crate::{
+        easing::{Curve, Easing},
+        geometry::ScreenRect,
+        graphics::{
+            Bitmap, Graphics, LCDBitmapFlip, LCDColor, LCDSolidColor, Pattern, LCD_COLUMNS,
+            LCD_ROWS,
+        },
+        sprite::{Sprite, SpriteManager},
+        system::System,
+        timer::TimerHandle,
+    },
+    alloc::{boxed::Box, vec::Vec},
+    anyhow::Error,
+    euclid::{point2, size2},
+};
+
+pub trait Scene {
+    /// Called when the scene is pushed, or replaces another, before its first `update`.
+    fn enter(&mut self, context: &mut SceneContext) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Called before the scene is popped or replaced.  Its sprites and timers are removed
+    /// afterwards.
+    fn exit(&mut self, context: &mut SceneContext) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Called when another scene is pushed on top of this one.
+    fn suspend(&mut self, context: &mut SceneContext) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Called when the scene on top of this one is popped.
+    fn resume(&mut self, context: &mut SceneContext) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Called once a frame while this is the top scene.
+    fn update(&mut self, context: &mut SceneContext) -> Result<(), Error>;
+}
+
+/// The direction a wipe or slide moves in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransitionKind {
+    /// Switches immediately.
+    Cut,
+    /// Dissolves the outgoing frame with an ordered dither.
+    Fade,
+    /// Reveals the incoming scene behind an edge moving across the screen.
+    Wipe(Direction),
+    /// Slides the outgoing frame off the screen.
+    Slide(Direction),
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Transition {
+    pub kind: TransitionKind,
+    pub duration_ms: usize,
+    pub easing: Easing,
+}
+
+impl Transition {
+    pub const CUT: Transition = Transition {
+        kind: TransitionKind::Cut,
+        duration_ms: 0,
+        easing: Easing::Linear,
+    };
+
+    pub fn fade(duration_ms: usize) -> Self {
+        Self::new(TransitionKind::Fade, duration_ms)
+    }
+
+    pub fn wipe(direction: Direction, duration_ms: usize) -> Self {
+        Self::new(TransitionKind::Wipe(direction), duration_ms)
+    }
+
+    pub fn slide(direction: Direction, duration_ms: usize) -> Self {
+        Self::new(TransitionKind::Slide(direction), duration_ms)
+    }
+
+    /// A transition of `kind`, easing in and out.
+    pub fn new(kind: TransitionKind, duration_ms: usize) -> Self {
+        Self {
+            kind,
+            duration_ms,
+            easing: Easing::InOut(Curve::Quad),
+        }
+    }
+
+    pub fn with_easing(mut self, easing: Easing) -> Self {
+        self.easing = easing;
+        self
+    }
+}
+
+enum SceneCommand {
+    Push(Box<dyn Scene>, Transition),
+    Pop(Transition),
+    Replace(Box<dyn Scene>, Transition),
+}
+
+/// What a scene owns, and a way for it to change scenes from its `update`.
+#[derive(Default)]
+pub struct SceneContext {
+    sprites: Vec<Sprite>,
+    timers: Vec<TimerHandle>,
+    command: Option<SceneCommand>,
+    transitioning: bool,
+}
+
+impl SceneContext {
+    /// Adds `sprite` to the display list, owned by this scene.
+    pub fn add_sprite(&mut self, sprite: &Sprite) -> Result<(), Error> {
+        SpriteManager::get_mut().add_sprite(sprite)?;
+        self.own_sprite(sprite);
+        Ok(())
+    }
+
+    /// Takes ownership of a sprite that's already in the display list, like the ones
+    /// `TextSprite` and the other helpers create.
+    pub fn own_sprite(&mut self, sprite: &Sprite) {
+        if !self.sprites.contains(sprite) {
+            self.sprites.push(sprite.clone());
+        }
+    }
+
+    /// Removes `sprite` from the display list and from this scene.
+    pub fn remove_sprite(&mut self, sprite: &Sprite) -> Result<(), Error> {
+        self.sprites.retain(|owned| owned != sprite);
+        SpriteManager::get_mut().remove_sprite(sprite)
+    }
+
+    /// Makes this scene responsible for `timer`, pausing it while the scene is covered and
+    /// removing it when the scene exits.
+    pub fn add_timer(&mut self, timer: TimerHandle) -> TimerHandle {
+        self.timers.retain(|timer| !timer.is_finished());
+        self.timers.push(timer.clone());
+        timer
+    }
+
+    /// Whether a transition to or from this scene is still playing.
+    pub fn is_transitioning(&self) -> bool {
+        self.transitioning
+    }
+
+    /// Pushes `scene` after this update.
+    pub fn push<S>(&mut self, scene: S, transition: Transition)
+    where
+        S: Scene + 'static,
+    {
+        self.command = Some(SceneCommand::Push(Box::new(scene), transition));
+    }
+
+    /// Pops this scene after this update.
+    pub fn pop(&mut self, transition: Transition) {
+        self.command = Some(SceneCommand::Pop(transition));
+    }
+
+    /// Replaces this scene with `scene` after this update.
+    pub fn replace<S>(&mut self, scene: S, transition: Transition)
+    where
+        S: Scene + 'static,
+    {
+        self.command = Some(SceneCommand::Replace(Box::new(scene), transition));
+    }
+
+    fn suspend(&mut self) -> Result<(), Error> {
+        let sprite_manager = SpriteManager::get_mut();
+        for sprite in &self.sprites {
+            sprite_manager.remove_sprite(sprite)?;
+        }
+        self.timers.iter().for_each(TimerHandle::pause);
+        Ok(())
+    }
+
+    fn resume(&mut self) -> Result<(), Error> {
+        let sprite_manager = SpriteManager::get_mut();
+        for sprite in &self.sprites {
+            sprite_manager.add_sprite(sprite)?;
+        }
+        self.timers.iter().for_each(TimerHandle::resume);
+        Ok(())
+    }
+
+    fn clear(&mut self) -> Result<(), Error> {
+        let sprite_manager = SpriteManager::get_mut();
+        for sprite in self.sprites.drain(..) {
+            sprite_manager.remove_sprite(&sprite)?;
+        }
+        self.timers.drain(..).for_each(|timer| timer.remove());
+        Ok(())
+    }
+}
+
+struct SceneEntry {
+    scene: Box<dyn Scene>,
+    context: SceneContext,
+}
+
+struct ActiveTransition {
+    transition: Transition,
+    // The outgoing frame, whose mask is redrawn each frame for fades and wipes.
+    snapshot: Bitmap,
+    mask: Bitmap,
+    sprite: Sprite,
+    start_ms: Option<usize>,
+    progress: f32,
+}
+
+impl ActiveTransition {
+    fn new(transition: Transition) -> Result<Self, Error> {
+        let graphics = Graphics::get();
+        // Sprites are drawn after `Game::update`, so the framebuffer still shows the last frame.
+        let snapshot = graphics.get_framebuffer_bitmap()?;
+        let screen = size2(LCD_COLUMNS as i32, LCD_ROWS as i32);
+        snapshot.set_mask(Some(
+            graphics.new_bitmap(screen, LCDColor::Solid(LCDSolidColor::kColorWhite))?,
+        ))?;
+        let mask = snapshot.get_mask()?;
+
+        let sprite_manager = SpriteManager::get_mut();
+        let mut sprite = sprite_manager.new_sprite()?;
+        sprite.set_image(snapshot.clone(), LCDBitmapFlip::kBitmapUnflipped)?;
+        sprite.set_z_index(i16::MAX)?;
+        sprite.move_to(LCD_COLUMNS as f32 / 2.0, LCD_ROWS as f32 / 2.0)?;
+        sprite_manager.add_sprite(&sprite)?;
+
+        Ok(Self {
+            transition,
+            snapshot,
+            mask,
+            sprite,
+            start_ms: None,
+            progress: 0.0,
+        })
+    }
+
+    // Returns whether the transition has finished.
+    fn update(&mut self) -> Result<bool, Error> {
+        let now = System::get().get_current_time_milliseconds()?;
+        let elapsed = now - *self.start_ms.get_or_insert(now);
+        let t = if self.transition.duration_ms == 0 {
+            1.0
+        } else {
+            elapsed as f32 / self.transition.duration_ms as f32
+        };
+        self.progress = self.transition.easing.apply(t);
+        self.apply()?;
+        Ok(t >= 1.0)
+    }
+
+    fn apply(&mut self) -> Result<(), Error> {
+        let graphics = Graphics::get();
+        let width = LCD_COLUMNS as i32;
+        let height = LCD_ROWS as i32;
+        let across = |length: i32| (length as f32 * self.progress) as i32;
+        match self.transition.kind {
+            TransitionKind::Cut => {}
+            TransitionKind::Fade => {
+                let opacity = (100.0 * (1.0 - self.progress)).clamp(0.0, 100.0) as u8;
+                graphics.with_context(Some(&self.mask), || {
+                    graphics.fill_rect(
+                        ScreenRect::new(point2(0, 0), size2(width, height)),
+                        LCDColor::Pattern(Pattern::gray(opacity)),
+                    )
+                })?;
+                self.sprite.mark_dirty()?;
+            }
+            TransitionKind::Wipe(direction) => {
+                let revealed = match direction {
+                    Direction::Left => (width - across(width), 0, across(width), height),
+                    Direction::Right => (0, 0, across(width), height),
+                    Direction::Up => (0, height - across(height), width, across(height)),
+                    Direction::Down => (0, 0, width, across(height)),
+                };
+                self.mask
+                    .clear(LCDColor::Solid(LCDSolidColor::kColorWhite))?;
+                graphics.with_context(Some(&self.mask), || {
+                    graphics.fill_rect(
+                        ScreenRect::new(
+                            point2(revealed.0, revealed.1),
+                            size2(revealed.2, revealed.3),
+                        ),
+                        LCDColor::Solid(LCDSolidColor::kColorBlack),
+                    )
+                })?;
+                self.sprite.mark_dirty()?;
+            }
+            TransitionKind::Slide(direction) => {
+                let (dx, dy) = match direction {
+                    Direction::Left => (-across(width), 0),
+                    Direction::Right => (across(width), 0),
+                    Direction::Up => (0, -across(height)),
+                    Direction::Down => (0, across(height)),
+                };
+                self.sprite
+                    .move_to((width / 2 + dx) as f32, (height / 2 + dy) as f32)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn draw(&self) -> Result<(), Error> {
+        let (x, y) = self.sprite.get_position()?;
+        let location = point2(
+            x as i32 - LCD_COLUMNS as i32 / 2,
+            y as i32 - LCD_ROWS as i32 / 2,
+        );
+        self.snapshot
+            .draw(location, LCDBitmapFlip::kBitmapUnflipped)
+    }
+
+    fn finish(self) -> Result<(), Error> {
+        SpriteManager::get_mut().remove_sprite(&self.sprite)
+    }
+}
+
+#[derive(Default)]
+pub struct SceneStack {
+    entries: Vec<SceneEntry>,
+    transition: Option<ActiveTransition>,
+}
+
+impl SceneStack {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn is_transitioning(&self) -> bool {
+        self.transition.is_some()
+    }
+
+    /// Covers the current scene with `scene`.
+    pub fn push<S>(&mut self, scene: S, transition: Transition) -> Result<(), Error>
+    where
+        S: Scene + 'static,
+    {
+        self.push_boxed(Box::new(scene), transition)
+    }
+
+    fn push_boxed(&mut self, scene: Box<dyn Scene>, transition: Transition) -> Result<(), Error> {
+        self.start_transition(transition)?;
+        if let Some(top) = self.entries.last_mut() {
+            top.scene.suspend(&mut top.context)?;
+            top.context.suspend()?;
+        }
+        self.enter(scene)
+    }
+
+    /// Removes the current scene, uncovering the one below, and returns it.
+    pub fn pop(&mut self, transition: Transition) -> Result<Option<Box<dyn Scene>>, Error> {
+        if self.entries.is_empty() {
+            return Ok(None);
+        }
+        self.start_transition(transition)?;
+        let scene = self.exit()?;
+        if let Some(top) = self.entries.last_mut() {
+            top.context.resume()?;
+            top.scene.resume(&mut top.context)?;
+        }
+        Ok(scene)
+    }
+
+    /// Swaps the current scene for `scene`, and returns the old one.
+    pub fn replace<S>(
+        &mut self,
+        scene: S,
+        transition: Transition,
+    ) -> Result<Option<Box<dyn Scene>>, Error>
+    where
+        S: Scene + 'static,
+    {
+        self.replace_boxed(Box::new(scene), transition)
+    }
+
+    fn replace_boxed(
+        &mut self,
+        scene: Box<dyn Scene>,
+        transition: Transition,
+    ) -> Result<Option<Box<dyn Scene>>, Error> {
+        self.start_transition(transition)?;
+        let old = self.exit()?;
+        self.enter(scene)?;
+        Ok(old)
+    }
+
+    /// Updates the top scene, carries out any scene change it asked for, and animates the
+    /// transition.  Call from `Game::update`.
+    pub fn update(&mut self) -> Result<(), Error> {
+        let transitioning = self.transition.is_some();
+        if let Some(top) = self.entries.last_mut() {
+            top.context.transitioning = transitioning;
+            top.scene.update(&mut top.context)?;
+            let command = top.context.command.take();
+            match command {
+                Some(SceneCommand::Push(scene, transition)) => {
+                    self.push_boxed(scene, transition)?
+                }
+                Some(SceneCommand::Pop(transition)) => {
+                    self.pop(transition)?;
+                }
+                Some(SceneCommand::Replace(scene, transition)) => {
+                    self.replace_boxed(scene, transition)?;
+                }
+                None => {}
+            }
+        }
+        if let Some(transition) = self.transition.as_mut() {
+            if transition.update()? {
+                self.finish_transition()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Draws the outgoing frame of a transition directly, for games that don't draw sprites.
+    pub fn draw_transition(&self) -> Result<(), Error> {
+        match &self.transition {
+            Some(transition) => transition.draw(),
+            None => Ok(()),
+        }
+    }
+
+    fn enter(&mut self, scene: Box<dyn Scene>) -> Result<(), Error> {
+        let mut entry = SceneEntry {
+            scene,
+            context: SceneContext::default(),
+        };
+        entry.context.transitioning = self.transition.is_some();
+        entry.scene.enter(&mut entry.context)?;
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    fn exit(&mut self) -> Result<Option<Box<dyn Scene>>, Error> {
+        let Some(mut entry) = self.entries.pop() else {
+            return Ok(None);
+        };
+        entry.scene.exit(&mut entry.context)?;
+        entry.context.clear()?;
+        Ok(Some(entry.scene))
+    }
+
+    fn start_transition(&mut self, transition: Transition) -> Result<(), Error> {
+        self.finish_transition()?;
+        if transition.kind != TransitionKind::Cut && transition.duration_ms > 0 {
+            self.transition = Some(ActiveTransition::new(transition)?);
+        }
+        Ok(())
+    }
+
+    fn finish_transition(&mut self) -> Result<(), Error> {
+        match self.transition.take() {
+            Some(transition) => transition.finish(),
+            None => Ok(()),
+        }
+    }
+}
@@ -20,26 +20,30 @@
 
 use crate::file::FileSystem;
 use crate::{pd_func_caller, pd_func_caller_log};
-use alloc::{format, vec};
+use alloc::{boxed::Box, format, rc::Rc, vec, vec::Vec};
 use core::marker::PhantomData;
 use crankstart_sys::LFOType;
 use crankstart_sys::{ctypes, SoundFormat};
+use synth::Signal;
 
 use anyhow::{anyhow, bail, ensure, Error, Result};
 use core::ptr;
 use cstr_core::CString;
 
 pub mod sampleplayer;
-pub use sampleplayer::{AudioSample, SamplePlayer};
+pub use sampleplayer::{AudioSample, AudioSampleData, SamplePlayer};
 pub mod fileplayer;
 pub use fileplayer::FilePlayer;
 pub mod synth;
 pub use synth::Synth;
-pub use synth::LFO;
+pub use synth::{ScaledSignal, LFO};
 pub mod effect;
 pub use effect::Overdrive;
 pub mod channel;
-pub use channel::SoundChannel;
+pub use channel::{ChannelLevelSignal, SoundChannel};
+pub mod music;
+pub mod wav;
+pub use music::{MusicPlayer, Track};
 
 // When the Playdate system struct is created, it passes the given playdate_sound to Sound::new,
 // which then replaces this.
@@ -47,6 +51,21 @@ static mut SOUND: Sound = Sound::null();
 
 static SAMPLES_PER_SECOND: u32 = 44100;
 
+type HeadphoneCallback = dyn FnMut(bool, bool) + 'static;
+static mut HEADPHONE_CALLBACK: Option<Box<HeadphoneCallback>> = None;
+
+extern "C" fn headphone_change_callback(headphone: ctypes::c_int, mic: ctypes::c_int) {
+    unsafe {
+        // Take the callback out while it runs so it can safely replace itself.
+        if let Some(mut callback) = HEADPHONE_CALLBACK.take() {
+            callback(headphone != 0, mic != 0);
+            if HEADPHONE_CALLBACK.is_none() {
+                HEADPHONE_CALLBACK = Some(callback);
+            }
+        }
+    }
+}
+
 /// `Sound` is the main interface to the Playdate audio subsystems.
 #[derive(Clone, Debug)]
 pub struct Sound {
@@ -64,10 +83,10 @@ pub struct Sound {
     raw_one_pole_filter: *const crankstart_sys::playdate_sound_effect_onepolefilter,
     raw_delay_line: *const crankstart_sys::playdate_sound_effect_delayline,
     raw_channel: *const crankstart_sys::playdate_sound_channel,
+    raw_signal: *const crankstart_sys::playdate_sound_signal,
 }
 
-// Not implemented: addSource, removeSource, setMicCallback, and getHeadphoneState (waiting on
-// crankstart callback strategy), getDefaultChannel, addChannel, removeChannel.
+// Not implemented: addSource, removeSource, and setMicCallback.
 impl Sound {
     const fn null() -> Self {
         Self {
@@ -82,6 +101,7 @@ impl Sound {
             raw_one_pole_filter: ptr::null(),
             raw_delay_line: ptr::null(),
             raw_channel: ptr::null(),
+            raw_signal: ptr::null(),
         }
     }
 
@@ -114,6 +134,8 @@ impl Sound {
         ensure!(!raw_delay_line.is_null(), "Null sound.effect_delayline");
         let raw_channel = unsafe { (*raw_sound).channel };
         ensure!(!raw_channel.is_null(), "Null sound.channel");
+        let raw_signal = unsafe { (*raw_sound).signal };
+        ensure!(!raw_signal.is_null(), "Null sound.signal");
 
         let sound = Self {
             raw_sound,
@@ -127,6 +149,7 @@ impl Sound {
             raw_one_pole_filter,
             raw_delay_line,
             raw_channel,
+            raw_signal,
         };
         unsafe { SOUND = sound };
         Ok(())
@@ -190,6 +213,15 @@ impl Sound {
         }
     }
 
+    /// Creates an `AudioSample` from the contents of a WAV file, e.g. one downloaded or
+    /// generated at runtime.  8 bit data is converted from WAV's unsigned samples to the signed
+    /// samples the Playdate expects, and ADPCM data is given its block size.
+    pub fn load_wav_from_data(&self, mut data: Vec<u8>) -> Result<AudioSample> {
+        let info = wav::parse_wav(&data)?;
+        let range = wav::prepare_sample_data(&mut data, &info)?;
+        AudioSample::from_data_range(data, range, info.format, info.sample_rate)
+    }
+
     /// Returns the sound engine's current time, in frames, 44.1k per second.
     pub fn get_current_time(&self) -> Result<ctypes::c_uint> {
         pd_func_caller!((*self.raw_sound).getCurrentTime)
@@ -205,6 +237,49 @@ impl Sound {
         )
     }
 
+    /// Returns whether headphones are plugged in, and whether they have a microphone.
+    pub fn get_headphone_state(&self) -> Result<(bool, bool)> {
+        // Passing a null callback would unregister any callback set with
+        // set_headphone_change_callback, so pass ours along if it's set.
+        let callback = unsafe { HEADPHONE_CALLBACK.as_ref() }.map(|_| {
+            headphone_change_callback as unsafe extern "C" fn(ctypes::c_int, ctypes::c_int)
+        });
+        self.get_headphone_state_internal(callback)
+    }
+
+    /// Calls `callback` with the headphone and microphone state whenever headphones are plugged
+    /// in or unplugged, and returns the current state.  Pass `None` to remove the callback.
+    ///
+    /// Note: while a callback is set, the system no longer switches between the speaker and
+    /// headphones automatically; use `set_outputs_active` from the callback to do so.
+    pub fn set_headphone_change_callback<F>(&self, callback: Option<F>) -> Result<(bool, bool)>
+    where
+        F: FnMut(bool, bool) + 'static,
+    {
+        let raw_callback = callback.as_ref().map(|_| {
+            headphone_change_callback as unsafe extern "C" fn(ctypes::c_int, ctypes::c_int)
+        });
+        unsafe {
+            HEADPHONE_CALLBACK = callback.map(|cb| Box::new(cb) as Box<HeadphoneCallback>);
+        }
+        self.get_headphone_state_internal(raw_callback)
+    }
+
+    fn get_headphone_state_internal(
+        &self,
+        callback: Option<unsafe extern "C" fn(ctypes::c_int, ctypes::c_int)>,
+    ) -> Result<(bool, bool)> {
+        let mut headphone = 0;
+        let mut mic = 0;
+        pd_func_caller!(
+            (*self.raw_sound).getHeadphoneState,
+            &mut headphone,
+            &mut mic,
+            callback
+        )?;
+        Ok((headphone != 0, mic != 0))
+    }
+
     pub fn new_synth(&self) -> Result<Synth> {
         crate::sound::Synth::new(self.raw_synth)
     }
@@ -213,6 +288,21 @@ impl Sound {
         crate::sound::LFO::new(self.raw_lfo, lfo_type)
     }
 
+    /// Wraps `signal` in a new signal whose value is `signal`'s value times `scale`, plus
+    /// `offset`; e.g. a scale of -0.5 and offset of 1 turns a channel's level signal into a
+    /// volume modulator that ducks to half volume.
+    pub fn new_scaled_signal<S: Signal>(
+        &self,
+        signal: S,
+        scale: f32,
+        offset: f32,
+    ) -> Result<ScaledSignal> {
+        let mut scaled = ScaledSignal::new(self.raw_signal, signal)?;
+        scaled.set_scale(scale)?;
+        scaled.set_offset(offset)?;
+        Ok(scaled)
+    }
+
     pub fn new_overdrive(&self) -> Result<Overdrive> {
         crate::sound::Overdrive::new(self.raw_sound_effect, self.raw_overdrive)
     }
@@ -233,6 +323,41 @@ impl Sound {
     pub fn new_channel(&self) -> Result<SoundChannel> {
         crate::sound::SoundChannel::new(self.raw_channel)
     }
+
+    /// Returns a handle to the default channel, where sounds play if they haven't been added to
+    /// another channel.  The channel itself belongs to the system and is never freed.
+    pub fn default_channel(&self) -> Result<SoundChannel> {
+        let raw_channel = pd_func_caller!((*self.raw_sound).getDefaultChannel)?;
+        ensure!(
+            !raw_channel.is_null(),
+            "Null returned from sound.getDefaultChannel"
+        );
+        SoundChannel::new_unowned(self.raw_channel, raw_channel)
+    }
+
+    /// Adds the channel to the mixer so its sources are heard.  The channel is removed from the
+    /// mixer automatically when it's dropped.
+    pub fn add_channel(&self, channel: &mut SoundChannel) -> Result<()> {
+        ensure!(!channel.is_in_mixer(), "Channel already added to the mixer");
+        let result = pd_func_caller!((*self.raw_sound).addChannel, channel.raw_channel())?;
+        ensure!(result != 0, "sound.addChannel failed");
+        channel.set_in_mixer(true);
+        Ok(())
+    }
+
+    /// Removes the channel from the mixer, silencing everything playing through it.
+    pub fn remove_channel(&self, channel: &mut SoundChannel) -> Result<()> {
+        ensure!(channel.is_in_mixer(), "Channel not in the mixer");
+        let result = pd_func_caller!((*self.raw_sound).removeChannel, channel.raw_channel())?;
+        ensure!(result != 0, "sound.removeChannel failed");
+        channel.set_in_mixer(false);
+        Ok(())
+    }
+
+    /// Internal: used by SoundChannel's Drop, so it can't fail.
+    pub(crate) fn remove_channel_internal(&self, raw_channel: *mut crankstart_sys::SoundChannel) {
+        pd_func_caller_log!((*self.raw_sound).removeChannel, raw_channel);
+    }
 }
 
 // PDA format constants
@@ -266,8 +391,78 @@ fn parse_pda_header(data: &[u8]) -> Option<(u32, SoundFormat, usize)> {
     Some((sample_rate, format, PDA_HEADER_SIZE))
 }
 
+type PlayerCallback = Box<dyn FnMut() + 'static>;
+
+/// Internal: closures registered with a `FilePlayer` or `SamplePlayer`.  The player boxes this so
+/// its address is stable, and passes it to the Playdate callbacks as userdata.
+#[derive(Default)]
+pub(crate) struct PlayerCallbacks {
+    pub(crate) finish: Option<PlayerCallback>,
+    pub(crate) looped: Option<PlayerCallback>,
+    pub(crate) fade: Option<PlayerCallback>,
+}
+
+impl core::fmt::Debug for PlayerCallbacks {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("PlayerCallbacks")
+            .field("finish", &self.finish.is_some())
+            .field("looped", &self.looped.is_some())
+            .field("fade", &self.fade.is_some())
+            .finish()
+    }
+}
+
+/// Internal: runs the callback in the given slot.  The callback is taken out while it runs so it
+/// can safely replace itself; it's put back afterward unless `once` is set or it was replaced.
+unsafe fn run_player_callback(
+    userdata: *mut ctypes::c_void,
+    slot: fn(&mut PlayerCallbacks) -> &mut Option<PlayerCallback>,
+    once: bool,
+) {
+    if userdata.is_null() {
+        return;
+    }
+    let callbacks = userdata as *mut PlayerCallbacks;
+    if let Some(mut callback) = slot(&mut *callbacks).take() {
+        callback();
+        let slot_ref = slot(&mut *callbacks);
+        if !once && slot_ref.is_none() {
+            *slot_ref = Some(callback);
+        }
+    }
+}
+
+pub(crate) extern "C" fn player_finish_trampoline(
+    _source: *mut crankstart_sys::SoundSource,
+    userdata: *mut ctypes::c_void,
+) {
+    unsafe { run_player_callback(userdata, |callbacks| &mut callbacks.finish, false) }
+}
+
+pub(crate) extern "C" fn player_loop_trampoline(
+    _source: *mut crankstart_sys::SoundSource,
+    userdata: *mut ctypes::c_void,
+) {
+    unsafe { run_player_callback(userdata, |callbacks| &mut callbacks.looped, false) }
+}
+
+pub(crate) extern "C" fn player_fade_trampoline(
+    _source: *mut crankstart_sys::SoundSource,
+    userdata: *mut ctypes::c_void,
+) {
+    unsafe { run_player_callback(userdata, |callbacks| &mut callbacks.fade, true) }
+}
+
 /// # Safety
 /// This trait must guarantee that the returned pointer is valid for the `self` lifetime.
 pub unsafe trait SoundSource: 'static {
     fn get_sound_source(&self) -> *mut crankstart_sys::SoundSource;
 }
+
+// SAFETY: the Rc keeps the inner source alive for self's lifetime.  This lets a source be added
+// to a channel while still being controlled elsewhere.
+unsafe impl<S: SoundSource> SoundSource for Rc<S> {
+    fn get_sound_source(&self) -> *mut crankstart_sys::SoundSource {
+        (**self).get_sound_source()
+    }
+}
@@ -1,38 +1,176 @@
 use crate::sound::effect::Effect;
+use crate::sound::synth::Signal;
 use crate::sound::SoundSource;
 use crate::{pd_func_caller, pd_func_caller_log};
 use alloc::boxed::Box;
+use alloc::rc::Rc;
 use alloc::vec::Vec;
-use anyhow::{Error, Result};
+use anyhow::{ensure, Error, Result};
+use core::cell::Cell;
 use core::marker::PhantomData;
+use core::ptr;
+use crankstart_sys::PDSynthSignalValue;
 
-pub struct SoundChannel {
+struct SoundChannelInner {
     raw_subsystem: *const crankstart_sys::playdate_sound_channel,
     raw_channel: *mut crankstart_sys::SoundChannel,
+    // False for the system's default channel, which we must never free.
+    owned: bool,
+    // Whether this channel has been added to the mixer with Sound::add_channel, so we can remove
+    // it again before it's freed.
+    in_mixer: Cell<bool>,
+}
+
+impl Drop for SoundChannelInner {
+    fn drop(&mut self) {
+        if self.owned {
+            if self.in_mixer.get() {
+                crate::sound::Sound::get().remove_channel_internal(self.raw_channel);
+            }
+            pd_func_caller_log!((*self.raw_subsystem).freeChannel, self.raw_channel);
+        }
+    }
+}
+
+pub struct SoundChannel {
+    // Shared with the channel's level signals, which are only valid while the channel exists.
+    inner: Rc<SoundChannelInner>,
     effects: Vec<Box<dyn Effect>>,
     sources: Vec<Box<dyn SoundSource>>,
+    volume_modulator: Option<Box<dyn Signal>>,
+    pan_modulator: Option<Box<dyn Signal>>,
 }
 
 impl SoundChannel {
-    pub fn new(
+    /// Internal: creates a new channel; use `Sound::new_channel`.  It won't be heard until it's
+    /// added to the mixer with `Sound::add_channel`.
+    pub(crate) fn new(
         raw_subsystem: *const crankstart_sys::playdate_sound_channel,
     ) -> Result<Self, Error> {
-        Ok(Self {
+        let raw_channel = pd_func_caller!((*raw_subsystem).newChannel)?;
+        ensure!(
+            !raw_channel.is_null(),
+            "Null returned from channel.newChannel"
+        );
+        Ok(Self::from_inner(SoundChannelInner {
+            raw_subsystem,
+            raw_channel,
+            owned: true,
+            in_mixer: Cell::new(false),
+        }))
+    }
+
+    /// Internal: wraps a channel owned by the system, e.g. the default channel.
+    pub(crate) fn new_unowned(
+        raw_subsystem: *const crankstart_sys::playdate_sound_channel,
+        raw_channel: *mut crankstart_sys::SoundChannel,
+    ) -> Result<Self, Error> {
+        ensure!(
+            !raw_channel.is_null(),
+            "Null pointer given as channel to SoundChannel::new_unowned"
+        );
+        Ok(Self::from_inner(SoundChannelInner {
             raw_subsystem,
-            raw_channel: pd_func_caller!((*raw_subsystem).newChannel)?,
+            raw_channel,
+            owned: false,
+            in_mixer: Cell::new(true),
+        }))
+    }
+
+    fn from_inner(inner: SoundChannelInner) -> Self {
+        Self {
+            inner: Rc::new(inner),
             effects: Vec::new(),
             sources: Vec::new(),
-        })
+            volume_modulator: None,
+            pan_modulator: None,
+        }
+    }
+
+    fn raw_subsystem(&self) -> *const crankstart_sys::playdate_sound_channel {
+        self.inner.raw_subsystem
+    }
+
+    pub(crate) fn raw_channel(&self) -> *mut crankstart_sys::SoundChannel {
+        self.inner.raw_channel
+    }
+
+    pub(crate) fn set_in_mixer(&mut self, in_mixer: bool) {
+        self.inner.in_mixer.set(in_mixer);
+    }
+
+    /// Returns whether the channel is currently being mixed into the audio output.
+    pub fn is_in_mixer(&self) -> bool {
+        self.inner.in_mixer.get()
     }
 
     pub fn set_volume(&mut self, volume: f32) -> Result<()> {
-        pd_func_caller!((*self.raw_subsystem).setVolume, self.raw_channel, volume)
+        pd_func_caller!(
+            (*self.raw_subsystem()).setVolume,
+            self.raw_channel(),
+            volume
+        )
+    }
+
+    /// Gets the channel's volume, out of 1.
+    pub fn get_volume(&self) -> Result<f32> {
+        pd_func_caller!((*self.raw_subsystem()).getVolume, self.raw_channel())
+    }
+
+    /// Sets the pan of the channel; -1 is left, 0 is center, and 1 is right.
+    pub fn set_pan(&mut self, pan: f32) -> Result<()> {
+        pd_func_caller!((*self.raw_subsystem()).setPan, self.raw_channel(), pan)
+    }
+
+    /// Sets a signal, e.g. an `LFO`, to modulate the channel's volume; `None` clears it.  The
+    /// channel keeps the modulator alive until it's replaced.
+    pub fn set_volume_modulator<S: Signal>(&mut self, modulator: Option<S>) -> Result<()> {
+        let raw_signal = signal_value_or_null(modulator.as_ref());
+        let result = pd_func_caller!(
+            (*self.raw_subsystem()).setVolumeModulator,
+            self.raw_channel(),
+            raw_signal
+        );
+        self.volume_modulator = modulator.map(|m| Box::new(m) as Box<dyn Signal>);
+        result
+    }
+
+    /// Sets a signal, e.g. an `LFO`, to modulate the channel's pan; `None` clears it.  The
+    /// channel keeps the modulator alive until it's replaced.
+    pub fn set_pan_modulator<S: Signal>(&mut self, modulator: Option<S>) -> Result<()> {
+        let raw_signal = signal_value_or_null(modulator.as_ref());
+        let result = pd_func_caller!(
+            (*self.raw_subsystem()).setPanModulator,
+            self.raw_channel(),
+            raw_signal
+        );
+        self.pan_modulator = modulator.map(|m| Box::new(m) as Box<dyn Signal>);
+        result
+    }
+
+    /// Returns a signal that follows the level of the channel's dry (pre-effect) output, for
+    /// use as a modulator elsewhere, e.g. to duck music while this channel is playing.
+    pub fn get_dry_level_signal(&self) -> Result<ChannelLevelSignal> {
+        let raw_signal = pd_func_caller!(
+            (*self.raw_subsystem()).getDryLevelSignal,
+            self.raw_channel()
+        )?;
+        ChannelLevelSignal::new(self.inner.clone(), raw_signal)
+    }
+
+    /// Returns a signal that follows the level of the channel's wet (post-effect) output.
+    pub fn get_wet_level_signal(&self) -> Result<ChannelLevelSignal> {
+        let raw_signal = pd_func_caller!(
+            (*self.raw_subsystem()).getWetLevelSignal,
+            self.raw_channel()
+        )?;
+        ChannelLevelSignal::new(self.inner.clone(), raw_signal)
     }
 
     pub fn add_effect<E: Effect>(&mut self, effect: E) -> Result<()> {
         let result = pd_func_caller!(
-            (*self.raw_subsystem).addEffect,
-            self.raw_channel,
+            (*self.raw_subsystem()).addEffect,
+            self.raw_channel(),
             effect.get_sound_effect()
         );
         self.effects.push(Box::new(effect));
@@ -46,8 +184,8 @@ impl SoundChannel {
 
     pub fn remove_effect<E: Effect>(&mut self, effect: E) -> Result<()> {
         let result = pd_func_caller!(
-            (*self.raw_subsystem).removeEffect,
-            self.raw_channel,
+            (*self.raw_subsystem()).removeEffect,
+            self.raw_channel(),
             effect.get_sound_effect()
         );
         self.effects
@@ -62,8 +200,8 @@ impl SoundChannel {
 
     pub fn add_source<S: SoundSource>(&mut self, source: S) -> Result<i32> {
         let result = pd_func_caller!(
-            (*self.raw_subsystem).addSource,
-            self.raw_channel,
+            (*self.raw_subsystem()).addSource,
+            self.raw_channel(),
             source.get_sound_source()
         );
         self.sources.push(Box::new(source));
@@ -72,8 +210,8 @@ impl SoundChannel {
 
     pub fn remove_source<S: SoundSource>(&mut self, source: S) -> Result<bool> {
         let result = pd_func_caller!(
-            (*self.raw_subsystem).removeSource,
-            self.raw_channel,
+            (*self.raw_subsystem()).removeSource,
+            self.raw_channel(),
             source.get_sound_source()
         );
         self.sources
@@ -82,6 +220,12 @@ impl SoundChannel {
     }
 }
 
+fn signal_value_or_null<S: Signal>(signal: Option<&S>) -> *mut PDSynthSignalValue {
+    signal
+        .map(|s| s.as_signal_value())
+        .unwrap_or(ptr::null_mut())
+}
+
 impl Drop for SoundChannel {
     fn drop(&mut self) {
         // Sources and effects must be removed before they are freed, otherwise you get
@@ -90,20 +234,63 @@ impl Drop for SoundChannel {
         // undesirable if someone has dropped the channel.
         for source in &self.sources {
             pd_func_caller_log!(
-                (*self.raw_subsystem).removeSource,
-                self.raw_channel,
+                (*self.raw_subsystem()).removeSource,
+                self.raw_channel(),
                 source.get_sound_source()
             );
         }
 
         for effect in &self.effects {
             pd_func_caller_log!(
-                (*self.raw_subsystem).removeEffect,
-                self.raw_channel,
+                (*self.raw_subsystem()).removeEffect,
+                self.raw_channel(),
                 effect.get_sound_effect()
             );
         }
 
-        pd_func_caller_log!((*self.raw_subsystem).freeChannel, self.raw_channel);
+        // Same for modulators; the channel itself may outlive us if a level signal is held.
+        if self.volume_modulator.is_some() {
+            pd_func_caller_log!(
+                (*self.raw_subsystem()).setVolumeModulator,
+                self.raw_channel(),
+                ptr::null_mut()
+            );
+        }
+        if self.pan_modulator.is_some() {
+            pd_func_caller_log!(
+                (*self.raw_subsystem()).setPanModulator,
+                self.raw_channel(),
+                ptr::null_mut()
+            );
+        }
+    }
+}
+
+/// A read-only signal following one of a channel's output levels; see
+/// `SoundChannel::get_dry_level_signal` and `SoundChannel::get_wet_level_signal`.  It keeps the
+/// underlying channel allocated until it's dropped.
+#[derive(Clone)]
+pub struct ChannelLevelSignal {
+    _channel: Rc<SoundChannelInner>,
+    raw_signal: *mut PDSynthSignalValue,
+}
+
+impl ChannelLevelSignal {
+    fn new(channel: Rc<SoundChannelInner>, raw_signal: *mut PDSynthSignalValue) -> Result<Self> {
+        ensure!(
+            !raw_signal.is_null(),
+            "Null returned for channel level signal"
+        );
+        Ok(Self {
+            _channel: channel,
+            raw_signal,
+        })
+    }
+}
+
+// SAFETY: the signal belongs to the channel, which we keep alive for self's lifetime
+unsafe impl Signal for ChannelLevelSignal {
+    fn as_signal_value(&self) -> *mut PDSynthSignalValue {
+        self.raw_signal
     }
 }
@@ -1,10 +1,14 @@
 use crate::{pd_func_caller, pd_func_caller_log};
 use crankstart_sys::ctypes;
 
+use alloc::boxed::Box;
 use anyhow::{anyhow, ensure, Error, Result};
 use cstr_core::CString;
 
-use super::SoundSource;
+use super::{
+    player_fade_trampoline, player_finish_trampoline, player_loop_trampoline, PlayerCallbacks,
+    SoundSource, SAMPLES_PER_SECOND,
+};
 
 /// Note: Make sure you hold on to a FilePlayer until the file has played as much as you want,
 /// because dropping it will stop playback.
@@ -12,6 +16,10 @@ use super::SoundSource;
 pub struct FilePlayer {
     raw_subsystem: *const crankstart_sys::playdate_sound_fileplayer,
     raw_player: *mut crankstart_sys::FilePlayer,
+
+    // Boxed so the address we give the Playdate as callback userdata doesn't move; dropped
+    // after the player is freed.
+    callbacks: Box<PlayerCallbacks>,
 }
 
 impl Drop for FilePlayer {
@@ -21,8 +29,7 @@ impl Drop for FilePlayer {
     }
 }
 
-// Not implemented: newPlayer (use Sound::get_file_player), setFinishCallback and fadeVolume
-// (waiting on crankstart callback strategy), and setLoopRange (does not seem to do anything).
+// Not implemented: newPlayer (use Sound::get_file_player).
 impl FilePlayer {
     pub(crate) fn new(
         raw_subsystem: *const crankstart_sys::playdate_sound_fileplayer,
@@ -39,9 +46,14 @@ impl FilePlayer {
         Ok(Self {
             raw_subsystem,
             raw_player,
+            callbacks: Box::default(),
         })
     }
 
+    fn callbacks_userdata(&mut self) -> *mut ctypes::c_void {
+        &mut *self.callbacks as *mut PlayerCallbacks as *mut ctypes::c_void
+    }
+
     /// Loads the given file into the player.  Unlike with SamplePlayer, you must give the
     /// compiled audio filename here, e.g. "file.pda" instead of "file.wav".  MP3 files are
     /// not compiled, so they keep their original .mp3 extension.
@@ -179,6 +191,99 @@ impl FilePlayer {
     pub fn get_length(&self) -> Result<f32> {
         pd_func_caller!((*self.raw_subsystem).getLength, self.raw_player)
     }
+
+    /// Sets the portion of the file, in seconds, that repeats when playing with a
+    /// `repeat_count` other than 1.  An `end` of 0 means the end of the file.
+    pub fn set_loop_range(&self, start: f32, end: f32) -> Result<()> {
+        pd_func_caller!(
+            (*self.raw_subsystem).setLoopRange,
+            self.raw_player,
+            start,
+            end
+        )
+    }
+
+    /// Like `set_loop_range`, but given in frames at 44.1k per second.
+    pub fn set_loop_range_frames(&self, start: u32, end: u32) -> Result<()> {
+        self.set_loop_range(frames_to_seconds(start), frames_to_seconds(end))
+    }
+
+    /// Calls `callback` when the file finishes playing, including when `stop` is called.  Pass
+    /// `None` to remove the callback.  The callback is dropped with the player.
+    pub fn set_finish_callback<F>(&mut self, callback: Option<F>) -> Result<()>
+    where
+        F: FnMut() + 'static,
+    {
+        self.callbacks.finish = callback.map(|cb| Box::new(cb) as Box<dyn FnMut()>);
+        let trampoline: crankstart_sys::sndCallbackProc = self
+            .callbacks
+            .finish
+            .as_ref()
+            .map(|_| player_finish_trampoline as _);
+        let userdata = self.callbacks_userdata();
+        pd_func_caller!(
+            (*self.raw_subsystem).setFinishCallback,
+            self.raw_player,
+            trampoline,
+            userdata
+        )
+    }
+
+    /// Calls `callback` each time playback loops back to the start of the loop range.  Pass
+    /// `None` to remove the callback.  The callback is dropped with the player.
+    pub fn set_loop_callback<F>(&mut self, callback: Option<F>) -> Result<()>
+    where
+        F: FnMut() + 'static,
+    {
+        self.callbacks.looped = callback.map(|cb| Box::new(cb) as Box<dyn FnMut()>);
+        let trampoline: crankstart_sys::sndCallbackProc = self
+            .callbacks
+            .looped
+            .as_ref()
+            .map(|_| player_loop_trampoline as _);
+        let userdata = self.callbacks_userdata();
+        pd_func_caller!(
+            (*self.raw_subsystem).setLoopCallback,
+            self.raw_player,
+            trampoline,
+            userdata
+        )
+    }
+
+    /// Changes the volume of the left and right channels to the given values, out of 1, over
+    /// `seconds`.  `on_done` is called once the fade completes; starting another fade replaces
+    /// it.
+    pub fn fade_volume<F>(
+        &mut self,
+        left: f32,
+        right: f32,
+        seconds: f32,
+        on_done: Option<F>,
+    ) -> Result<()>
+    where
+        F: FnMut() + 'static,
+    {
+        self.callbacks.fade = on_done.map(|cb| Box::new(cb) as Box<dyn FnMut()>);
+        let trampoline: crankstart_sys::sndCallbackProc = self
+            .callbacks
+            .fade
+            .as_ref()
+            .map(|_| player_fade_trampoline as _);
+        let userdata = self.callbacks_userdata();
+        pd_func_caller!(
+            (*self.raw_subsystem).fadeVolume,
+            self.raw_player,
+            left,
+            right,
+            (seconds * SAMPLES_PER_SECOND as f32) as i32,
+            trampoline,
+            userdata
+        )
+    }
+}
+
+fn frames_to_seconds(frames: u32) -> f32 {
+    frames as f32 / SAMPLES_PER_SECOND as f32
 }
 
 // SAFETY: FilePlayers are sound sources, we keep it alive for the lifetime of the FilePlayer,
@@ -0,0 +1,414 @@
+//! A higher-level music player built on two `FilePlayer`s mixed into their own `SoundChannel`.
+//!
+//! ```rust
+//! let mut music = MusicPlayer::new()?;
+//! music.play(Track::new("music/title.pda").looping(), 0.0)?;
+//! // Later, fade into the level theme, which has a two second intro before it loops.
+//! music.play(Track::new("music/level1.pda").with_loop_range(2.0, 0.0), 1.5)?;
+//!
+//! // Each frame, from Game::update:
+//! music.update()?;
+//!
+//! // And from Game::handle_event, so music pauses while the system menu is open:
+//! music.handle_event(event)?;
+//!
+//! // Duck the music under sound effects played through their own channel.
+//! music.duck_under(Some(&effects_channel))?;
+//! ```
+
+use crate::sound::{FilePlayer, ScaledSignal, Sound, SoundChannel};
+use crate::system::System;
+use alloc::{collections::VecDeque, rc::Rc, string::String};
+use anyhow::Result;
+use crankstart_sys::PDSystemEvent;
+
+/// A music file to be played by a `MusicPlayer`.
+#[derive(Clone, Debug)]
+pub struct Track {
+    path: String,
+    start_offset: f32,
+    loop_range: Option<(f32, f32)>,
+    looping: bool,
+}
+
+impl Track {
+    /// A track that plays the given compiled audio file (e.g. "music.pda") once.
+    pub fn new(path: &str) -> Self {
+        Self {
+            path: path.into(),
+            start_offset: 0.0,
+            loop_range: None,
+            looping: false,
+        }
+    }
+
+    /// Repeat the track until another is played.
+    pub fn looping(mut self) -> Self {
+        self.looping = true;
+        self
+    }
+
+    /// Start playback `offset` seconds into the file.
+    pub fn with_start_offset(mut self, offset: f32) -> Self {
+        self.start_offset = offset;
+        self
+    }
+
+    /// Play from the start of the file, then repeat from `start` to `end` seconds until
+    /// another track is played, for intro+loop music.  An `end` of 0 means the end of the file.
+    pub fn with_loop_range(mut self, start: f32, end: f32) -> Self {
+        self.loop_range = Some((start, end));
+        self.looping = true;
+        self
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    fn repeat_count(&self) -> i32 {
+        if self.looping {
+            0
+        } else {
+            1
+        }
+    }
+}
+
+struct Crossfade {
+    from: usize,
+    start_ms: usize,
+    duration_ms: usize,
+}
+
+struct Ducking {
+    // Volume multiplier applied while ducked, out of 1.
+    level: f32,
+    // How long it takes to duck or recover, in seconds.
+    speed: f32,
+    // The multiplier currently applied to the music channel.
+    current: f32,
+    manual: bool,
+    // The music channel's volume modulator, following another channel's level.
+    sidechain: Option<ScaledSignal>,
+}
+
+/// Plays a queue of `Track`s, crossfading between them, pausing along with the system menu, and
+/// optionally ducking under sound effects.  Call `update` once per frame.
+pub struct MusicPlayer {
+    channel: SoundChannel,
+    // Two players so one can fade in while the other fades out.
+    decks: [Rc<FilePlayer>; 2],
+    active: usize,
+    current: Option<Track>,
+    queue: VecDeque<Track>,
+    crossfade: Option<Crossfade>,
+    crossfade_seconds: f32,
+    volume: f32,
+    ducking: Ducking,
+    // The repeat count each deck was last played with, for resuming.
+    deck_repeats: [i32; 2],
+    // Which decks were playing when the music was paused.
+    paused_decks: [bool; 2],
+    paused_at_ms: Option<usize>,
+    last_update_ms: usize,
+}
+
+impl MusicPlayer {
+    /// Creates a music player with its own channel, added to the mixer.
+    pub fn new() -> Result<Self> {
+        let sound = Sound::get();
+        let decks = [
+            Rc::new(sound.get_file_player()?),
+            Rc::new(sound.get_file_player()?),
+        ];
+        let mut channel = sound.new_channel()?;
+        for deck in &decks {
+            channel.add_source(deck.clone())?;
+        }
+        sound.add_channel(&mut channel)?;
+        Ok(Self {
+            channel,
+            decks,
+            active: 0,
+            current: None,
+            queue: VecDeque::new(),
+            crossfade: None,
+            crossfade_seconds: 0.0,
+            volume: 1.0,
+            ducking: Ducking {
+                level: 0.4,
+                speed: 0.2,
+                current: 1.0,
+                manual: false,
+                sidechain: None,
+            },
+            deck_repeats: [1; 2],
+            paused_decks: [false; 2],
+            paused_at_ms: None,
+            last_update_ms: now_ms()?,
+        })
+    }
+
+    /// The channel the music plays through, e.g. for adding effects.
+    pub fn channel_mut(&mut self) -> &mut SoundChannel {
+        &mut self.channel
+    }
+
+    /// Returns the track currently playing, if any.
+    pub fn current_track(&self) -> Option<&Track> {
+        self.current.as_ref()
+    }
+
+    /// Sets the overall music volume, out of 1.
+    pub fn set_volume(&mut self, volume: f32) -> Result<()> {
+        self.volume = volume.clamp(0.0, 1.0);
+        self.apply_channel_volume()
+    }
+
+    /// Plays `track` now, crossfading from the current track over `crossfade_seconds`.  Anything
+    /// queued is kept and plays after it.
+    pub fn play(&mut self, track: Track, crossfade_seconds: f32) -> Result<()> {
+        let incoming = 1 - self.active;
+        let deck = &self.decks[incoming];
+        deck.stop()?;
+        deck.load_into_player(&track.path)?;
+        match track.loop_range {
+            Some((start, end)) => deck.set_loop_range(start, end)?,
+            None => deck.set_loop_range(0.0, 0.0)?,
+        }
+        deck.set_offset(track.start_offset)?;
+
+        let has_outgoing = self.current.is_some() && self.decks[self.active].is_playing()?;
+        if has_outgoing && crossfade_seconds > 0.0 {
+            deck.set_volume(0.0, 0.0)?;
+            self.crossfade = Some(Crossfade {
+                from: self.active,
+                start_ms: now_ms()?,
+                duration_ms: (crossfade_seconds * 1000.0) as usize,
+            });
+        } else {
+            if let Some(crossfade) = self.crossfade.take() {
+                self.decks[crossfade.from].stop()?;
+            }
+            self.decks[self.active].stop()?;
+            self.paused_decks[self.active] = false;
+            deck.set_volume(1.0, 1.0)?;
+        }
+        deck.play(track.repeat_count())?;
+        self.deck_repeats[incoming] = track.repeat_count();
+        if self.paused_at_ms.is_some() {
+            deck.pause()?;
+            self.paused_decks[incoming] = true;
+        }
+        self.active = incoming;
+        self.current = Some(track);
+        Ok(())
+    }
+
+    /// Adds `track` to the end of the queue.  Queued tracks start when the current one ends,
+    /// crossfading over the duration given to `set_queue_crossfade`.
+    pub fn enqueue(&mut self, track: Track) {
+        self.queue.push_back(track);
+    }
+
+    /// Sets how long to crossfade when moving to the next queued track, in seconds.
+    pub fn set_queue_crossfade(&mut self, seconds: f32) {
+        self.crossfade_seconds = seconds;
+    }
+
+    /// Skips to the next queued track, crossfading over `crossfade_seconds`.  Returns false if
+    /// the queue was empty.
+    pub fn skip(&mut self, crossfade_seconds: f32) -> Result<bool> {
+        if let Some(next) = self.queue.pop_front() {
+            self.play(next, crossfade_seconds)?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// Stops all music and clears the queue.
+    pub fn stop(&mut self) -> Result<()> {
+        self.queue.clear();
+        self.crossfade = None;
+        self.current = None;
+        self.paused_decks = [false; 2];
+        for deck in &self.decks {
+            deck.stop()?;
+        }
+        Ok(())
+    }
+
+    /// Pauses the music; `resume` continues from the same point.
+    pub fn pause(&mut self) -> Result<()> {
+        if self.paused_at_ms.is_none() {
+            self.paused_at_ms = Some(now_ms()?);
+            for (deck, paused) in self.decks.iter().zip(self.paused_decks.iter_mut()) {
+                *paused = deck.is_playing()?;
+                if *paused {
+                    deck.pause()?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Resumes music paused with `pause`.
+    pub fn resume(&mut self) -> Result<()> {
+        if let Some(paused_at_ms) = self.paused_at_ms.take() {
+            let paused_for = now_ms()?.saturating_sub(paused_at_ms);
+            if let Some(crossfade) = self.crossfade.as_mut() {
+                crossfade.start_ms += paused_for;
+            }
+            // Only continue decks that were playing, so a finished track doesn't restart.
+            for (index, deck) in self.decks.iter().enumerate() {
+                if core::mem::take(&mut self.paused_decks[index]) {
+                    deck.play(self.deck_repeats[index])?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused_at_ms.is_some()
+    }
+
+    /// Pauses on `kEventPause` and resumes on `kEventResume`, so the music stops while the
+    /// system menu is open.  Call from `Game::handle_event`.
+    pub fn handle_event(&mut self, event: PDSystemEvent) -> Result<()> {
+        match event {
+            PDSystemEvent::kEventPause => self.pause(),
+            PDSystemEvent::kEventResume => self.resume(),
+            _ => Ok(()),
+        }
+    }
+
+    /// Sets how far to duck the music, as a volume multiplier out of 1, and how long `duck` and
+    /// `unduck` take, in seconds.
+    pub fn set_ducking(&mut self, level: f32, speed: f32) -> Result<()> {
+        self.ducking.level = level.clamp(0.0, 1.0);
+        self.ducking.speed = speed.max(0.0);
+        let scale = self.ducking_scale();
+        if let Some(sidechain) = self.ducking.sidechain.as_mut() {
+            sidechain.set_scale(scale)?;
+        }
+        Ok(())
+    }
+
+    /// Ducks the music until `unduck` is called.
+    pub fn duck(&mut self) {
+        self.ducking.manual = true;
+    }
+
+    pub fn unduck(&mut self) {
+        self.ducking.manual = false;
+    }
+
+    /// Ducks the music under `channel`, e.g. the channel sound effects play through, by
+    /// modulating the music channel's volume with `channel`'s level.  The louder `channel` is,
+    /// the quieter the music, down to the `set_ducking` level when `channel` is at full scale.
+    /// This runs in the audio engine, so it follows the sound effects sample by sample rather
+    /// than once a frame.  Pass `None` to stop.
+    pub fn duck_under(&mut self, channel: Option<&SoundChannel>) -> Result<()> {
+        self.ducking.sidechain = match channel {
+            Some(channel) => Some(Sound::get().new_scaled_signal(
+                channel.get_dry_level_signal()?,
+                self.ducking_scale(),
+                1.0,
+            )?),
+            None => None,
+        };
+        self.channel
+            .set_volume_modulator(self.ducking.sidechain.clone())
+    }
+
+    // Maps a sidechain level from 0 to 1 onto a volume multiplier from 1 to the ducking level.
+    fn ducking_scale(&self) -> f32 {
+        self.ducking.level - 1.0
+    }
+
+    /// Advances crossfades, ducking, and the queue.  Call once per frame.
+    pub fn update(&mut self) -> Result<()> {
+        let now = now_ms()?;
+        let elapsed_seconds = now.saturating_sub(self.last_update_ms) as f32 / 1000.0;
+        self.last_update_ms = now;
+        if self.paused_at_ms.is_some() {
+            return Ok(());
+        }
+
+        self.update_ducking(elapsed_seconds)?;
+
+        if let Some(crossfade) = self.crossfade.as_ref() {
+            let progress = if crossfade.duration_ms == 0 {
+                1.0
+            } else {
+                (now.saturating_sub(crossfade.start_ms) as f32 / crossfade.duration_ms as f32)
+                    .min(1.0)
+            };
+            self.decks[self.active].set_volume(progress, progress)?;
+            if progress >= 1.0 {
+                self.decks[crossfade.from].stop()?;
+                self.crossfade = None;
+            } else {
+                let outgoing = 1.0 - progress;
+                self.decks[crossfade.from].set_volume(outgoing, outgoing)?;
+            }
+        }
+
+        self.update_queue()
+    }
+
+    fn update_queue(&mut self) -> Result<()> {
+        if self.queue.is_empty() || self.crossfade.is_some() {
+            return Ok(());
+        }
+        let deck = &self.decks[self.active];
+        let playing = deck.is_playing()?;
+        let track_ending = match self.current.as_ref() {
+            None => true,
+            Some(track) if track.looping => false,
+            Some(_) if !playing => true,
+            Some(_) => {
+                let remaining = deck.get_length()? - deck.get_offset()?;
+                remaining <= self.crossfade_seconds
+            }
+        };
+        if track_ending {
+            let crossfade_seconds = if playing { self.crossfade_seconds } else { 0.0 };
+            self.skip(crossfade_seconds)?;
+        }
+        Ok(())
+    }
+
+    fn update_ducking(&mut self, elapsed_seconds: f32) -> Result<()> {
+        let ducking = &mut self.ducking;
+        let target = if ducking.manual { ducking.level } else { 1.0 };
+        if ducking.current == target {
+            return Ok(());
+        }
+        // Move at a rate that covers the full range in `speed`, so changing the level while
+        // ducked still recovers.
+        let step = if ducking.speed > 0.0 {
+            elapsed_seconds / ducking.speed
+        } else {
+            1.0
+        };
+        ducking.current = if ducking.current < target {
+            (ducking.current + step).min(target)
+        } else {
+            (ducking.current - step).max(target)
+        };
+        self.apply_channel_volume()
+    }
+
+    fn apply_channel_volume(&mut self) -> Result<()> {
+        let volume = self.volume * self.ducking.current;
+        self.channel.set_volume(volume)
+    }
+}
+
+fn now_ms() -> Result<usize> {
+    System::get().get_current_time_milliseconds()
+}
@@ -1,10 +1,13 @@
 use crate::{log_to_console, pd_func_caller, pd_func_caller_log};
 use crankstart_sys::ctypes;
 
-use alloc::{rc::Rc, vec::Vec};
+use alloc::{boxed::Box, rc::Rc, vec::Vec};
 use anyhow::{anyhow, ensure, Error, Result};
+use core::{ptr, slice};
+use crankstart_sys::SoundFormat;
+use cstr_core::CString;
 
-use super::SoundSource;
+use super::{player_finish_trampoline, player_loop_trampoline, PlayerCallbacks, SoundSource};
 
 /// Note: Make sure you hold on to a SamplePlayer until the sample has played as much as you want,
 /// because dropping it will stop playback.
@@ -16,6 +19,10 @@ pub struct SamplePlayer {
     // We store an Rc clone of the audio sample so that it's not freed before the player is
     // finished using it, or until another sample is set.
     sample: Option<AudioSample>,
+
+    // Boxed so the address we give the Playdate as callback userdata doesn't move; dropped
+    // after the player is freed.
+    callbacks: Box<PlayerCallbacks>,
 }
 
 impl Drop for SamplePlayer {
@@ -25,8 +32,7 @@ impl Drop for SamplePlayer {
     }
 }
 
-// Not implemented: newPlayer (use Sound::get_sample_player), and setFinishCallback and setLoopCallback
-// (waiting on crankstart callback strategy).
+// Not implemented: newPlayer (use Sound::get_sample_player).
 impl SamplePlayer {
     pub(crate) fn new(
         raw_subsystem: *const crankstart_sys::playdate_sound_sampleplayer,
@@ -44,9 +50,14 @@ impl SamplePlayer {
             raw_subsystem,
             raw_player,
             sample: None,
+            callbacks: Box::default(),
         })
     }
 
+    fn callbacks_userdata(&mut self) -> *mut ctypes::c_void {
+        &mut *self.callbacks as *mut PlayerCallbacks as *mut ctypes::c_void
+    }
+
     /// Sets the sound effect to be played by this player.
     pub fn set_sample(&mut self, audio_sample: &AudioSample) -> Result<()> {
         // We store an Rc clone of the audio sample so that it's not freed before the player is
@@ -112,6 +123,61 @@ impl SamplePlayer {
         )
     }
 
+    /// Like `set_play_range`, but given in seconds, using the sample rate of the current sample.
+    pub fn set_play_range_seconds(&self, start: f32, end: f32) -> Result<()> {
+        let sample = self
+            .sample
+            .as_ref()
+            .ok_or_else(|| anyhow!("set_play_range_seconds called before set_sample"))?;
+        let sample_rate = sample.get_sample_rate()? as f32;
+        self.set_play_range(
+            (start * sample_rate) as ctypes::c_int,
+            (end * sample_rate) as ctypes::c_int,
+        )
+    }
+
+    /// Calls `callback` when the sample finishes playing, including when `stop` is called.  Pass
+    /// `None` to remove the callback.  The callback is dropped with the player.
+    pub fn set_finish_callback<F>(&mut self, callback: Option<F>) -> Result<()>
+    where
+        F: FnMut() + 'static,
+    {
+        self.callbacks.finish = callback.map(|cb| Box::new(cb) as Box<dyn FnMut()>);
+        let trampoline: crankstart_sys::sndCallbackProc = self
+            .callbacks
+            .finish
+            .as_ref()
+            .map(|_| player_finish_trampoline as _);
+        let userdata = self.callbacks_userdata();
+        pd_func_caller!(
+            (*self.raw_subsystem).setFinishCallback,
+            self.raw_player,
+            trampoline,
+            userdata
+        )
+    }
+
+    /// Calls `callback` each time playback loops.  Pass `None` to remove the callback.  The
+    /// callback is dropped with the player.
+    pub fn set_loop_callback<F>(&mut self, callback: Option<F>) -> Result<()>
+    where
+        F: FnMut() + 'static,
+    {
+        self.callbacks.looped = callback.map(|cb| Box::new(cb) as Box<dyn FnMut()>);
+        let trampoline: crankstart_sys::sndCallbackProc = self
+            .callbacks
+            .looped
+            .as_ref()
+            .map(|_| player_loop_trampoline as _);
+        let userdata = self.callbacks_userdata();
+        pd_func_caller!(
+            (*self.raw_subsystem).setLoopCallback,
+            self.raw_player,
+            trampoline,
+            userdata
+        )
+    }
+
     /// Returns the current offset into the sample, in seconds, increasing as it plays.  This is not
     /// adjusted for rate.
     pub fn get_offset(&self) -> Result<f32> {
@@ -190,8 +256,37 @@ impl Drop for AudioSampleInner {
     }
 }
 
-// Not implemented: getData, newSampleBuffer, loadIntoSample, newSampleFromData -
-// only Sound::load_audio_sample for now.
+/// The raw contents of an `AudioSample`; see `AudioSample::data`.
+#[derive(Clone, Copy, Debug)]
+pub struct AudioSampleData<'a> {
+    pub format: SoundFormat,
+    pub sample_rate: u32,
+    pub bytes: &'a [u8],
+}
+
+impl<'a> AudioSampleData<'a> {
+    /// Returns the data as 16 bit samples, interleaved if stereo, or None for other formats.
+    pub fn samples_i16(&self) -> Option<&'a [i16]> {
+        match self.format {
+            SoundFormat::kSound16bitMono | SoundFormat::kSound16bitStereo => {
+                // SAFETY: the Playdate allocates sample data with at least 2 byte alignment.
+                let (prefix, samples, _) = unsafe { self.bytes.align_to::<i16>() };
+                if prefix.is_empty() {
+                    Some(samples)
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the number of frames in the data, or None for ADPCM.
+    pub fn frame_count(&self) -> Option<usize> {
+        super::wav::bytes_per_frame(self.format).map(|bytes| self.bytes.len() / bytes)
+    }
+}
+
 impl AudioSample {
     pub(crate) fn new(
         raw_subsystem: *const crankstart_sys::playdate_sound_sample,
@@ -222,6 +317,113 @@ impl AudioSample {
             self.inner.raw_audio_sample
         )
     }
+
+    /// Creates a sample from 16 bit PCM samples, interleaved left and right if `format` is
+    /// stereo.  The samples are copied.
+    pub fn from_pcm(samples: &[i16], format: SoundFormat, sample_rate: u32) -> Result<Self> {
+        ensure!(
+            matches!(
+                format,
+                SoundFormat::kSound16bitMono | SoundFormat::kSound16bitStereo
+            ),
+            "AudioSample::from_pcm needs a 16 bit format, not {:?}",
+            format
+        );
+        let bytes = samples
+            .iter()
+            .flat_map(|sample| sample.to_le_bytes())
+            .collect::<Vec<u8>>();
+        Self::from_data(bytes, format, sample_rate)
+    }
+
+    /// Creates a sample from raw data in any `SoundFormat`.  The sample takes ownership of the
+    /// data, which is kept alive until the sample is dropped.
+    pub fn from_data(data: Vec<u8>, format: SoundFormat, sample_rate: u32) -> Result<Self> {
+        let len = data.len();
+        Self::from_data_range(data, 0..len, format, sample_rate)
+    }
+
+    /// Internal: creates a sample from part of `data`, e.g. skipping a file header.
+    pub(crate) fn from_data_range(
+        mut data: Vec<u8>,
+        range: core::ops::Range<usize>,
+        format: SoundFormat,
+        sample_rate: u32,
+    ) -> Result<Self> {
+        ensure!(!range.is_empty(), "No audio data given for sample");
+        let sound = super::Sound::get();
+        let byte_count = range.len();
+        let raw_audio_sample = pd_func_caller!(
+            (*sound.raw_sample).newSampleFromData,
+            data[range].as_mut_ptr(),
+            format,
+            sample_rate,
+            byte_count as ctypes::c_int,
+            0 // we free the data when the sample is dropped
+        )?;
+        ensure!(
+            !raw_audio_sample.is_null(),
+            "Null returned from sample.newSampleFromData"
+        );
+        Self::new(sound.raw_sample, raw_audio_sample, Some(data))
+    }
+
+    /// Creates an empty sample with a buffer of the given size, for use with `load`.
+    pub fn new_buffer(byte_count: usize) -> Result<Self> {
+        let sound = super::Sound::get();
+        let raw_audio_sample = pd_func_caller!(
+            (*sound.raw_sample).newSampleBuffer,
+            byte_count as ctypes::c_int
+        )?;
+        ensure!(
+            !raw_audio_sample.is_null(),
+            "Null returned from sample.newSampleBuffer"
+        );
+        Self::new(sound.raw_sample, raw_audio_sample, None)
+    }
+
+    /// Loads the given file into the sample's existing buffer, e.g. one from `new_buffer`.
+    pub fn load(&self, path: &str) -> Result<()> {
+        let c_path = CString::new(path).map_err(Error::msg)?;
+        let result = pd_func_caller!(
+            (*self.inner.raw_subsystem).loadIntoSample,
+            self.inner.raw_audio_sample,
+            c_path.as_ptr()
+        )?;
+        ensure!(result != 0, "Failed to load '{}' into sample", path);
+        Ok(())
+    }
+
+    /// Returns the sample's format, sample rate, and data.
+    pub fn data(&self) -> Result<AudioSampleData<'_>> {
+        let mut data = ptr::null_mut();
+        let mut format = SoundFormat::kSound16bitMono;
+        let mut sample_rate = 0;
+        let mut byte_length = 0;
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).getData,
+            self.inner.raw_audio_sample,
+            &mut data,
+            &mut format,
+            &mut sample_rate,
+            &mut byte_length
+        )?;
+        let bytes = if data.is_null() {
+            &[]
+        } else {
+            unsafe { slice::from_raw_parts(data as *const u8, byte_length as usize) }
+        };
+        Ok(AudioSampleData {
+            format,
+            sample_rate,
+            bytes,
+        })
+    }
+
+    /// Internal: the sample rate of the sample's data, in frames per second.
+    pub(crate) fn get_sample_rate(&self) -> Result<u32> {
+        Ok(self.data()?.sample_rate)
+    }
 }
 
 // SAFETY: players are sound sources which we keep alive for self's lifetime
@@ -3,7 +3,7 @@ use core::cell::RefCell;
 use crate::{pd_func_caller, pd_func_caller_log};
 use alloc::{boxed::Box, rc::Rc};
 use anyhow::{anyhow, ensure, Error, Result};
-use crankstart_sys::{PDSynth, PDSynthSignalValue};
+use crankstart_sys::{PDSynth, PDSynthSignal, PDSynthSignalValue};
 
 use super::SoundSource;
 
@@ -164,3 +164,67 @@ unsafe impl Signal for LFO {
         self.0.raw_lfo as *mut PDSynthSignalValue
     }
 }
+
+struct ScaledSignalInner {
+    raw_subsystem: *const crankstart_sys::playdate_sound_signal,
+    raw_signal: *mut PDSynthSignal,
+    // The wrapped signal, which must outlive ours.
+    _source: Box<dyn Signal>,
+}
+
+/// A signal that follows another, scaled and offset; see `Sound::new_scaled_signal`.
+#[derive(Clone)]
+pub struct ScaledSignal(Rc<ScaledSignalInner>);
+
+impl ScaledSignal {
+    pub(crate) fn new<S: Signal>(
+        raw_subsystem: *const crankstart_sys::playdate_sound_signal,
+        source: S,
+    ) -> Result<Self, Error> {
+        let raw_signal =
+            pd_func_caller!((*raw_subsystem).newSignalForValue, source.as_signal_value())?;
+        ensure!(
+            !raw_signal.is_null(),
+            "Null returned from signal.newSignalForValue"
+        );
+        Ok(Self(Rc::new(ScaledSignalInner {
+            raw_subsystem,
+            raw_signal,
+            _source: Box::new(source),
+        })))
+    }
+
+    pub fn set_scale(&mut self, scale: f32) -> Result<()> {
+        pd_func_caller!(
+            (*self.0.raw_subsystem).setValueScale,
+            self.0.raw_signal,
+            scale
+        )
+    }
+
+    pub fn set_offset(&mut self, offset: f32) -> Result<()> {
+        pd_func_caller!(
+            (*self.0.raw_subsystem).setValueOffset,
+            self.0.raw_signal,
+            offset
+        )
+    }
+
+    /// Returns the signal's current value, after scaling.
+    pub fn get_value(&self) -> Result<f32> {
+        pd_func_caller!((*self.0.raw_subsystem).getValue, self.0.raw_signal)
+    }
+}
+
+impl Drop for ScaledSignalInner {
+    fn drop(&mut self) {
+        pd_func_caller_log!((*self.raw_subsystem).freeSignal, self.raw_signal);
+    }
+}
+
+// SAFETY: the Rc keeps the signal, and the signal it wraps, alive for self's lifetime
+unsafe impl Signal for ScaledSignal {
+    fn as_signal_value(&self) -> *mut PDSynthSignalValue {
+        self.0.raw_signal as *mut PDSynthSignalValue
+    }
+}
@@ -0,0 +1,297 @@
+//! A small WAV header parser, so audio that didn't go through the Playdate compiler (e.g.
+//! generated or downloaded at runtime) can be turned into an `AudioSample` with
+//! `Sound::load_wav_from_data`.  This doesn't touch the Playdate API.
+
+use anyhow::{anyhow, bail, ensure, Result};
+use core::ops::Range;
+use crankstart_sys::SoundFormat;
+
+const WAVE_FORMAT_PCM: u16 = 0x0001;
+const WAVE_FORMAT_IMA_ADPCM: u16 = 0x0011;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xfffe;
+
+/// The parts of a WAV file needed to build an `AudioSample`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WavInfo {
+    pub format: SoundFormat,
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub bits_per_sample: u16,
+    /// Bytes per ADPCM block, or per frame for PCM.
+    pub block_align: u16,
+    /// Where the sample data is within the file.
+    pub data: Range<usize>,
+}
+
+impl WavInfo {
+    /// Returns the number of frames in the data, for PCM formats.
+    pub fn frame_count(&self) -> Option<usize> {
+        match self.format {
+            SoundFormat::kSoundADPCMMono | SoundFormat::kSoundADPCMStereo => None,
+            _ => Some(self.data.len() / self.block_align as usize),
+        }
+    }
+}
+
+/// Parses the header of a RIFF WAV file.  Supports 8 and 16 bit PCM and IMA ADPCM, mono or
+/// stereo.
+pub fn parse_wav(data: &[u8]) -> Result<WavInfo> {
+    ensure!(data.len() >= 12, "WAV data too short for a RIFF header");
+    ensure!(&data[0..4] == b"RIFF", "WAV data missing RIFF magic");
+    ensure!(&data[8..12] == b"WAVE", "RIFF data is not WAVE");
+
+    let mut fmt: Option<(u16, u16, u32, u16, u16)> = None;
+    let mut offset = 12;
+    while offset + 8 <= data.len() {
+        let chunk_id = &data[offset..offset + 4];
+        let chunk_len = read_u32(data, offset + 4)? as usize;
+        let body = offset + 8;
+        match chunk_id {
+            b"fmt " => {
+                ensure!(chunk_len >= 16, "WAV fmt chunk too short");
+                ensure!(body + 16 <= data.len(), "WAV fmt chunk truncated");
+                let mut format_tag = read_u16(data, body)?;
+                if format_tag == WAVE_FORMAT_EXTENSIBLE {
+                    // The real format is the first two bytes of the subformat GUID.
+                    ensure!(chunk_len >= 26, "WAV extensible fmt chunk too short");
+                    format_tag = read_u16(data, body + 24)?;
+                }
+                fmt = Some((
+                    format_tag,
+                    read_u16(data, body + 2)?,
+                    read_u32(data, body + 4)?,
+                    read_u16(data, body + 12)?,
+                    read_u16(data, body + 14)?,
+                ));
+            }
+            b"data" => {
+                let (format_tag, channels, sample_rate, block_align, bits_per_sample) =
+                    fmt.ok_or_else(|| anyhow!("WAV data chunk before fmt chunk"))?;
+                // Some writers leave the length unset when streaming, so clamp to what we have.
+                let end = body.saturating_add(chunk_len).min(data.len());
+                return Ok(WavInfo {
+                    format: sound_format(format_tag, channels, bits_per_sample)?,
+                    sample_rate,
+                    channels,
+                    bits_per_sample,
+                    block_align,
+                    data: body..end,
+                });
+            }
+            _ => {}
+        }
+        // Chunks are padded to an even length.
+        offset = body.saturating_add(chunk_len).saturating_add(chunk_len & 1);
+    }
+    bail!("WAV data has no data chunk")
+}
+
+fn sound_format(format_tag: u16, channels: u16, bits_per_sample: u16) -> Result<SoundFormat> {
+    Ok(match (format_tag, channels, bits_per_sample) {
+        (WAVE_FORMAT_PCM, 1, 8) => SoundFormat::kSound8bitMono,
+        (WAVE_FORMAT_PCM, 2, 8) => SoundFormat::kSound8bitStereo,
+        (WAVE_FORMAT_PCM, 1, 16) => SoundFormat::kSound16bitMono,
+        (WAVE_FORMAT_PCM, 2, 16) => SoundFormat::kSound16bitStereo,
+        (WAVE_FORMAT_IMA_ADPCM, 1, 4) => SoundFormat::kSoundADPCMMono,
+        (WAVE_FORMAT_IMA_ADPCM, 2, 4) => SoundFormat::kSoundADPCMStereo,
+        _ => bail!(
+            "Unsupported WAV format {:#x} with {} channels at {} bits",
+            format_tag,
+            channels,
+            bits_per_sample
+        ),
+    })
+}
+
+/// Converts the sample data in a WAV file, as described by `info`, to what
+/// `newSampleFromData` expects, and returns its range within `data`.  8 bit samples are
+/// flipped from unsigned to signed, and ADPCM data is prefixed with its block size, as in a
+/// compiled .pda file; the prefix overwrites the end of the data chunk's header.
+pub fn prepare_sample_data(data: &mut [u8], info: &WavInfo) -> Result<Range<usize>> {
+    ensure!(info.data.end <= data.len(), "WAV data range out of bounds");
+    match info.format {
+        SoundFormat::kSoundADPCMMono | SoundFormat::kSoundADPCMStereo => {
+            ensure!(info.data.start >= 2, "No room for the ADPCM block size");
+            let start = info.data.start - 2;
+            data[start..info.data.start].copy_from_slice(&info.block_align.to_le_bytes());
+            Ok(start..info.data.end)
+        }
+        SoundFormat::kSound8bitMono | SoundFormat::kSound8bitStereo => {
+            for byte in &mut data[info.data.clone()] {
+                *byte ^= 0x80;
+            }
+            Ok(info.data.clone())
+        }
+        SoundFormat::kSound16bitMono | SoundFormat::kSound16bitStereo => Ok(info.data.clone()),
+    }
+}
+
+/// Returns the number of bytes per frame for PCM formats, or None for ADPCM.
+pub fn bytes_per_frame(format: SoundFormat) -> Option<usize> {
+    match format {
+        SoundFormat::kSound8bitMono => Some(1),
+        SoundFormat::kSound8bitStereo | SoundFormat::kSound16bitMono => Some(2),
+        SoundFormat::kSound16bitStereo => Some(4),
+        SoundFormat::kSoundADPCMMono | SoundFormat::kSoundADPCMStereo => None,
+    }
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Result<u16> {
+    data.get(offset..offset + 2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .ok_or_else(|| anyhow!("WAV data truncated at {}", offset))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32> {
+    data.get(offset..offset + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| anyhow!("WAV data truncated at {}", offset))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    fn chunk(id: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut chunk = Vec::new();
+        chunk.extend_from_slice(id);
+        chunk.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        chunk.extend_from_slice(body);
+        if body.len() % 2 == 1 {
+            chunk.push(0);
+        }
+        chunk
+    }
+
+    fn fmt_body(format_tag: u16, channels: u16, rate: u32, block_align: u16, bits: u16) -> Vec<u8> {
+        let mut body = Vec::new();
+        body.extend_from_slice(&format_tag.to_le_bytes());
+        body.extend_from_slice(&channels.to_le_bytes());
+        body.extend_from_slice(&rate.to_le_bytes());
+        body.extend_from_slice(&(rate * block_align as u32).to_le_bytes());
+        body.extend_from_slice(&block_align.to_le_bytes());
+        body.extend_from_slice(&bits.to_le_bytes());
+        body
+    }
+
+    fn riff(chunks: &[Vec<u8>]) -> Vec<u8> {
+        let body: Vec<u8> = chunks.concat();
+        let mut wav = Vec::new();
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&(body.len() as u32 + 4).to_le_bytes());
+        wav.extend_from_slice(b"WAVE");
+        wav.extend_from_slice(&body);
+        wav
+    }
+
+    #[test]
+    fn parses_16_bit_pcm() {
+        let wav = riff(&[
+            chunk(b"fmt ", &fmt_body(WAVE_FORMAT_PCM, 2, 44100, 4, 16)),
+            chunk(b"data", &[1, 2, 3, 4, 5, 6, 7, 8]),
+        ]);
+        let info = parse_wav(&wav).unwrap();
+        assert_eq!(info.format, SoundFormat::kSound16bitStereo);
+        assert_eq!(info.sample_rate, 44100);
+        assert_eq!(info.channels, 2);
+        assert_eq!(info.frame_count(), Some(2));
+        assert_eq!(&wav[info.data.clone()], &[1, 2, 3, 4, 5, 6, 7, 8]);
+    }
+
+    #[test]
+    fn flips_8_bit_pcm_to_signed() {
+        let mut wav = riff(&[
+            chunk(b"fmt ", &fmt_body(WAVE_FORMAT_PCM, 1, 22050, 1, 8)),
+            chunk(b"data", &[0x80, 0xff, 0x00]),
+        ]);
+        let info = parse_wav(&wav).unwrap();
+        assert_eq!(info.format, SoundFormat::kSound8bitMono);
+        assert_eq!(info.frame_count(), Some(3));
+        let range = prepare_sample_data(&mut wav, &info).unwrap();
+        assert_eq!(&wav[range], &[0x00, 0x7f, 0x80]);
+    }
+
+    #[test]
+    fn prefixes_adpcm_with_block_size() {
+        let mut wav = riff(&[
+            chunk(b"fmt ", &fmt_body(WAVE_FORMAT_IMA_ADPCM, 1, 44100, 256, 4)),
+            chunk(b"data", &[9; 6]),
+        ]);
+        let info = parse_wav(&wav).unwrap();
+        assert_eq!(info.format, SoundFormat::kSoundADPCMMono);
+        assert_eq!(info.block_align, 256);
+        assert_eq!(info.frame_count(), None);
+        let range = prepare_sample_data(&mut wav, &info).unwrap();
+        assert_eq!(&wav[range], &[0, 1, 9, 9, 9, 9, 9, 9]);
+    }
+
+    #[test]
+    fn reads_extensible_format() {
+        let mut body = fmt_body(WAVE_FORMAT_EXTENSIBLE, 1, 8000, 2, 16);
+        body.extend_from_slice(&22u16.to_le_bytes());
+        body.extend_from_slice(&16u16.to_le_bytes());
+        body.extend_from_slice(&0u32.to_le_bytes());
+        body.extend_from_slice(&WAVE_FORMAT_PCM.to_le_bytes());
+        body.extend_from_slice(&[0; 14]);
+        let wav = riff(&[chunk(b"fmt ", &body), chunk(b"data", &[0; 4])]);
+        assert_eq!(
+            parse_wav(&wav).unwrap().format,
+            SoundFormat::kSound16bitMono
+        );
+    }
+
+    #[test]
+    fn skips_odd_length_chunks_with_padding() {
+        let wav = riff(&[
+            chunk(b"LIST", b"odd"),
+            chunk(b"fmt ", &fmt_body(WAVE_FORMAT_PCM, 1, 44100, 2, 16)),
+            chunk(b"junk", &[1]),
+            chunk(b"data", &[7, 7]),
+        ]);
+        let info = parse_wav(&wav).unwrap();
+        assert_eq!(&wav[info.data], &[7, 7]);
+    }
+
+    #[test]
+    fn clamps_data_length_to_file() {
+        let mut wav = riff(&[
+            chunk(b"fmt ", &fmt_body(WAVE_FORMAT_PCM, 1, 44100, 2, 16)),
+            chunk(b"data", &[1, 2, 3, 4]),
+        ]);
+        let len_offset = wav.len() - 8;
+        wav[len_offset..len_offset + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        let info = parse_wav(&wav).unwrap();
+        assert_eq!(info.data.end, wav.len());
+    }
+
+    #[test]
+    fn rejects_missing_fmt() {
+        let wav = riff(&[chunk(b"data", &[0; 4])]);
+        assert!(parse_wav(&wav).is_err());
+    }
+
+    #[test]
+    fn rejects_missing_data() {
+        let wav = riff(&[chunk(b"fmt ", &fmt_body(WAVE_FORMAT_PCM, 1, 44100, 2, 16))]);
+        assert!(parse_wav(&wav).is_err());
+    }
+
+    #[test]
+    fn rejects_truncated_headers() {
+        assert!(parse_wav(b"RIFF").is_err());
+        assert!(parse_wav(b"RIFF\0\0\0\0WAVX").is_err());
+        let wav = riff(&[chunk(b"fmt ", &fmt_body(WAVE_FORMAT_PCM, 1, 44100, 2, 16))]);
+        // Cut the fmt chunk off part way through its body.
+        assert!(parse_wav(&wav[..wav.len() - 6]).is_err());
+    }
+
+    #[test]
+    fn rejects_unsupported_formats() {
+        let wav = riff(&[
+            chunk(b"fmt ", &fmt_body(WAVE_FORMAT_PCM, 1, 44100, 3, 24)),
+            chunk(b"data", &[0; 6]),
+        ]);
+        assert!(parse_wav(&wav).is_err());
+    }
+}
@@ -2,7 +2,7 @@ extern crate alloc;
 
 use {
     crate::{
-        graphics::{Bitmap, Graphics, LCDBitmapDrawMode, LCDBitmapFlip, LCDColor, PDRect},
+        graphics::{Bitmap, Font, Graphics, LCDBitmapDrawMode, LCDBitmapFlip, LCDColor, PDRect},
         log_to_console, pd_func_caller, pd_func_caller_log,
         system::System,
         Playdate,
@@ -29,7 +29,14 @@ use {
 
 pub use crankstart_sys::SpriteCollisionResponseType;
 
-// Currently no font:getHeight in C API.
+pub mod animation;
+pub use animation::{AnimatedSprite, AnimationClip, AnimationFrame, PlaybackMode};
+pub mod aseprite;
+pub use aseprite::{
+    AsepriteDirection, AsepriteSheet, AsepriteSlice, AsepriteSliceKey, AsepriteTag,
+};
+
+// There's no C API to get the system font, so we can't ask for its height.
 const SYSTEM_FONT_HEIGHT: i32 = 18;
 
 pub type SpriteUpdateFunction = unsafe extern "C" fn(sprite: *mut crankstart_sys::LCDSprite);
@@ -47,8 +54,9 @@ pub trait SpriteCollider: Debug + 'static {
     fn response_type(&self, sprite: Sprite, other: Sprite) -> SpriteCollisionResponseType;
 }
 
+// Shared so that copies of a sprite can use the same collider.
 pub type SpriteCollisionResponses =
-    HashMap<*const crankstart_sys::LCDSprite, Box<dyn SpriteCollider>>;
+    HashMap<*const crankstart_sys::LCDSprite, Rc<dyn SpriteCollider>>;
 
 static mut SPRITE_COLLISION_RESPONSES: Option<SpriteCollisionResponses> = None;
 static mut SPRITE_MANAGER: Option<SpriteManager> = None;
@@ -116,6 +124,9 @@ pub struct SpriteInner {
     pub raw_sprite: *mut crankstart_sys::LCDSprite,
     playdate_sprite: *const playdate_sprite,
     image: Option<Bitmap>,
+    stencil: Option<Bitmap>,
+    // The SDK keeps a pointer to the pattern rather than copying it.
+    stencil_pattern: Option<Box<[u8; 8]>>,
     userdata: Option<Rc<dyn core::any::Any>>,
 }
 
@@ -152,7 +163,7 @@ impl SpriteInner {
         if let Some(response_type) = response_type {
             unsafe {
                 if let Some(collision_responses) = SPRITE_COLLISION_RESPONSES.as_mut() {
-                    collision_responses.insert(self.raw_sprite, response_type);
+                    collision_responses.insert(self.raw_sprite, Rc::from(response_type));
                 } else {
                     log_to_console!("Can't access SPRITE_COLLISION_RESPONSES");
                 }
@@ -312,6 +323,148 @@ impl SpriteInner {
         pd_func_caller!((*self.playdate_sprite).markDirty, self.raw_sprite,)
     }
 
+    pub fn set_size(&mut self, width: f32, height: f32) -> Result<(), Error> {
+        pd_func_caller!(
+            (*self.playdate_sprite).setSize,
+            self.raw_sprite,
+            width,
+            height
+        )
+    }
+
+    /// Sets where the sprite's position is within its bounds, from (0, 0) for the top left
+    /// to (1, 1) for the bottom right.  The default is (0.5, 0.5).
+    pub fn set_center(&mut self, x: f32, y: f32) -> Result<(), Error> {
+        pd_func_caller!((*self.playdate_sprite).setCenter, self.raw_sprite, x, y)
+    }
+
+    pub fn get_center(&self) -> Result<(f32, f32), Error> {
+        let mut x = 0.0;
+        let mut y = 0.0;
+        pd_func_caller!(
+            (*self.playdate_sprite).getCenter,
+            self.raw_sprite,
+            &mut x,
+            &mut y
+        )?;
+        Ok((x, y))
+    }
+
+    pub fn move_by(&mut self, dx: f32, dy: f32) -> Result<(), Error> {
+        pd_func_caller!((*self.playdate_sprite).moveBy, self.raw_sprite, dx, dy)
+    }
+
+    pub fn set_image_flip(&mut self, flip: LCDBitmapFlip) -> Result<(), Error> {
+        pd_func_caller!((*self.playdate_sprite).setImageFlip, self.raw_sprite, flip)
+    }
+
+    pub fn get_image_flip(&self) -> Result<LCDBitmapFlip, Error> {
+        pd_func_caller!((*self.playdate_sprite).getImageFlip, self.raw_sprite)
+    }
+
+    /// Only draws the part of the sprite inside `clip_rect`, in screen coordinates.
+    pub fn set_clip_rect(&mut self, clip_rect: LCDRect) -> Result<(), Error> {
+        pd_func_caller!(
+            (*self.playdate_sprite).setClipRect,
+            self.raw_sprite,
+            clip_rect
+        )
+    }
+
+    pub fn clear_clip_rect(&mut self) -> Result<(), Error> {
+        pd_func_caller!((*self.playdate_sprite).clearClipRect, self.raw_sprite)
+    }
+
+    /// Only draws the sprite where `stencil` has white pixels.
+    pub fn set_stencil(&mut self, stencil: Bitmap) -> Result<(), Error> {
+        pd_func_caller!(
+            (*self.playdate_sprite).setStencil,
+            self.raw_sprite,
+            stencil.inner.borrow().raw_bitmap,
+        )?;
+        self.stencil = Some(stencil);
+        self.stencil_pattern = None;
+        Ok(())
+    }
+
+    /// Like `set_stencil`, but if `tile` is set, repeats the stencil to cover the sprite.
+    pub fn set_stencil_image(&mut self, stencil: Bitmap, tile: bool) -> Result<(), Error> {
+        pd_func_caller!(
+            (*self.playdate_sprite).setStencilImage,
+            self.raw_sprite,
+            stencil.inner.borrow().raw_bitmap,
+            tile as i32,
+        )?;
+        self.stencil = Some(stencil);
+        self.stencil_pattern = None;
+        Ok(())
+    }
+
+    /// Only draws the sprite where the 8x8 `pattern`, one byte per row, has set bits.
+    pub fn set_stencil_pattern(&mut self, pattern: [u8; 8]) -> Result<(), Error> {
+        let mut pattern = Box::new(pattern);
+        pd_func_caller!(
+            (*self.playdate_sprite).setStencilPattern,
+            self.raw_sprite,
+            pattern.as_mut_ptr(),
+        )?;
+        self.stencil = None;
+        self.stencil_pattern = Some(pattern);
+        Ok(())
+    }
+
+    pub fn clear_stencil(&mut self) -> Result<(), Error> {
+        pd_func_caller!((*self.playdate_sprite).clearStencil, self.raw_sprite)?;
+        self.stencil = None;
+        self.stencil_pattern = None;
+        Ok(())
+    }
+
+    /// Sets whether `update_and_draw_sprites` calls the sprite's update function.
+    pub fn set_updates_enabled(&mut self, enabled: bool) -> Result<(), Error> {
+        pd_func_caller!(
+            (*self.playdate_sprite).setUpdatesEnabled,
+            self.raw_sprite,
+            enabled as i32
+        )
+    }
+
+    pub fn updates_enabled(&self) -> Result<bool, Error> {
+        let enabled = pd_func_caller!((*self.playdate_sprite).updatesEnabled, self.raw_sprite)?;
+        Ok(enabled != 0)
+    }
+
+    /// Sets whether the sprite takes part in collisions, without clearing its collide rect.
+    pub fn set_collisions_enabled(&mut self, enabled: bool) -> Result<(), Error> {
+        pd_func_caller!(
+            (*self.playdate_sprite).setCollisionsEnabled,
+            self.raw_sprite,
+            enabled as i32
+        )
+    }
+
+    pub fn collisions_enabled(&self) -> Result<bool, Error> {
+        let enabled = pd_func_caller!((*self.playdate_sprite).collisionsEnabled, self.raw_sprite)?;
+        Ok(enabled != 0)
+    }
+
+    /// Draws the sprite in screen coordinates, ignoring `Graphics::set_draw_offset`.
+    pub fn set_ignores_draw_offset(&mut self, ignores: bool) -> Result<(), Error> {
+        pd_func_caller!(
+            (*self.playdate_sprite).setIgnoresDrawOffset,
+            self.raw_sprite,
+            ignores as i32
+        )
+    }
+
+    pub fn get_collide_rect(&self) -> Result<PDRect, Error> {
+        pd_func_caller!((*self.playdate_sprite).getCollideRect, self.raw_sprite)
+    }
+
+    pub fn clear_collide_rect(&mut self) -> Result<(), Error> {
+        pd_func_caller!((*self.playdate_sprite).clearCollideRect, self.raw_sprite)
+    }
+
     pub fn get_userdata<T>(&self) -> Result<Option<Rc<T>>, Error>
     where
         T: 'static,
@@ -502,6 +655,151 @@ impl Sprite {
             .mark_dirty()
     }
 
+    pub fn set_size(&mut self, width: f32, height: f32) -> Result<(), Error> {
+        self.inner
+            .try_borrow_mut()
+            .map_err(Error::msg)?
+            .set_size(width, height)
+    }
+
+    /// Sets where the sprite's position is within its bounds, from (0, 0) for the top left
+    /// to (1, 1) for the bottom right.  The default is (0.5, 0.5).
+    pub fn set_center(&mut self, x: f32, y: f32) -> Result<(), Error> {
+        self.inner
+            .try_borrow_mut()
+            .map_err(Error::msg)?
+            .set_center(x, y)
+    }
+
+    pub fn get_center(&self) -> Result<(f32, f32), Error> {
+        self.inner.try_borrow().map_err(Error::msg)?.get_center()
+    }
+
+    pub fn move_by(&mut self, dx: f32, dy: f32) -> Result<(), Error> {
+        self.inner
+            .try_borrow_mut()
+            .map_err(Error::msg)?
+            .move_by(dx, dy)
+    }
+
+    pub fn set_image_flip(&mut self, flip: LCDBitmapFlip) -> Result<(), Error> {
+        self.inner
+            .try_borrow_mut()
+            .map_err(Error::msg)?
+            .set_image_flip(flip)
+    }
+
+    pub fn get_image_flip(&self) -> Result<LCDBitmapFlip, Error> {
+        self.inner
+            .try_borrow()
+            .map_err(Error::msg)?
+            .get_image_flip()
+    }
+
+    /// Only draws the part of the sprite inside `clip_rect`, in screen coordinates.
+    pub fn set_clip_rect(&mut self, clip_rect: LCDRect) -> Result<(), Error> {
+        self.inner
+            .try_borrow_mut()
+            .map_err(Error::msg)?
+            .set_clip_rect(clip_rect)
+    }
+
+    pub fn clear_clip_rect(&mut self) -> Result<(), Error> {
+        self.inner
+            .try_borrow_mut()
+            .map_err(Error::msg)?
+            .clear_clip_rect()
+    }
+
+    /// Only draws the sprite where `stencil` has white pixels.
+    pub fn set_stencil(&mut self, stencil: Bitmap) -> Result<(), Error> {
+        self.inner
+            .try_borrow_mut()
+            .map_err(Error::msg)?
+            .set_stencil(stencil)
+    }
+
+    /// Like `set_stencil`, but if `tile` is set, repeats the stencil to cover the sprite.
+    pub fn set_stencil_image(&mut self, stencil: Bitmap, tile: bool) -> Result<(), Error> {
+        self.inner
+            .try_borrow_mut()
+            .map_err(Error::msg)?
+            .set_stencil_image(stencil, tile)
+    }
+
+    /// Only draws the sprite where the 8x8 `pattern`, one byte per row, has set bits.
+    pub fn set_stencil_pattern(&mut self, pattern: [u8; 8]) -> Result<(), Error> {
+        self.inner
+            .try_borrow_mut()
+            .map_err(Error::msg)?
+            .set_stencil_pattern(pattern)
+    }
+
+    pub fn clear_stencil(&mut self) -> Result<(), Error> {
+        self.inner
+            .try_borrow_mut()
+            .map_err(Error::msg)?
+            .clear_stencil()
+    }
+
+    /// Sets whether `update_and_draw_sprites` calls the sprite's update function.
+    pub fn set_updates_enabled(&mut self, enabled: bool) -> Result<(), Error> {
+        self.inner
+            .try_borrow_mut()
+            .map_err(Error::msg)?
+            .set_updates_enabled(enabled)
+    }
+
+    pub fn updates_enabled(&self) -> Result<bool, Error> {
+        self.inner
+            .try_borrow()
+            .map_err(Error::msg)?
+            .updates_enabled()
+    }
+
+    /// Sets whether the sprite takes part in collisions, without clearing its collide rect.
+    pub fn set_collisions_enabled(&mut self, enabled: bool) -> Result<(), Error> {
+        self.inner
+            .try_borrow_mut()
+            .map_err(Error::msg)?
+            .set_collisions_enabled(enabled)
+    }
+
+    pub fn collisions_enabled(&self) -> Result<bool, Error> {
+        self.inner
+            .try_borrow()
+            .map_err(Error::msg)?
+            .collisions_enabled()
+    }
+
+    /// Draws the sprite in screen coordinates, ignoring `Graphics::set_draw_offset`.
+    pub fn set_ignores_draw_offset(&mut self, ignores: bool) -> Result<(), Error> {
+        self.inner
+            .try_borrow_mut()
+            .map_err(Error::msg)?
+            .set_ignores_draw_offset(ignores)
+    }
+
+    pub fn get_collide_rect(&self) -> Result<PDRect, Error> {
+        self.inner
+            .try_borrow()
+            .map_err(Error::msg)?
+            .get_collide_rect()
+    }
+
+    pub fn clear_collide_rect(&mut self) -> Result<(), Error> {
+        self.inner
+            .try_borrow_mut()
+            .map_err(Error::msg)?
+            .clear_collide_rect()
+    }
+
+    /// Creates a copy of the sprite, sharing its image, stencil, userdata and collider.  Like a
+    /// new sprite, the copy isn't drawn until it's passed to `SpriteManager::add_sprite`.
+    pub fn copy(&self) -> Result<Sprite, Error> {
+        SpriteManager::get_mut().copy_sprite(self)
+    }
+
     pub fn get_userdata<T>(&self) -> Result<Option<Rc<T>>, Error>
     where
         T: 'static,
@@ -570,16 +868,63 @@ impl SpriteManager {
                 raw_sprite,
                 playdate_sprite: self.playdate_sprite,
                 image: None,
+                stencil: None,
+                stencil_pattern: None,
                 userdata: None,
             };
             sprite.set_update_function(unsafe { SPRITE_UPDATE.expect("SPRITE_UPDATE") })?;
-            let sprite_ptr = Rc::new(RefCell::new(sprite));
-            let weak_ptr = Rc::downgrade(&sprite_ptr);
-            self.sprites.insert(raw_sprite, weak_ptr);
-            Ok(Sprite { inner: sprite_ptr })
+            Ok(self.register_sprite(sprite))
         }
     }
 
+    /// See `Sprite::copy`.
+    pub fn copy_sprite(&mut self, sprite: &Sprite) -> Result<Sprite, Error> {
+        let original = sprite.inner.try_borrow().map_err(Error::msg)?;
+        let raw_sprite = pd_func_caller!((*self.playdate_sprite).copy, original.raw_sprite)?;
+        if raw_sprite.is_null() {
+            return Err(anyhow!("copy sprite failed"));
+        }
+        let mut copy = SpriteInner {
+            raw_sprite,
+            playdate_sprite: self.playdate_sprite,
+            image: original.image.clone(),
+            stencil: original.stencil.clone(),
+            stencil_pattern: original.stencil_pattern.clone(),
+            userdata: original.userdata.clone(),
+        };
+        // Point the copy at its own pattern, so it outlives the original.
+        if let Some(pattern) = copy.stencil_pattern.take() {
+            copy.set_stencil_pattern(*pattern)?;
+        }
+        copy.set_update_function(unsafe { SPRITE_UPDATE.expect("SPRITE_UPDATE") })?;
+        let collider = unsafe {
+            SPRITE_COLLISION_RESPONSES
+                .as_ref()
+                .and_then(|collision_responses| {
+                    collision_responses
+                        .get(&(original.raw_sprite as *const crankstart_sys::LCDSprite))
+                        .cloned()
+                })
+        };
+        if let Some(collider) = collider {
+            unsafe {
+                if let Some(collision_responses) = SPRITE_COLLISION_RESPONSES.as_mut() {
+                    collision_responses.insert(raw_sprite, collider);
+                }
+            }
+            copy.set_collision_response_function(Some(get_sprite_collision_response))?;
+        }
+        Ok(self.register_sprite(copy))
+    }
+
+    fn register_sprite(&mut self, sprite: SpriteInner) -> Sprite {
+        let raw_sprite = sprite.raw_sprite;
+        let sprite_ptr = Rc::new(RefCell::new(sprite));
+        let weak_ptr = Rc::downgrade(&sprite_ptr);
+        self.sprites.insert(raw_sprite, weak_ptr);
+        Sprite { inner: sprite_ptr }
+    }
+
     pub fn add_sprite(&self, sprite: &Sprite) -> Result<(), Error> {
         pd_func_caller!(
             (*self.playdate_sprite).addSprite,
@@ -602,6 +947,29 @@ impl SpriteManager {
         pd_func_caller!((*Self::get_mut().playdate_sprite).addDirtyRect, dirty_rect)
     }
 
+    /// Only draws sprites with a z index from `start_z` to `end_z` inside `clip_rect`.
+    pub fn set_clip_rects_in_range(
+        &self,
+        clip_rect: LCDRect,
+        start_z: i32,
+        end_z: i32,
+    ) -> Result<(), Error> {
+        pd_func_caller!(
+            (*self.playdate_sprite).setClipRectsInRange,
+            clip_rect,
+            start_z,
+            end_z
+        )
+    }
+
+    pub fn clear_clip_rects_in_range(&self, start_z: i32, end_z: i32) -> Result<(), Error> {
+        pd_func_caller!(
+            (*self.playdate_sprite).clearClipRectsInRange,
+            start_z,
+            end_z
+        )
+    }
+
     pub fn get_sprite_static(raw_sprite: *const LCDSprite) -> Option<Sprite> {
         Self::get_mut().get_sprite(raw_sprite)
     }
@@ -626,16 +994,17 @@ impl SpriteManager {
 /// recommended way to display text when using sprites in your game; it removes timing issues and
 /// gives you the flexibility of the sprite system rather than draw_text alone.
 ///
-/// After creation with `new`, you can `update_text` as desired, and use `get_sprite` or
-/// `get_sprite_mut` to access the `Sprite` for other operations like `move_to` and `get_bounds`
-/// (which can tell you the height and width of the generated bitmap).
+/// After creation with `new` or `with_font`, you can `update_text` as desired, and use
+/// `get_sprite` or `get_sprite_mut` to access the `Sprite` for other operations like `move_to`
+/// and `get_bounds` (which can tell you the height and width of the generated bitmap).
 ///
-/// Note: it's assumed that you're using the system font and haven't changed its tracking; we have
-/// no way to retrieve the current font or tracking with C APIs.
+/// Note: there's no C API to get the system font, so `new` assumes it's still the current font
+/// and that it's 18 pixels tall; use `with_font` for any other font.
 #[derive(Clone, Debug)]
 pub struct TextSprite {
     sprite: Sprite,
     background: LCDColor,
+    font: Option<Font>,
 }
 
 impl TextSprite {
@@ -645,27 +1014,54 @@ impl TextSprite {
     where
         S: AsRef<str>,
     {
-        let text = text.as_ref();
-        let graphics = Graphics::get();
+        Self::create(text.as_ref(), background, None)
+    }
+
+    /// Like `new`, but draws and measures the text with `font`.
+    pub fn with_font<S>(text: S, font: Font, background: LCDColor) -> Result<Self, Error>
+    where
+        S: AsRef<str>,
+    {
+        Self::create(text.as_ref(), background, Some(font))
+    }
+
+    fn create(text: &str, background: LCDColor, font: Option<Font>) -> Result<Self, Error> {
         let sprite_manager = SpriteManager::get_mut();
+        let text_bitmap = Self::render(text, &background, font.as_ref())?;
+
+        let mut sprite = sprite_manager.new_sprite()?;
+        sprite.set_image(text_bitmap, LCDBitmapFlip::kBitmapUnflipped)?;
+        sprite_manager.add_sprite(&sprite)?;
 
-        // Currently no getTextTracking C API; assume none has been set.
-        let tracking = 0;
+        Ok(Self {
+            sprite,
+            background,
+            font,
+        })
+    }
 
-        let width = graphics.get_system_text_width(text, tracking)?;
+    fn render(text: &str, background: &LCDColor, font: Option<&Font>) -> Result<Bitmap, Error> {
+        let graphics = Graphics::get();
+        let tracking = graphics.get_text_tracking()?;
+
+        let (width, height) = match font {
+            Some(font) => (font.text_width(text, tracking)?, font.height()? as i32),
+            None => (
+                graphics.get_system_text_width(text, tracking)?,
+                SYSTEM_FONT_HEIGHT,
+            ),
+        };
 
-        let text_bitmap =
-            graphics.new_bitmap(size2(width, SYSTEM_FONT_HEIGHT), background.clone())?;
+        let text_bitmap = graphics.new_bitmap(size2(width, height), background.clone())?;
         graphics.with_context(Some(&text_bitmap), || {
+            // The font is part of the pushed context, so this doesn't leak out.
+            if let Some(font) = font {
+                graphics.set_font(font)?;
+            }
             graphics.draw_text(text, point2(0, 0))?;
             Ok(())
         })?;
-
-        let mut sprite = sprite_manager.new_sprite()?;
-        sprite.set_image(text_bitmap, LCDBitmapFlip::kBitmapUnflipped)?;
-        sprite_manager.add_sprite(&sprite)?;
-
-        Ok(Self { sprite, background })
+        Ok(text_bitmap)
     }
 
     pub fn get_sprite(&self) -> &Sprite {
@@ -676,27 +1072,17 @@ impl TextSprite {
         &mut self.sprite
     }
 
+    pub fn get_font(&self) -> Option<&Font> {
+        self.font.as_ref()
+    }
+
     /// Recreates the underlying bitmap with the given text; use `get_sprite().get_bounds()`
     /// to see the new size.
     pub fn update_text<S>(&mut self, text: S) -> Result<(), Error>
     where
         S: AsRef<str>,
     {
-        let text = text.as_ref();
-        let graphics = Graphics::get();
-
-        // Currently no getTextTracking C API; assume none has been set.
-        let tracking = 0;
-
-        let width = graphics.get_system_text_width(text, tracking)?;
-
-        let text_bitmap =
-            graphics.new_bitmap(size2(width, SYSTEM_FONT_HEIGHT), self.background.clone())?;
-        graphics.with_context(Some(&text_bitmap), || {
-            graphics.draw_text(text, point2(0, 0))?;
-            Ok(())
-        })?;
-
+        let text_bitmap = Self::render(text.as_ref(), &self.background, self.font.as_ref())?;
         self.sprite
             .set_image(text_bitmap, LCDBitmapFlip::kBitmapUnflipped)?;
 
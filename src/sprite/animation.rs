@@ -0,0 +1,318 @@
+//! Frame-by-frame sprite animation from a `BitmapTable`.
+//!
+//! An `AnimatedSprite` holds named `AnimationClip`s, each a list of table indexes with a
+//! duration per frame.  Playback advances by elapsed time rather than by update count, so
+//! animations keep their speed when the frame rate drops.
+
+use {
+    super::{Sprite, SpriteManager},
+    crate::{
+        graphics::{BitmapTable, LCDBitmapFlip},
+        system::System,
+    },
+    alloc::{boxed::Box, string::String, vec::Vec},
+    anyhow::{bail, ensure, Error},
+    hashbrown::HashMap,
+};
+
+/// What a clip does when it reaches its last frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlaybackMode {
+    /// Starts again from the first frame.
+    Loop,
+    /// Plays backwards to the first frame, then forwards again.
+    PingPong,
+    /// Stops on the last frame.
+    Once,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AnimationFrame {
+    /// Index of the frame's bitmap in the table.
+    pub index: usize,
+    pub duration_ms: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AnimationClip {
+    pub frames: Vec<AnimationFrame>,
+    pub mode: PlaybackMode,
+    /// How every frame of the clip is drawn, e.g. `kBitmapFlippedX` to reuse a right-facing
+    /// walk cycle for walking left.
+    pub flip: LCDBitmapFlip,
+}
+
+impl AnimationClip {
+    /// A looping, unflipped clip showing the table `indexes` for `duration_ms` each.
+    pub fn new<I>(indexes: I, duration_ms: usize) -> Self
+    where
+        I: IntoIterator<Item = usize>,
+    {
+        Self {
+            frames: indexes
+                .into_iter()
+                .map(|index| AnimationFrame { index, duration_ms })
+                .collect(),
+            mode: PlaybackMode::Loop,
+            flip: LCDBitmapFlip::kBitmapUnflipped,
+        }
+    }
+
+    pub fn with_mode(mut self, mode: PlaybackMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn with_flip(mut self, flip: LCDBitmapFlip) -> Self {
+        self.flip = flip;
+        self
+    }
+
+    /// The time to play every frame once.
+    pub fn duration_ms(&self) -> usize {
+        self.frames.iter().map(|frame| frame.duration_ms).sum()
+    }
+}
+
+type ClipEndCallback = Box<dyn FnMut(&str)>;
+
+/// A sprite that plays `AnimationClip`s from a `BitmapTable`.
+///
+/// After creation with `new`, add clips with `add_clip`, start one with `play`, and call
+/// `update` once a frame.  Use `get_sprite` or `get_sprite_mut` for other operations like
+/// `move_to`.
+pub struct AnimatedSprite {
+    sprite: Sprite,
+    table: BitmapTable,
+    clips: HashMap<String, AnimationClip>,
+    current: Option<String>,
+    // Position in the current clip's frame list, and how long it's been shown.
+    position: usize,
+    frame_elapsed_ms: usize,
+    // Whether a ping-pong clip is playing backwards.
+    reversed: bool,
+    playing: bool,
+    last_update_ms: Option<usize>,
+    // The table index and flip currently set on the sprite.
+    shown: Option<(usize, LCDBitmapFlip)>,
+    on_clip_end: Option<ClipEndCallback>,
+}
+
+impl AnimatedSprite {
+    /// Creates an `AnimatedSprite` showing the first bitmap in `table`, and adds the
+    /// underlying sprite to the `SpriteManager`.
+    pub fn new(table: BitmapTable) -> Result<Self, Error> {
+        let sprite_manager = SpriteManager::get_mut();
+        let sprite = sprite_manager.new_sprite()?;
+        let mut animated = Self {
+            sprite,
+            table,
+            clips: HashMap::new(),
+            current: None,
+            position: 0,
+            frame_elapsed_ms: 0,
+            reversed: false,
+            playing: false,
+            last_update_ms: None,
+            shown: None,
+            on_clip_end: None,
+        };
+        animated.show(0, LCDBitmapFlip::kBitmapUnflipped)?;
+        sprite_manager.add_sprite(&animated.sprite)?;
+        Ok(animated)
+    }
+
+    pub fn get_sprite(&self) -> &Sprite {
+        &self.sprite
+    }
+
+    pub fn get_sprite_mut(&mut self) -> &mut Sprite {
+        &mut self.sprite
+    }
+
+    pub fn get_table(&self) -> &BitmapTable {
+        &self.table
+    }
+
+    /// Adds a clip, replacing any with the same name.  Every frame must be in the table.
+    pub fn add_clip<S>(&mut self, name: S, clip: AnimationClip) -> Result<(), Error>
+    where
+        S: Into<String>,
+    {
+        let name = name.into();
+        let count = self.table.count()?;
+        ensure!(
+            !clip.frames.is_empty(),
+            "Animation clip {} has no frames",
+            name
+        );
+        if let Some(frame) = clip.frames.iter().find(|frame| frame.index >= count) {
+            bail!(
+                "Animation clip {} uses frame {}, but the table only has {}",
+                name,
+                frame.index,
+                count
+            );
+        }
+        if self.current.as_ref() == Some(&name) {
+            self.position = 0;
+            self.frame_elapsed_ms = 0;
+            self.reversed = false;
+        }
+        self.clips.insert(name, clip);
+        Ok(())
+    }
+
+    pub fn get_clip(&self, name: &str) -> Option<&AnimationClip> {
+        self.clips.get(name)
+    }
+
+    pub fn current_clip(&self) -> Option<&str> {
+        self.current.as_deref()
+    }
+
+    /// The table index of the frame being shown.
+    pub fn current_frame(&self) -> Option<usize> {
+        self.shown.map(|(index, _)| index)
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    /// Plays the named clip from its first frame, unless it's already playing.
+    pub fn play(&mut self, name: &str) -> Result<(), Error> {
+        if self.playing && self.current.as_deref() == Some(name) {
+            return Ok(());
+        }
+        self.restart(name)
+    }
+
+    /// Plays the named clip from its first frame, even if it's already playing.
+    pub fn restart(&mut self, name: &str) -> Result<(), Error> {
+        ensure!(
+            self.clips.contains_key(name),
+            "No animation clip named {}",
+            name
+        );
+        self.current = Some(String::from(name));
+        self.position = 0;
+        self.frame_elapsed_ms = 0;
+        self.reversed = false;
+        self.playing = true;
+        self.last_update_ms = None;
+        self.show_current()
+    }
+
+    /// Pauses on the current frame.
+    pub fn stop(&mut self) {
+        self.playing = false;
+    }
+
+    /// Continues a stopped clip from where it stopped.
+    pub fn resume(&mut self) {
+        if self.current.is_some() {
+            self.playing = true;
+            self.last_update_ms = None;
+        }
+    }
+
+    /// Calls `callback` with the clip's name when a clip reaches its end: after the last
+    /// frame of a `Once` clip, at every loop of a `Loop` clip, and each time a `PingPong`
+    /// clip gets back to its first frame.
+    pub fn on_clip_end<F>(&mut self, callback: Option<F>)
+    where
+        F: FnMut(&str) + 'static,
+    {
+        self.on_clip_end = callback.map(|cb| Box::new(cb) as ClipEndCallback);
+    }
+
+    /// Advances by the time since the last update.  Call once a frame.
+    pub fn update(&mut self) -> Result<(), Error> {
+        let now = System::get().get_current_time_milliseconds()?;
+        let elapsed = now.saturating_sub(self.last_update_ms.unwrap_or(now));
+        self.last_update_ms = Some(now);
+        self.advance(elapsed)
+    }
+
+    /// Advances by `elapsed_ms`, for games that keep their own clock.
+    pub fn advance(&mut self, elapsed_ms: usize) -> Result<(), Error> {
+        if !self.playing {
+            return Ok(());
+        }
+        let clips = &self.clips;
+        let Some(clip) = self.current.as_ref().and_then(|name| clips.get(name)) else {
+            return Ok(());
+        };
+        let last = clip.frames.len() - 1;
+        let mode = clip.mode;
+        let mut ended = 0;
+        self.frame_elapsed_ms += elapsed_ms;
+        loop {
+            // Zero-length frames are shown for a millisecond, so this always terminates.
+            let duration = clip.frames[self.position].duration_ms.max(1);
+            if self.frame_elapsed_ms < duration {
+                break;
+            }
+            self.frame_elapsed_ms -= duration;
+            match mode {
+                PlaybackMode::Loop if self.position == last => {
+                    self.position = 0;
+                    ended += 1;
+                }
+                PlaybackMode::Once if self.position == last => {
+                    self.frame_elapsed_ms = 0;
+                    self.playing = false;
+                    ended += 1;
+                    break;
+                }
+                PlaybackMode::PingPong if last == 0 => ended += 1,
+                PlaybackMode::PingPong if self.reversed => {
+                    self.position -= 1;
+                    if self.position == 0 {
+                        self.reversed = false;
+                        ended += 1;
+                    }
+                }
+                PlaybackMode::PingPong if self.position == last => {
+                    self.reversed = true;
+                    self.position -= 1;
+                    if self.position == 0 {
+                        self.reversed = false;
+                        ended += 1;
+                    }
+                }
+                _ => self.position += 1,
+            }
+        }
+        self.show_current()?;
+        if ended > 0 {
+            if let (Some(callback), Some(name)) = (self.on_clip_end.as_mut(), &self.current) {
+                for _ in 0..ended {
+                    callback(name);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn show_current(&mut self) -> Result<(), Error> {
+        let clips = &self.clips;
+        let Some(clip) = self.current.as_ref().and_then(|name| clips.get(name)) else {
+            return Ok(());
+        };
+        let frame = clip.frames[self.position.min(clip.frames.len() - 1)];
+        let flip = clip.flip;
+        self.show(frame.index, flip)
+    }
+
+    fn show(&mut self, index: usize, flip: LCDBitmapFlip) -> Result<(), Error> {
+        if self.shown == Some((index, flip)) {
+            return Ok(());
+        }
+        let bitmap = self.table.get_bitmap(index)?;
+        self.sprite.set_image(bitmap, flip)?;
+        self.shown = Some((index, flip));
+        Ok(())
+    }
+}
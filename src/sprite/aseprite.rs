@@ -0,0 +1,718 @@
+//! Animation clips and slices from the JSON data Aseprite exports alongside a sprite sheet.
+//!
+//! Export the sheet as an image table (e.g. `walk-table-32-32.png`) with "JSON Data" turned
+//! on, including tags and slices, and copy the `.json` into the game's assets.  Both the
+//! "Array" and "Hash" frame layouts are understood.  Parsing is plain Rust with no Playdate
+//! calls, so `AsepriteSheet::parse` also works off the device.
+
+use {
+    super::{AnimatedSprite, AnimationClip, AnimationFrame, PlaybackMode},
+    crate::{
+        file::FileSystem,
+        geometry::{ScreenPoint, ScreenRect},
+        graphics::{BitmapTable, LCDBitmapFlip},
+    },
+    alloc::{string::String, vec::Vec},
+    anyhow::{anyhow, bail, ensure, Error},
+    euclid::{point2, size2},
+};
+
+/// The direction a tag plays in, as set in Aseprite's tag properties.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AsepriteDirection {
+    Forward,
+    Reverse,
+    PingPong,
+    PingPongReverse,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AsepriteTag {
+    pub name: String,
+    /// The first and last frames, inclusive.
+    pub from: usize,
+    pub to: usize,
+    pub direction: AsepriteDirection,
+    /// How many times the tag plays, if it isn't set to repeat forever.
+    pub repeat: Option<usize>,
+}
+
+/// A slice's bounds from a given frame onward.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AsepriteSliceKey {
+    pub frame: usize,
+    pub bounds: ScreenRect,
+    /// The center of a nine-patch slice, relative to `bounds`.
+    pub center: Option<ScreenRect>,
+    /// The pivot point, relative to `bounds`.
+    pub pivot: Option<ScreenPoint>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AsepriteSlice {
+    pub name: String,
+    /// Sorted by frame.
+    pub keys: Vec<AsepriteSliceKey>,
+}
+
+impl AsepriteSlice {
+    /// The key in effect on `frame`, i.e. the last one set at or before it.
+    pub fn key_at(&self, frame: usize) -> Option<&AsepriteSliceKey> {
+        self.keys.iter().rev().find(|key| key.frame <= frame)
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AsepriteSheet {
+    /// How long each frame is shown, in table order.
+    pub frame_durations: Vec<usize>,
+    pub tags: Vec<AsepriteTag>,
+    pub slices: Vec<AsepriteSlice>,
+}
+
+impl AsepriteSheet {
+    /// Reads and parses the JSON file at `path`.
+    pub fn load(path: &str) -> Result<Self, Error> {
+        let json = FileSystem::get().read_file_as_string(path)?;
+        Self::parse(&json).map_err(|err| anyhow!("{}: {}", path, err))
+    }
+
+    /// Parses Aseprite's exported JSON.
+    pub fn parse(json: &str) -> Result<Self, Error> {
+        let root = Parser::new(json).parse_document()?;
+        let frames = match root.get("frames") {
+            Some(Value::Array(frames)) => frames.iter().collect::<Vec<_>>(),
+            Some(Value::Object(frames)) => frames.iter().map(|(_, frame)| frame).collect(),
+            _ => bail!("Aseprite data has no frames"),
+        };
+        let frame_durations = frames
+            .iter()
+            .map(|frame| frame.get("duration").map_or(Ok(100), Value::as_usize))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let meta = root.get("meta");
+        let mut tags = Vec::new();
+        for tag in meta
+            .and_then(|meta| meta.get("frameTags"))
+            .map_or(Ok(&[][..]), Value::as_array)?
+        {
+            let name = String::from(tag.field("name")?.as_str()?);
+            let from = tag.field("from")?.as_usize()?;
+            let to = tag.field("to")?.as_usize()?;
+            ensure!(
+                from <= to && to < frame_durations.len(),
+                "Tag {} covers frames {}-{}, but there are only {}",
+                name,
+                from,
+                to,
+                frame_durations.len()
+            );
+            let direction = match tag.get("direction").map_or(Ok("forward"), Value::as_str)? {
+                "forward" => AsepriteDirection::Forward,
+                "reverse" => AsepriteDirection::Reverse,
+                "pingpong" => AsepriteDirection::PingPong,
+                "pingpong_reverse" => AsepriteDirection::PingPongReverse,
+                other => bail!("Tag {} has unknown direction {}", name, other),
+            };
+            // Aseprite writes the repeat count as a string, and leaves it out (or writes 0)
+            // for tags that repeat forever.
+            let repeat = match tag.get("repeat") {
+                None => None,
+                Some(Value::String(count)) => Some(count.parse::<usize>().map_err(Error::msg)?),
+                Some(count) => Some(count.as_usize()?),
+            }
+            .filter(|&count| count > 0);
+            tags.push(AsepriteTag {
+                name,
+                from,
+                to,
+                direction,
+                repeat,
+            });
+        }
+
+        let mut slices = Vec::new();
+        for slice in meta
+            .and_then(|meta| meta.get("slices"))
+            .map_or(Ok(&[][..]), Value::as_array)?
+        {
+            let name = String::from(slice.field("name")?.as_str()?);
+            let mut keys = Vec::new();
+            for key in slice.field("keys")?.as_array()? {
+                keys.push(AsepriteSliceKey {
+                    frame: key.field("frame")?.as_usize()?,
+                    bounds: key.field("bounds")?.as_rect()?,
+                    center: key.get("center").map(Value::as_rect).transpose()?,
+                    pivot: key
+                        .get("pivot")
+                        .map(|pivot| -> Result<ScreenPoint, Error> {
+                            Ok(point2(
+                                pivot.field("x")?.as_i32()?,
+                                pivot.field("y")?.as_i32()?,
+                            ))
+                        })
+                        .transpose()?,
+                });
+            }
+            keys.sort_by_key(|key| key.frame);
+            slices.push(AsepriteSlice { name, keys });
+        }
+
+        Ok(Self {
+            frame_durations,
+            tags,
+            slices,
+        })
+    }
+
+    pub fn tag(&self, name: &str) -> Option<&AsepriteTag> {
+        self.tags.iter().find(|tag| tag.name == name)
+    }
+
+    pub fn slice(&self, name: &str) -> Option<&AsepriteSlice> {
+        self.slices.iter().find(|slice| slice.name == name)
+    }
+
+    /// The named slice's bounds on `frame`.
+    pub fn slice_bounds(&self, name: &str, frame: usize) -> Option<ScreenRect> {
+        self.slice(name)?.key_at(frame).map(|key| key.bounds)
+    }
+
+    /// The clip for a tag.  Reverse tags list their frames backwards; a tag set to play a
+    /// fixed number of times becomes a `Once` clip, since clips can't count repeats.
+    pub fn clip(&self, tag: &AsepriteTag) -> AnimationClip {
+        let frame = |index| AnimationFrame {
+            index,
+            duration_ms: self.frame_durations[index],
+        };
+        let frames = match tag.direction {
+            AsepriteDirection::Forward | AsepriteDirection::PingPong => {
+                (tag.from..=tag.to).map(frame).collect()
+            }
+            AsepriteDirection::Reverse | AsepriteDirection::PingPongReverse => {
+                (tag.from..=tag.to).rev().map(frame).collect()
+            }
+        };
+        let mode = match tag.direction {
+            _ if tag.repeat.is_some() => PlaybackMode::Once,
+            AsepriteDirection::PingPong | AsepriteDirection::PingPongReverse => {
+                PlaybackMode::PingPong
+            }
+            _ => PlaybackMode::Loop,
+        };
+        AnimationClip {
+            frames,
+            mode,
+            flip: LCDBitmapFlip::kBitmapUnflipped,
+        }
+    }
+
+    /// A clip for every tag, named after it.
+    pub fn clips(&self) -> impl Iterator<Item = (&str, AnimationClip)> + '_ {
+        self.tags
+            .iter()
+            .map(move |tag| (tag.name.as_str(), self.clip(tag)))
+    }
+
+    /// Creates an `AnimatedSprite` over `table`, which should be the image table exported with
+    /// this data, with a clip for every tag.
+    pub fn create_sprite(&self, table: BitmapTable) -> Result<AnimatedSprite, Error> {
+        let count = table.count()?;
+        ensure!(
+            count == self.frame_durations.len(),
+            "Aseprite data has {} frames, but the bitmap table has {}",
+            self.frame_durations.len(),
+            count
+        );
+        let mut sprite = AnimatedSprite::new(table)?;
+        for (name, clip) in self.clips() {
+            sprite.add_clip(name, clip)?;
+        }
+        Ok(sprite)
+    }
+}
+
+// Just enough JSON to read Aseprite's output: objects keep their key order, since the "Hash"
+// layout lists frames as object members.
+#[derive(Clone, Debug, PartialEq)]
+enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(members) => members
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    fn field(&self, key: &str) -> Result<&Value, Error> {
+        self.get(key)
+            .ok_or_else(|| anyhow!("Missing field {} in Aseprite data", key))
+    }
+
+    fn as_str(&self) -> Result<&str, Error> {
+        match self {
+            Value::String(s) => Ok(s),
+            _ => bail!("Expected a string in Aseprite data, found {:?}", self),
+        }
+    }
+
+    fn as_array(&self) -> Result<&[Value], Error> {
+        match self {
+            Value::Array(values) => Ok(values),
+            _ => bail!("Expected an array in Aseprite data, found {:?}", self),
+        }
+    }
+
+    fn as_i32(&self) -> Result<i32, Error> {
+        match self {
+            Value::Number(n) if (*n as i32) as f64 == *n => Ok(*n as i32),
+            _ => bail!("Expected an integer in Aseprite data, found {:?}", self),
+        }
+    }
+
+    fn as_usize(&self) -> Result<usize, Error> {
+        let n = self.as_i32()?;
+        ensure!(n >= 0, "Expected a non-negative integer, found {}", n);
+        Ok(n as usize)
+    }
+
+    fn as_rect(&self) -> Result<ScreenRect, Error> {
+        Ok(ScreenRect::new(
+            point2(self.field("x")?.as_i32()?, self.field("y")?.as_i32()?),
+            size2(self.field("w")?.as_i32()?, self.field("h")?.as_i32()?),
+        ))
+    }
+}
+
+// Aseprite nests a few levels deep; the limit keeps bad input from overflowing the stack.
+const MAX_DEPTH: usize = 32;
+
+struct Parser<'a> {
+    input: &'a str,
+    position: usize,
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(input: &'a str) -> Self {
+        Self {
+            input,
+            position: 0,
+            depth: 0,
+        }
+    }
+
+    fn parse_document(&mut self) -> Result<Value, Error> {
+        // Aseprite doesn't write a byte order mark, but editors sometimes add one.
+        if self.input.starts_with('\u{feff}') {
+            self.position = '\u{feff}'.len_utf8();
+        }
+        let value = self.parse_value()?;
+        self.skip_whitespace();
+        ensure!(
+            self.position == self.input.len(),
+            "Unexpected data after JSON value at byte {}",
+            self.position
+        );
+        Ok(value)
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.input.as_bytes().get(self.position).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.position += 1;
+        }
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), Error> {
+        self.skip_whitespace();
+        ensure!(
+            self.peek() == Some(byte),
+            "Expected '{}' at byte {} of JSON",
+            byte as char,
+            self.position
+        );
+        self.position += 1;
+        Ok(())
+    }
+
+    fn parse_value(&mut self) -> Result<Value, Error> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(b'{' | b'[') => {
+                ensure!(
+                    self.depth < MAX_DEPTH,
+                    "JSON nested too deeply at byte {}",
+                    self.position
+                );
+                self.depth += 1;
+                let value = if self.peek() == Some(b'{') {
+                    self.parse_object()
+                } else {
+                    self.parse_array()
+                };
+                self.depth -= 1;
+                value
+            }
+            Some(b'"') => Ok(Value::String(self.parse_string()?)),
+            Some(b't') => self.parse_literal("true", Value::Bool(true)),
+            Some(b'f') => self.parse_literal("false", Value::Bool(false)),
+            Some(b'n') => self.parse_literal("null", Value::Null),
+            Some(b'-' | b'0'..=b'9') => self.parse_number(),
+            Some(_) => bail!("Unexpected character at byte {} of JSON", self.position),
+            None => bail!("Unexpected end of JSON"),
+        }
+    }
+
+    fn parse_literal(&mut self, literal: &str, value: Value) -> Result<Value, Error> {
+        ensure!(
+            self.input[self.position..].starts_with(literal),
+            "Invalid literal at byte {} of JSON",
+            self.position
+        );
+        self.position += literal.len();
+        Ok(value)
+    }
+
+    fn parse_number(&mut self) -> Result<Value, Error> {
+        let start = self.position;
+        while matches!(
+            self.peek(),
+            Some(b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9')
+        ) {
+            self.position += 1;
+        }
+        let text = &self.input[start..self.position];
+        text.parse::<f64>()
+            .map(Value::Number)
+            .map_err(|_| anyhow!("Invalid number {} in JSON", text))
+    }
+
+    fn parse_string(&mut self) -> Result<String, Error> {
+        self.expect(b'"')?;
+        let mut result = String::new();
+        loop {
+            let rest = &self.input[self.position..];
+            let Some(c) = rest.chars().next() else {
+                bail!("Unterminated string in JSON");
+            };
+            self.position += c.len_utf8();
+            match c {
+                '"' => return Ok(result),
+                '\\' => {
+                    let escape = self
+                        .peek()
+                        .ok_or_else(|| anyhow!("Unterminated string in JSON"))?;
+                    self.position += 1;
+                    result.push(match escape {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => self.parse_unicode_escape()?,
+                        _ => bail!("Invalid escape at byte {} of JSON", self.position - 1),
+                    });
+                }
+                c => result.push(c),
+            }
+        }
+    }
+
+    // Parses the digits after `\u`, combining surrogate pairs.
+    fn parse_unicode_escape(&mut self) -> Result<char, Error> {
+        let high = self.parse_hex4()?;
+        let code = if (0xd800..0xdc00).contains(&high) {
+            ensure!(
+                self.input[self.position..].starts_with("\\u"),
+                "Unpaired surrogate in JSON string"
+            );
+            self.position += 2;
+            let low = self.parse_hex4()?;
+            ensure!(
+                (0xdc00..0xe000).contains(&low),
+                "Unpaired surrogate in JSON string"
+            );
+            0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00)
+        } else {
+            high
+        };
+        char::from_u32(code).ok_or_else(|| anyhow!("Invalid character escape in JSON string"))
+    }
+
+    fn parse_hex4(&mut self) -> Result<u32, Error> {
+        let digits = self
+            .input
+            .get(self.position..self.position + 4)
+            .ok_or_else(|| anyhow!("Truncated escape in JSON string"))?;
+        ensure!(
+            digits.bytes().all(|b| b.is_ascii_hexdigit()),
+            "Invalid escape \\u{} in JSON string",
+            digits
+        );
+        let code = u32::from_str_radix(digits, 16)
+            .map_err(|_| anyhow!("Invalid escape \\u{} in JSON string", digits))?;
+        self.position += 4;
+        Ok(code)
+    }
+
+    fn parse_array(&mut self) -> Result<Value, Error> {
+        self.expect(b'[')?;
+        let mut values = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b']') {
+            self.position += 1;
+            return Ok(Value::Array(values));
+        }
+        loop {
+            values.push(self.parse_value()?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.position += 1,
+                Some(b']') => {
+                    self.position += 1;
+                    return Ok(Value::Array(values));
+                }
+                _ => bail!("Expected ',' or ']' at byte {} of JSON", self.position),
+            }
+        }
+    }
+
+    fn parse_object(&mut self) -> Result<Value, Error> {
+        self.expect(b'{')?;
+        let mut members = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b'}') {
+            self.position += 1;
+            return Ok(Value::Object(members));
+        }
+        loop {
+            self.skip_whitespace();
+            let key = self.parse_string()?;
+            self.expect(b':')?;
+            members.push((key, self.parse_value()?));
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.position += 1,
+                Some(b'}') => {
+                    self.position += 1;
+                    return Ok(Value::Object(members));
+                }
+                _ => bail!("Expected ',' or '}}' at byte {} of JSON", self.position),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ARRAY: &str = r##"{
+        "frames": [
+            { "filename": "walk 0.aseprite", "frame": { "x": 0, "y": 0, "w": 32, "h": 32 }, "duration": 100 },
+            { "filename": "walk 1.aseprite", "frame": { "x": 32, "y": 0, "w": 32, "h": 32 }, "duration": 150 },
+            { "filename": "walk 2.aseprite", "frame": { "x": 64, "y": 0, "w": 32, "h": 32 } }
+        ],
+        "meta": {
+            "app": "https://www.aseprite.org/",
+            "frameTags": [
+                { "name": "walk", "from": 0, "to": 2, "direction": "pingpong" },
+                { "name": "wave", "from": 1, "to": 2, "direction": "reverse", "repeat": "2" },
+                { "name": "idle", "from": 0, "to": 0, "direction": "forward", "repeat": 0 },
+                { "name": "jump", "from": 0, "to": 1, "direction": "pingpong_reverse", "repeat": 3 }
+            ],
+            "slices": [
+                { "name": "hitbox", "color": "#0000ffff", "keys": [
+                    { "frame": 2, "bounds": { "x": 4, "y": 6, "w": 10, "h": 12 } },
+                    { "frame": 0, "bounds": { "x": 1, "y": 2, "w": 3, "h": 4 },
+                      "center": { "x": 1, "y": 1, "w": 1, "h": 2 }, "pivot": { "x": -1, "y": 2 } }
+                ] }
+            ]
+        }
+    }"##;
+
+    fn parse_err(json: &str) -> bool {
+        AsepriteSheet::parse(json).is_err()
+    }
+
+    fn string(json: &str) -> Result<String, Error> {
+        Parser::new(json).parse_string()
+    }
+
+    #[test]
+    fn array_frames() {
+        let sheet = AsepriteSheet::parse(ARRAY).unwrap();
+        assert_eq!(sheet.frame_durations, [100, 150, 100]);
+        assert_eq!(sheet.tags.len(), 4);
+    }
+
+    #[test]
+    fn hash_frames_keep_their_order() {
+        let sheet = AsepriteSheet::parse(
+            r#"{ "frames": {
+                "b 1.aseprite": { "duration": 50 },
+                "a 0.aseprite": { "duration": 60 }
+            } }"#,
+        )
+        .unwrap();
+        assert_eq!(sheet.frame_durations, [50, 60]);
+        assert!(sheet.tags.is_empty() && sheet.slices.is_empty());
+    }
+
+    #[test]
+    fn repeat_counts() {
+        let sheet = AsepriteSheet::parse(ARRAY).unwrap();
+        let repeats: Vec<_> = sheet.tags.iter().map(|tag| tag.repeat).collect();
+        assert_eq!(repeats, [None, Some(2), None, Some(3)]);
+        assert!(parse_err(
+            r#"{ "frames": [{}], "meta": { "frameTags": [
+                { "name": "t", "from": 0, "to": 0, "repeat": "forever" }
+            ] } }"#
+        ));
+    }
+
+    #[test]
+    fn clips_follow_direction_and_repeat() {
+        let sheet = AsepriteSheet::parse(ARRAY).unwrap();
+        let walk = sheet.clip(sheet.tag("walk").unwrap());
+        assert_eq!(walk.mode, PlaybackMode::PingPong);
+        let indices: Vec<_> = walk.frames.iter().map(|frame| frame.index).collect();
+        assert_eq!(indices, [0, 1, 2]);
+
+        let wave = sheet.clip(sheet.tag("wave").unwrap());
+        assert_eq!(wave.mode, PlaybackMode::Once);
+        let frames: Vec<_> = wave
+            .frames
+            .iter()
+            .map(|frame| (frame.index, frame.duration_ms))
+            .collect();
+        assert_eq!(frames, [(2, 100), (1, 150)]);
+
+        assert_eq!(
+            sheet.clip(sheet.tag("idle").unwrap()).mode,
+            PlaybackMode::Loop
+        );
+        assert_eq!(sheet.clips().count(), 4);
+    }
+
+    #[test]
+    fn slices_with_center_and_pivot() {
+        let sheet = AsepriteSheet::parse(ARRAY).unwrap();
+        let slice = sheet.slice("hitbox").unwrap();
+        assert_eq!(slice.keys.len(), 2);
+        let first = slice.key_at(1).unwrap();
+        assert_eq!(first.frame, 0);
+        assert_eq!(first.bounds, ScreenRect::new(point2(1, 2), size2(3, 4)));
+        assert_eq!(
+            first.center,
+            Some(ScreenRect::new(point2(1, 1), size2(1, 2)))
+        );
+        assert_eq!(first.pivot, Some(point2(-1, 2)));
+
+        let second = slice.key_at(5).unwrap();
+        assert_eq!((second.center, second.pivot), (None, None));
+        assert_eq!(
+            sheet.slice_bounds("hitbox", 2),
+            Some(ScreenRect::new(point2(4, 6), size2(10, 12)))
+        );
+        assert_eq!(sheet.slice_bounds("missing", 0), None);
+    }
+
+    #[test]
+    fn string_escapes() {
+        assert_eq!(
+            string(r#""a\"b\\c\/d\n\t\u00e9""#).unwrap(),
+            "a\"b\\c/d\n\t\u{e9}"
+        );
+        assert_eq!(string(r#""\ud83d\ude00!""#).unwrap(), "\u{1f600}!");
+        assert_eq!(string(r#""😀""#).unwrap(), "\u{1f600}");
+    }
+
+    #[test]
+    fn bad_string_escapes() {
+        // Unpaired high and low surrogates.
+        assert!(string(r#""\ud83d""#).is_err());
+        assert!(string(r#""\ud83dA""#).is_err());
+        assert!(string(r#""\ude00""#).is_err());
+        // Short, signed and unknown escapes, and unterminated strings.
+        assert!(string(r#""\u12""#).is_err());
+        assert!(string(r#""\u+041""#).is_err());
+        assert!(string(r#""\x""#).is_err());
+        assert!(string(r#""abc"#).is_err());
+        assert!(string(r#""\"#).is_err());
+    }
+
+    #[test]
+    fn byte_order_mark_is_skipped() {
+        let sheet = AsepriteSheet::parse("\u{feff}{\"frames\": [{\"duration\": 7}]}").unwrap();
+        assert_eq!(sheet.frame_durations, [7]);
+    }
+
+    #[test]
+    fn malformed_documents() {
+        assert!(parse_err(""));
+        assert!(parse_err("{"));
+        assert!(parse_err(r#"{ "frames": [] } x"#));
+        assert!(parse_err(r#"{ "frames": [1, ] }"#));
+        assert!(parse_err(r#"{ "frames": [{ "duration": 1.5 }] }"#));
+        assert!(parse_err(r#"{ "frames": [{ "duration": -1 }] }"#));
+        assert!(parse_err(r#"{ "frames": [{ "duration": tru }] }"#));
+        assert!(parse_err(r#"{ "frames": [{ "duration" 1 }] }"#));
+        assert!(parse_err(r#"{ "meta": {} }"#));
+        assert!(parse_err(r#"{ "frames": 3 }"#));
+    }
+
+    #[test]
+    fn invalid_tags_and_slices() {
+        let tag = |tag: &str| {
+            parse_err(&alloc::format!(
+                r#"{{ "frames": [{{}}, {{}}], "meta": {{ "frameTags": [{}] }} }}"#,
+                tag
+            ))
+        };
+        assert!(!tag(r#"{ "name": "ok", "from": 0, "to": 1 }"#));
+        assert!(tag(r#"{ "name": "late", "from": 0, "to": 2 }"#));
+        assert!(tag(r#"{ "name": "backwards", "from": 1, "to": 0 }"#));
+        assert!(tag(
+            r#"{ "name": "odd", "from": 0, "to": 1, "direction": "sideways" }"#
+        ));
+        assert!(tag(r#"{ "from": 0, "to": 1 }"#));
+        assert!(parse_err(
+            r#"{ "frames": [{}], "meta": { "slices": [
+                { "name": "s", "keys": [{ "frame": 0, "bounds": { "x": 0, "y": 0, "w": 1 } }] }
+            ] } }"#
+        ));
+    }
+
+    #[test]
+    fn nesting_is_limited() {
+        let nested = |depth: usize| {
+            let mut json = String::from(r#"{ "frames": [{}], "extra": "#);
+            (0..depth).for_each(|_| json.push('['));
+            (0..depth).for_each(|_| json.push(']'));
+            json.push('}');
+            AsepriteSheet::parse(&json)
+        };
+        assert!(nested(MAX_DEPTH - 1).is_ok());
+        assert!(nested(MAX_DEPTH).is_err());
+        assert!(nested(100_000).is_err());
+    }
+}
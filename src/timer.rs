@@ -0,0 +1,408 @@
+//! Timers like the Lua SDK's `playdate.timer` and `playdate.frameTimer`, ticked by
+//! `GameRunner::update` before `Game::update`.
+//!
+//! ```rust
+//! timer::after(2000, || log_to_console!("Two seconds later"));
+//! let blink = timer::every_frames(15, move || cursor.toggle());
+//! // Later:
+//! blink.remove();
+//!
+//! // Fade the music out over a second.
+//! Timer::new(1000)
+//!     .with_value(1.0, 0.0, Easing::Out(Curve::Quad))
+//!     .on_update(move |volume| {
+//!         player.set_volume(volume, volume).ok();
+//!     })
+//!     .start();
+//! ```
+//!
+//! Millisecond timers don't count time while the system menu is open, or while the game has
+//! called `Timers::pause_all`.
+
+use {
+    crate::{easing::Easing, system::System},
+    alloc::{boxed::Box, rc::Rc, vec::Vec},
+    anyhow::Error,
+    core::{
+        cell::{Cell, RefCell},
+        ptr::addr_of_mut,
+    },
+    crankstart_sys::PDSystemEvent,
+};
+
+static mut TIMERS: Option<Timers> = None;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum TimerUnit {
+    Milliseconds,
+    Frames,
+}
+
+#[derive(Default)]
+struct TimerShared {
+    paused: Cell<bool>,
+    removed: Cell<bool>,
+    finished: Cell<bool>,
+    value: Cell<f32>,
+}
+
+/// A handle to a started timer.  Dropping it does not remove the timer.
+#[derive(Clone)]
+pub struct TimerHandle(Rc<TimerShared>);
+
+impl TimerHandle {
+    /// Stops the timer without calling its end callback.
+    pub fn remove(&self) {
+        self.0.removed.set(true);
+    }
+
+    /// Stops the timer counting until `resume`.
+    pub fn pause(&self) {
+        self.0.paused.set(true);
+    }
+
+    pub fn resume(&self) {
+        self.0.paused.set(false);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.0.paused.get()
+    }
+
+    /// Returns whether a one-shot timer has fired, or the timer has been removed.
+    pub fn is_finished(&self) -> bool {
+        self.0.finished.get() || self.0.removed.get()
+    }
+
+    /// The timer's current value; see `Timer::with_value`.
+    pub fn value(&self) -> f32 {
+        self.0.value.get()
+    }
+}
+
+/// A timer to configure and `start`.  For simple cases, use `after`, `every`, `after_frames`
+/// or `every_frames`.
+pub struct Timer {
+    unit: TimerUnit,
+    duration: usize,
+    repeats: bool,
+    from: f32,
+    to: f32,
+    easing: Easing,
+    on_update: Option<Box<dyn FnMut(f32)>>,
+    on_end: Option<Box<dyn FnMut()>>,
+}
+
+impl Timer {
+    /// A timer that runs for `duration_ms` milliseconds.
+    pub fn new(duration_ms: usize) -> Self {
+        Self::with_unit(TimerUnit::Milliseconds, duration_ms)
+    }
+
+    /// A timer that runs for `frames` calls to `GameRunner::update`.
+    pub fn frames(frames: usize) -> Self {
+        Self::with_unit(TimerUnit::Frames, frames)
+    }
+
+    fn with_unit(unit: TimerUnit, duration: usize) -> Self {
+        Self {
+            unit,
+            duration,
+            repeats: false,
+            from: 0.0,
+            to: 1.0,
+            easing: Easing::Linear,
+            on_update: None,
+            on_end: None,
+        }
+    }
+
+    /// Starts again each time the duration is up, rather than finishing.
+    pub fn repeating(mut self) -> Self {
+        self.repeats = true;
+        self
+    }
+
+    /// Sets the value the timer moves between over its duration.  Without this, the value is
+    /// the linear progress from 0 to 1.
+    pub fn with_value(mut self, from: f32, to: f32, easing: Easing) -> Self {
+        self.from = from;
+        self.to = to;
+        self.easing = easing;
+        self
+    }
+
+    /// Calls `callback` with the timer's value every frame it runs.
+    pub fn on_update<F>(mut self, callback: F) -> Self
+    where
+        F: FnMut(f32) + 'static,
+    {
+        self.on_update = Some(Box::new(callback));
+        self
+    }
+
+    /// Calls `callback` each time the duration is up.
+    pub fn on_end<F>(mut self, callback: F) -> Self
+    where
+        F: FnMut() + 'static,
+    {
+        self.on_end = Some(Box::new(callback));
+        self
+    }
+
+    /// Starts the timer counting from now; it's first checked on the next frame.
+    pub fn start(self) -> TimerHandle {
+        Timers::get().start(self)
+    }
+}
+
+/// Calls `callback` once, after `delay_ms`.
+pub fn after<F>(delay_ms: usize, callback: F) -> TimerHandle
+where
+    F: FnMut() + 'static,
+{
+    Timer::new(delay_ms).on_end(callback).start()
+}
+
+/// Calls `callback` every `interval_ms`.
+pub fn every<F>(interval_ms: usize, callback: F) -> TimerHandle
+where
+    F: FnMut() + 'static,
+{
+    Timer::new(interval_ms).repeating().on_end(callback).start()
+}
+
+/// Calls `callback` once, after `frames` frames.
+pub fn after_frames<F>(frames: usize, callback: F) -> TimerHandle
+where
+    F: FnMut() + 'static,
+{
+    Timer::frames(frames).on_end(callback).start()
+}
+
+/// Calls `callback` every `frames` frames.
+pub fn every_frames<F>(frames: usize, callback: F) -> TimerHandle
+where
+    F: FnMut() + 'static,
+{
+    Timer::frames(frames).repeating().on_end(callback).start()
+}
+
+struct RunningTimer {
+    timer: Timer,
+    shared: Rc<TimerShared>,
+    // For millisecond timers, when the current period started on the timers' clock.
+    start_ms: usize,
+    frames: usize,
+    // When the handle paused it, on the timers' clock.
+    paused_at_ms: Option<usize>,
+}
+
+impl RunningTimer {
+    fn tick(&mut self, now_ms: usize) {
+        let shared = self.shared.clone();
+        match (shared.paused.get(), self.paused_at_ms) {
+            (true, None) => self.paused_at_ms = Some(now_ms),
+            (false, Some(paused_at)) => {
+                self.start_ms += now_ms.saturating_sub(paused_at);
+                self.paused_at_ms = None;
+            }
+            _ => {}
+        }
+        if shared.paused.get() {
+            return;
+        }
+
+        let timer = &mut self.timer;
+        // A repeating timer with no duration would fire forever.
+        let duration = if timer.repeats {
+            timer.duration.max(1)
+        } else {
+            timer.duration
+        };
+        let mut elapsed = match timer.unit {
+            TimerUnit::Milliseconds => now_ms.saturating_sub(self.start_ms),
+            TimerUnit::Frames => {
+                self.frames += 1;
+                self.frames
+            }
+        };
+        while elapsed >= duration {
+            if let Some(on_update) = timer.on_update.as_mut() {
+                shared.value.set(timer.to);
+                on_update(timer.to);
+            }
+            if let Some(on_end) = timer.on_end.as_mut() {
+                on_end();
+            }
+            if !timer.repeats || shared.removed.get() {
+                shared.value.set(timer.to);
+                shared.finished.set(true);
+                return;
+            }
+            elapsed -= duration;
+            match timer.unit {
+                TimerUnit::Milliseconds => self.start_ms += duration,
+                TimerUnit::Frames => self.frames -= duration,
+            }
+        }
+        let progress = if duration == 0 {
+            1.0
+        } else {
+            elapsed as f32 / duration as f32
+        };
+        let value = timer.easing.interpolate(timer.from, timer.to, progress);
+        shared.value.set(value);
+        if let Some(on_update) = timer.on_update.as_mut() {
+            on_update(value);
+        }
+    }
+}
+
+pub struct Timers {
+    timers: RefCell<Vec<Option<RunningTimer>>>,
+    system_paused: Cell<bool>,
+    game_paused: Cell<bool>,
+    // When the clock stopped, and how long it's been stopped in total, in system time.
+    paused_at_ms: Cell<Option<usize>>,
+    paused_total_ms: Cell<usize>,
+    // The timers' clock at the last frame, for when the current time can't be read.
+    now_ms: Cell<usize>,
+}
+
+impl Timers {
+    fn new() -> Self {
+        Self {
+            timers: RefCell::new(Vec::new()),
+            system_paused: Cell::new(false),
+            game_paused: Cell::new(false),
+            paused_at_ms: Cell::new(None),
+            paused_total_ms: Cell::new(0),
+            now_ms: Cell::new(0),
+        }
+    }
+
+    pub fn get() -> &'static Timers {
+        // Only touched from the game's thread, so nothing else holds a reference.
+        unsafe { (*addr_of_mut!(TIMERS)).get_or_insert_with(Timers::new) }
+    }
+
+    fn start(&self, timer: Timer) -> TimerHandle {
+        let shared = Rc::new(TimerShared::default());
+        shared.value.set(timer.from);
+        let running = RunningTimer {
+            timer,
+            shared: shared.clone(),
+            start_ms: self.clock_ms(),
+            frames: 0,
+            paused_at_ms: None,
+        };
+        let mut timers = self.timers.borrow_mut();
+        if let Some(slot) = timers.iter_mut().find(|slot| slot.is_none()) {
+            *slot = Some(running);
+        } else {
+            timers.push(Some(running));
+        }
+        TimerHandle(shared)
+    }
+
+    // The timers' clock: system time less time spent paused, standing still while paused.
+    fn clock_ms(&self) -> usize {
+        let now = match self.paused_at_ms.get() {
+            Some(paused_at) => Ok(paused_at),
+            None => System::get().get_current_time_milliseconds(),
+        };
+        now.map_or(self.now_ms.get(), |now| {
+            now.saturating_sub(self.paused_total_ms.get())
+        })
+    }
+
+    /// Returns the number of timers that haven't finished or been removed.
+    pub fn count(&self) -> usize {
+        self.timers
+            .borrow()
+            .iter()
+            .flatten()
+            .filter(|timer| !timer.shared.removed.get())
+            .count()
+    }
+
+    /// Removes every timer.
+    pub fn remove_all(&self) {
+        for timer in self.timers.borrow().iter().flatten() {
+            timer.shared.removed.set(true);
+        }
+    }
+
+    /// Stops all timers counting, e.g. while the game shows its own pause screen.
+    pub fn pause_all(&self) -> Result<(), Error> {
+        self.set_paused(&self.game_paused, true)
+    }
+
+    pub fn resume_all(&self) -> Result<(), Error> {
+        self.set_paused(&self.game_paused, false)
+    }
+
+    fn set_paused(&self, flag: &Cell<bool>, paused: bool) -> Result<(), Error> {
+        let was_paused = self.is_paused();
+        flag.set(paused);
+        if was_paused != self.is_paused() {
+            let now = System::get().get_current_time_milliseconds()?;
+            match self.paused_at_ms.take() {
+                Some(paused_at) => self
+                    .paused_total_ms
+                    .set(self.paused_total_ms.get() + now.saturating_sub(paused_at)),
+                None => self.paused_at_ms.set(Some(now)),
+            }
+        }
+        Ok(())
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.system_paused.get() || self.game_paused.get()
+    }
+
+    /// Pauses timers while the system menu is open.  Called by `GameRunner::handle_event`.
+    pub(crate) fn handle_event(&self, event: PDSystemEvent) -> Result<(), Error> {
+        match event {
+            PDSystemEvent::kEventPause => self.set_paused(&self.system_paused, true),
+            PDSystemEvent::kEventResume => self.set_paused(&self.system_paused, false),
+            _ => Ok(()),
+        }
+    }
+
+    /// Ticks every timer and drops finished ones.  Called by `GameRunner::update`.
+    pub(crate) fn run_frame(&self) -> Result<(), Error> {
+        if self.is_paused() {
+            return Ok(());
+        }
+        let now = System::get()
+            .get_current_time_milliseconds()?
+            .saturating_sub(self.paused_total_ms.get());
+        self.now_ms.set(now);
+
+        // Timers started from callbacks go into free slots or onto the end; only tick the
+        // ones that existed when we started.
+        let timer_count = self.timers.borrow().len();
+        for index in 0..timer_count {
+            // Take the timer out while its callbacks run, so they can start timers.
+            let timer = self.timers.borrow_mut()[index].take();
+            let Some(mut timer) = timer else {
+                continue;
+            };
+            if !timer.shared.removed.get() {
+                timer.tick(now);
+            }
+            if timer.shared.removed.get() || timer.shared.finished.get() {
+                continue;
+            }
+            let mut timers = self.timers.borrow_mut();
+            if timers[index].is_none() {
+                timers[index] = Some(timer);
+            } else {
+                timers.push(Some(timer));
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,8 @@
+//! UI components built on sprites, text layout and input.
+
+pub mod dialog;
+pub use dialog::{DialogBox, DialogState};
+pub mod grid;
+pub use grid::{GridIndex, GridView};
+pub mod keyboard;
+pub use keyboard::{Keyboard, KeyboardPage, KeyboardSounds};
@@ -0,0 +1,340 @@
+//! A dialogue box that types its text out a character at a time.
+//!
+//! Call `DialogBox::update` once a frame.  A or B while text is appearing shows the rest of the
+//! page; once the page is full, A moves to the next one, and after the last page A dismisses
+//! the box.  With the crank undocked, turning it scrolls back through what's been shown.
+
+use {
+    crate::{
+        geometry::{ScreenRect, ScreenSize},
+        graphics::{
+            Bitmap, FontFamily, Graphics, LCDBitmapFlip, LCDColor, LCDSolidColor, NineSlice,
+            TextLayout, TextLayoutOptions,
+        },
+        sprite::{Sprite, SpriteManager},
+        system::System,
+    },
+    alloc::{boxed::Box, string::String},
+    anyhow::Error,
+    crankstart_sys::PDButtons,
+    euclid::{point2, size2, vec2},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DialogState {
+    /// Characters are still appearing on the current page.
+    Revealing,
+    /// The page is full and there's more text; waiting for A.
+    PageComplete,
+    /// All the text is shown; waiting for A.
+    Complete,
+    /// The player dismissed the box after reading everything.
+    Dismissed,
+}
+
+pub struct DialogBox {
+    sprite: Sprite,
+    bitmap: Bitmap,
+    background: LCDColor,
+    frame: Option<NineSlice>,
+    padding: i32,
+    family: FontFamily,
+    options: TextLayoutOptions,
+    source: String,
+    layout: TextLayout,
+    state: DialogState,
+    // Byte offset into the layout's text of the first hidden character.
+    revealed: usize,
+    // Layout y of the top of the current page, and of what's currently in view.
+    page_top: i32,
+    view_top: i32,
+    chars_per_second: usize,
+    last_update_ms: Option<usize>,
+    pending_ms: usize,
+    crank_pixels_per_degree: f32,
+    crank_remainder: f32,
+    on_character: Option<Box<dyn FnMut(char)>>,
+    on_dismiss: Option<Box<dyn FnMut()>>,
+}
+
+impl DialogBox {
+    /// Creates a dialogue box of the given size showing `text`, and adds its sprite to the
+    /// `SpriteManager`.  `text` can use `TextLayout` markup.
+    pub fn new(text: &str, family: impl Into<FontFamily>, size: ScreenSize) -> Result<Self, Error> {
+        let graphics = Graphics::get();
+        let background = LCDColor::Solid(LCDSolidColor::kColorWhite);
+        let bitmap = graphics.new_bitmap(size, background.clone())?;
+        let family = family.into();
+        let options = TextLayoutOptions::default();
+        let layout = TextLayout::new("", family.clone(), options.clone())?;
+
+        let sprite_manager = SpriteManager::get_mut();
+        let mut sprite = sprite_manager.new_sprite()?;
+        sprite.set_image(bitmap.clone(), LCDBitmapFlip::kBitmapUnflipped)?;
+        sprite_manager.add_sprite(&sprite)?;
+
+        let mut dialog = Self {
+            sprite,
+            bitmap,
+            background,
+            frame: None,
+            padding: 4,
+            family,
+            options,
+            source: String::new(),
+            layout,
+            state: DialogState::Revealing,
+            revealed: 0,
+            page_top: 0,
+            view_top: 0,
+            chars_per_second: 30,
+            last_update_ms: None,
+            pending_ms: 0,
+            crank_pixels_per_degree: 0.25,
+            crank_remainder: 0.0,
+            on_character: None,
+            on_dismiss: None,
+        };
+        dialog.set_text(text)?;
+        Ok(dialog)
+    }
+
+    pub fn get_sprite(&self) -> &Sprite {
+        &self.sprite
+    }
+
+    /// The dialogue box's sprite, e.g. to `move_to` or `set_z_index` it.
+    pub fn get_sprite_mut(&mut self) -> &mut Sprite {
+        &mut self.sprite
+    }
+
+    pub fn state(&self) -> DialogState {
+        self.state
+    }
+
+    /// Replaces the text and starts revealing it from the beginning.
+    pub fn set_text(&mut self, text: &str) -> Result<(), Error> {
+        let mut options = self.options.clone();
+        options.max_width = Some(self.text_rect()?.size.width);
+        self.layout = TextLayout::new(text, self.family.clone(), options)?;
+        self.source = String::from(text);
+        self.state = DialogState::Revealing;
+        self.revealed = 0;
+        self.page_top = 0;
+        self.view_top = 0;
+        self.last_update_ms = None;
+        self.pending_ms = 0;
+        self.render()
+    }
+
+    /// Sets the alignment, tracking, leading and markup used for the text; `max_width` is
+    /// always the width inside the frame.  Restarts the text.
+    pub fn set_text_options(&mut self, options: TextLayoutOptions) -> Result<(), Error> {
+        self.options = options;
+        self.restart()
+    }
+
+    /// Draws `frame` around the box, with the text inside its content area.  Restarts the
+    /// text.
+    pub fn set_frame(&mut self, frame: Option<NineSlice>) -> Result<(), Error> {
+        self.frame = frame;
+        self.restart()
+    }
+
+    /// Sets the space between the frame and the text.  Restarts the text.
+    pub fn set_padding(&mut self, padding: i32) -> Result<(), Error> {
+        self.padding = padding;
+        self.restart()
+    }
+
+    pub fn set_background(&mut self, background: LCDColor) -> Result<(), Error> {
+        self.background = background;
+        self.render()
+    }
+
+    pub fn set_chars_per_second(&mut self, chars_per_second: usize) {
+        self.chars_per_second = chars_per_second.max(1);
+    }
+
+    /// Sets how far the text scrolls per degree of crank rotation.
+    pub fn set_crank_pixels_per_degree(&mut self, pixels: f32) {
+        self.crank_pixels_per_degree = pixels;
+    }
+
+    /// Calls `callback` as each non-whitespace character appears, e.g. to play a blip.
+    pub fn on_character<F>(&mut self, callback: Option<F>)
+    where
+        F: FnMut(char) + 'static,
+    {
+        self.on_character = callback.map(|cb| Box::new(cb) as Box<dyn FnMut(char)>);
+    }
+
+    /// Calls `callback` when the player dismisses the box after the last page.
+    pub fn on_dismiss<F>(&mut self, callback: Option<F>)
+    where
+        F: FnMut() + 'static,
+    {
+        self.on_dismiss = callback.map(|cb| Box::new(cb) as Box<dyn FnMut()>);
+    }
+
+    /// Reads input, reveals text and redraws if needed.  Call once a frame.
+    pub fn update(&mut self) -> Result<DialogState, Error> {
+        let system = System::get();
+        let (_, pushed, _) = system.get_button_state()?;
+        let a_pushed = (pushed & PDButtons::kButtonA) == PDButtons::kButtonA;
+        let b_pushed = (pushed & PDButtons::kButtonB) == PDButtons::kButtonB;
+        let now = system.get_current_time_milliseconds()?;
+        let elapsed = now.saturating_sub(self.last_update_ms.unwrap_or(now));
+        self.last_update_ms = Some(now);
+
+        let mut changed = false;
+        match self.state {
+            DialogState::Revealing => {
+                let count = if a_pushed || b_pushed {
+                    usize::MAX
+                } else {
+                    self.pending_ms += elapsed;
+                    let count = self.pending_ms * self.chars_per_second / 1000;
+                    self.pending_ms -= count * 1000 / self.chars_per_second;
+                    count
+                };
+                changed = self.reveal(count);
+            }
+            DialogState::PageComplete if a_pushed => {
+                self.page_top = self.line_top(self.revealed);
+                self.view_top = self.page_top;
+                self.state = DialogState::Revealing;
+                self.pending_ms = 0;
+                changed = true;
+            }
+            DialogState::Complete if a_pushed => {
+                self.state = DialogState::Dismissed;
+                if let Some(callback) = self.on_dismiss.as_mut() {
+                    callback();
+                }
+            }
+            _ => {}
+        }
+
+        if self.state != DialogState::Revealing && !system.is_crank_docked()? {
+            changed |= self.scroll_by_crank(system.get_crank_change()?)?;
+        }
+        if changed {
+            self.render()?;
+        }
+        Ok(self.state)
+    }
+
+    // Reveals up to `count` characters, stopping at the end of the page.  Returns whether
+    // anything new appeared.
+    fn reveal(&mut self, count: usize) -> bool {
+        let page_bottom = self.page_top + self.page_height();
+        let mut changed = false;
+        for _ in 0..count {
+            let Some(c) = self.layout.text()[self.revealed..].chars().next() else {
+                self.state = DialogState::Complete;
+                break;
+            };
+            let line = self.line_rect(self.revealed);
+            if line.max_y() > page_bottom && line.min_y() > self.page_top {
+                self.state = DialogState::PageComplete;
+                break;
+            }
+            self.revealed += c.len_utf8();
+            changed = true;
+            if !c.is_whitespace() {
+                if let Some(callback) = self.on_character.as_mut() {
+                    callback(c);
+                }
+            }
+        }
+        if self.revealed == self.layout.text().len() {
+            self.state = DialogState::Complete;
+        }
+        changed
+    }
+
+    fn scroll_by_crank(&mut self, degrees: f32) -> Result<bool, Error> {
+        let pixels = degrees * self.crank_pixels_per_degree + self.crank_remainder;
+        let whole = pixels as i32;
+        self.crank_remainder = pixels - whole as f32;
+        if whole == 0 {
+            return Ok(false);
+        }
+        // Only what's been revealed can be scrolled to.
+        let shown_bottom = if self.revealed == 0 {
+            0
+        } else {
+            self.line_rect(self.revealed - 1).max_y()
+        };
+        let max_top = (shown_bottom - self.page_height()).max(0);
+        let top = (self.view_top + whole).clamp(0, max_top);
+        let changed = top != self.view_top;
+        self.view_top = top;
+        Ok(changed)
+    }
+
+    // The rect of the line containing byte `index`, or of the next line if `index` falls in
+    // whitespace that was dropped when wrapping.
+    fn line_rect(&self, index: usize) -> ScreenRect {
+        self.layout
+            .lines()
+            .iter()
+            .find(|line| index < line.range.end || line.range.start >= index)
+            .or_else(|| self.layout.lines().last())
+            .map(|line| line.rect)
+            .unwrap_or_else(ScreenRect::zero)
+    }
+
+    fn line_top(&self, index: usize) -> i32 {
+        self.line_rect(index).min_y()
+    }
+
+    fn page_height(&self) -> i32 {
+        self.text_rect()
+            .map(|rect| rect.size.height)
+            .unwrap_or_default()
+    }
+
+    fn restart(&mut self) -> Result<(), Error> {
+        let source = core::mem::take(&mut self.source);
+        self.set_text(&source)
+    }
+
+    // The area inside the frame and padding that text is drawn in.
+    fn text_rect(&self) -> Result<ScreenRect, Error> {
+        let bounds = self.bounds()?;
+        let inner = match &self.frame {
+            Some(frame) => frame.content_rect(bounds),
+            None => bounds,
+        };
+        Ok(inner.inflate(-self.padding, -self.padding))
+    }
+
+    fn bounds(&self) -> Result<ScreenRect, Error> {
+        let data = self.bitmap.get_data()?;
+        Ok(ScreenRect::new(
+            point2(0, 0),
+            size2(data.width, data.height),
+        ))
+    }
+
+    fn render(&mut self) -> Result<(), Error> {
+        let graphics = Graphics::get();
+        let bounds = self.bounds()?;
+        let text_rect = self.text_rect()?;
+        self.bitmap.clear(self.background.clone())?;
+        graphics.with_context(Some(&self.bitmap), || {
+            if let Some(frame) = &self.frame {
+                frame.draw(bounds)?;
+            }
+            graphics.set_clip_rect(text_rect)?;
+            let origin = text_rect.origin - vec2(0, self.view_top);
+            // The layout draws into the current context, the dialog's bitmap.
+            let result = self.layout.draw_until(origin, self.revealed);
+            graphics.clear_clip_rect()?;
+            result
+        })?;
+        self.sprite.mark_dirty()
+    }
+}
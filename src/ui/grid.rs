@@ -0,0 +1,697 @@
+//! A scrolling grid of cells in sections, like the Lua SDK's `playdate.ui.gridview`; with
+//! one column it's a list.
+//!
+//! Layout, selection and scrolling are plain arithmetic on the `GridView`'s own state, so they
+//! work without a Playdate.  Only `update`, `handle_input`, the drawing functions and the
+//! sprite touch the API.
+
+use {
+    crate::{
+        geometry::{ScreenPoint, ScreenRect, ScreenSize, ScreenVector},
+        graphics::{Bitmap, Graphics, LCDBitmapFlip, LCDColor, LCDSolidColor},
+        sprite::{Sprite, SpriteManager},
+        system::System,
+    },
+    alloc::{boxed::Box, vec::Vec},
+    anyhow::Error,
+    core::ops::Range,
+    crankstart_sys::PDButtons,
+    euclid::{point2, size2, vec2},
+};
+
+/// A cell's position: row and column within a section.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct GridIndex {
+    pub section: usize,
+    pub row: usize,
+    pub column: usize,
+}
+
+impl GridIndex {
+    pub fn new(section: usize, row: usize, column: usize) -> Self {
+        Self {
+            section,
+            row,
+            column,
+        }
+    }
+}
+
+type DrawCell = Box<dyn FnMut(GridIndex, bool, ScreenRect) -> Result<(), Error>>;
+type DrawHeader = Box<dyn FnMut(usize, ScreenRect) -> Result<(), Error>>;
+
+pub struct GridView {
+    size: ScreenSize,
+    rows: Vec<usize>,
+    columns: usize,
+    cell_size: ScreenSize,
+    cell_padding: i32,
+    content_inset: i32,
+    header_height: i32,
+    selection: GridIndex,
+    wrap_selection: bool,
+    crank_degrees_per_row: f32,
+    crank_accumulator: f32,
+    scroll: f32,
+    scroll_from: f32,
+    scroll_to: i32,
+    scroll_started_ms: Option<usize>,
+    scroll_duration_ms: usize,
+    needs_display: bool,
+    draw_cell: DrawCell,
+    draw_header: Option<DrawHeader>,
+    background: LCDColor,
+    sprite: Option<(Sprite, Bitmap)>,
+}
+
+impl GridView {
+    /// Creates a view `size` pixels big with cells of `cell_size`.  A cell width of 0 divides
+    /// the view's width between the columns.  `draw_cell` is called with each visible cell's
+    /// index, whether it's selected, and its rect, with drawing clipped to that rect.
+    pub fn new<F>(size: ScreenSize, cell_size: ScreenSize, draw_cell: F) -> Self
+    where
+        F: FnMut(GridIndex, bool, ScreenRect) -> Result<(), Error> + 'static,
+    {
+        Self {
+            size,
+            rows: Vec::new(),
+            columns: 1,
+            cell_size,
+            cell_padding: 0,
+            content_inset: 0,
+            header_height: 0,
+            selection: GridIndex::default(),
+            wrap_selection: true,
+            crank_degrees_per_row: 30.0,
+            crank_accumulator: 0.0,
+            scroll: 0.0,
+            scroll_from: 0.0,
+            scroll_to: 0,
+            scroll_started_ms: None,
+            scroll_duration_ms: 250,
+            needs_display: true,
+            draw_cell: Box::new(draw_cell),
+            draw_header: None,
+            background: LCDColor::Solid(LCDSolidColor::kColorWhite),
+            sprite: None,
+        }
+    }
+
+    /// Sets the number of rows in each section.
+    pub fn set_rows(&mut self, rows_per_section: &[usize]) {
+        self.rows = rows_per_section.to_vec();
+        self.selection = self.clamped(self.selection);
+        self.scroll_to_selection(false);
+    }
+
+    pub fn set_columns(&mut self, columns: usize) {
+        self.columns = columns.max(1);
+        self.selection = self.clamped(self.selection);
+        self.scroll_to_selection(false);
+    }
+
+    pub fn set_size(&mut self, size: ScreenSize) {
+        self.size = size;
+        self.scroll_to_selection(false);
+    }
+
+    pub fn set_cell_size(&mut self, cell_size: ScreenSize) {
+        self.cell_size = cell_size;
+        self.scroll_to_selection(false);
+    }
+
+    /// Sets the space around each cell.
+    pub fn set_cell_padding(&mut self, padding: i32) {
+        self.cell_padding = padding;
+        self.scroll_to_selection(false);
+    }
+
+    /// Sets the space between the view's edges and the cells.
+    pub fn set_content_inset(&mut self, inset: i32) {
+        self.content_inset = inset;
+        self.scroll_to_selection(false);
+    }
+
+    /// Gives each section a header of `height` drawn by `draw_header`, or removes headers.
+    pub fn set_section_headers<F>(&mut self, height: i32, draw_header: Option<F>)
+    where
+        F: FnMut(usize, ScreenRect) -> Result<(), Error> + 'static,
+    {
+        self.draw_header = draw_header.map(|f| Box::new(f) as DrawHeader);
+        self.header_height = if self.draw_header.is_some() {
+            height
+        } else {
+            0
+        };
+        self.scroll_to_selection(false);
+    }
+
+    /// Whether moving past the last row or column wraps around to the first.
+    pub fn set_wrap_selection(&mut self, wrap: bool) {
+        self.wrap_selection = wrap;
+    }
+
+    /// Sets how far the crank turns to move the selection by one row.
+    pub fn set_crank_degrees_per_row(&mut self, degrees: f32) {
+        self.crank_degrees_per_row = degrees;
+    }
+
+    /// Sets how long scrolling to a new selection takes; 0 jumps straight there.
+    pub fn set_scroll_duration(&mut self, duration_ms: usize) {
+        self.scroll_duration_ms = duration_ms;
+    }
+
+    pub fn set_background(&mut self, background: LCDColor) {
+        self.background = background;
+        self.needs_display = true;
+    }
+
+    pub fn size(&self) -> ScreenSize {
+        self.size
+    }
+
+    pub fn selection(&self) -> GridIndex {
+        self.selection
+    }
+
+    /// Whether something changed that needs drawing.
+    pub fn needs_display(&self) -> bool {
+        self.needs_display
+    }
+
+    pub fn column_width(&self) -> i32 {
+        if self.cell_size.width > 0 {
+            self.cell_size.width + 2 * self.cell_padding
+        } else {
+            (self.size.width - 2 * self.content_inset) / self.columns as i32
+        }
+    }
+
+    pub fn row_height(&self) -> i32 {
+        self.cell_size.height + 2 * self.cell_padding
+    }
+
+    /// The top of `section`'s header in content coordinates.
+    pub fn section_top(&self, section: usize) -> i32 {
+        self.content_inset
+            + self.rows[..section.min(self.rows.len())]
+                .iter()
+                .map(|&rows| self.header_height + rows as i32 * self.row_height())
+                .sum::<i32>()
+    }
+
+    pub fn content_height(&self) -> i32 {
+        self.section_top(self.rows.len()) + self.content_inset
+    }
+
+    /// The rect of `section`'s header in content coordinates.
+    pub fn header_rect(&self, section: usize) -> ScreenRect {
+        ScreenRect::new(
+            point2(self.content_inset, self.section_top(section)),
+            size2(self.size.width - 2 * self.content_inset, self.header_height),
+        )
+    }
+
+    /// The rect of a cell, inside its padding, in content coordinates.
+    pub fn cell_rect(&self, index: GridIndex) -> ScreenRect {
+        let column_width = self.column_width();
+        let x = self.content_inset + index.column as i32 * column_width + self.cell_padding;
+        let y = self.section_top(index.section)
+            + self.header_height
+            + index.row as i32 * self.row_height()
+            + self.cell_padding;
+        ScreenRect::new(
+            point2(x, y),
+            size2(column_width - 2 * self.cell_padding, self.cell_size.height),
+        )
+    }
+
+    /// The largest scroll position; scrolling is clamped to `0..=max_scroll()`.
+    pub fn max_scroll(&self) -> i32 {
+        (self.content_height() - self.size.height).max(0)
+    }
+
+    /// The current scroll position, part way through any animation.
+    pub fn scroll_position(&self) -> i32 {
+        self.scroll as i32
+    }
+
+    /// Where the view is scrolling to.
+    pub fn scroll_target(&self) -> i32 {
+        self.scroll_to
+    }
+
+    /// Returns the cell at `point`, in view coordinates.
+    pub fn index_at(&self, point: ScreenPoint) -> Option<GridIndex> {
+        let y = point.y + self.scroll_position();
+        let section = (0..self.rows.len()).find(|&s| y < self.section_top(s + 1))?;
+        let row_y = y - self.section_top(section) - self.header_height;
+        if row_y < 0 {
+            return None;
+        }
+        let row = (row_y / self.row_height().max(1)) as usize;
+        let column_x = point.x - self.content_inset;
+        if column_x < 0 {
+            return None;
+        }
+        let column = (column_x / self.column_width().max(1)) as usize;
+        let index = GridIndex::new(section, row, column);
+        (row < self.rows[section]
+            && column < self.columns
+            && self.cell_rect(index).contains(point2(point.x, y)))
+        .then_some(index)
+    }
+
+    /// Selects `index` (clamped to an existing cell) and scrolls to show it.
+    pub fn set_selection(&mut self, index: GridIndex, animate: bool) {
+        self.selection = self.clamped(index);
+        self.needs_display = true;
+        self.scroll_to_selection(animate);
+    }
+
+    pub fn select_next_row(&mut self) {
+        let mut index = self.selection;
+        loop {
+            if index.row + 1 < self.rows_in(index.section) {
+                index.row += 1;
+                break;
+            }
+            match (index.section + 1..self.rows.len()).find(|&s| self.rows[s] > 0) {
+                Some(section) => {
+                    index.section = section;
+                    index.row = 0;
+                    break;
+                }
+                None if self.wrap_selection => {
+                    index.section = 0;
+                    index.row = 0;
+                    if self.rows_in(0) > 0 {
+                        break;
+                    }
+                }
+                None => return,
+            }
+            if index == self.selection {
+                return;
+            }
+        }
+        self.set_selection(index, true);
+    }
+
+    pub fn select_previous_row(&mut self) {
+        let mut index = self.selection;
+        if index.row > 0 {
+            index.row -= 1;
+        } else {
+            let previous = (0..index.section).rev().find(|&s| self.rows[s] > 0);
+            let section = match previous {
+                Some(section) => section,
+                None if self.wrap_selection => {
+                    match (0..self.rows.len()).rev().find(|&s| self.rows[s] > 0) {
+                        Some(section) => section,
+                        None => return,
+                    }
+                }
+                None => return,
+            };
+            index.section = section;
+            index.row = self.rows[section] - 1;
+        }
+        self.set_selection(index, true);
+    }
+
+    pub fn select_next_column(&mut self) {
+        let mut index = self.selection;
+        if index.column + 1 < self.columns {
+            index.column += 1;
+        } else if self.wrap_selection {
+            index.column = 0;
+        } else {
+            return;
+        }
+        self.set_selection(index, true);
+    }
+
+    pub fn select_previous_column(&mut self) {
+        let mut index = self.selection;
+        if index.column > 0 {
+            index.column -= 1;
+        } else if self.wrap_selection {
+            index.column = self.columns - 1;
+        } else {
+            return;
+        }
+        self.set_selection(index, true);
+    }
+
+    /// Moves the selection by one row per `crank_degrees_per_row` of crank rotation,
+    /// remembering partial turns.  Returns whether the selection moved.
+    pub fn apply_crank(&mut self, degrees: f32) -> bool {
+        if self.crank_degrees_per_row <= 0.0 {
+            return false;
+        }
+        self.crank_accumulator += degrees;
+        let before = self.selection;
+        while self.crank_accumulator >= self.crank_degrees_per_row {
+            self.crank_accumulator -= self.crank_degrees_per_row;
+            self.select_next_row();
+        }
+        while self.crank_accumulator <= -self.crank_degrees_per_row {
+            self.crank_accumulator += self.crank_degrees_per_row;
+            self.select_previous_row();
+        }
+        self.selection != before
+    }
+
+    /// Scrolls to `position`, clamped to the content.
+    pub fn set_scroll_position(&mut self, position: i32, animate: bool) {
+        let target = position.clamp(0, self.max_scroll());
+        if animate && self.scroll_duration_ms > 0 {
+            self.scroll_from = self.scroll;
+            self.scroll_started_ms = None;
+        } else {
+            self.scroll = target as f32;
+            self.scroll_from = self.scroll;
+        }
+        if target != self.scroll_to || !animate {
+            self.needs_display = true;
+        }
+        self.scroll_to = target;
+    }
+
+    /// Advances any scroll animation to `now_ms`; the first call after a scroll starts starts
+    /// its clock.  Returns whether the position changed.
+    pub fn animate_scroll(&mut self, now_ms: usize) -> bool {
+        let target = self.scroll_to as f32;
+        if self.scroll == target {
+            self.scroll_started_ms = None;
+            return false;
+        }
+        let start = *self.scroll_started_ms.get_or_insert(now_ms);
+        let elapsed = now_ms.saturating_sub(start);
+        let before = self.scroll_position();
+        if elapsed >= self.scroll_duration_ms {
+            self.scroll = target;
+        } else {
+            // Ease out (cubic), so scrolling starts fast and settles gently.
+            let t = 1.0 - elapsed as f32 / self.scroll_duration_ms as f32;
+            let eased = 1.0 - t * t * t;
+            self.scroll = self.scroll_from + (target - self.scroll_from) * eased;
+        }
+        let changed = self.scroll_position() != before || self.scroll == target;
+        self.needs_display |= changed;
+        changed
+    }
+
+    /// Moves the selection with the D-pad and crank.  Returns whether it moved.
+    pub fn handle_input(&mut self) -> Result<bool, Error> {
+        let system = System::get();
+        let (_, pushed, _) = system.get_button_state()?;
+        let before = self.selection;
+        let is_pushed = |button: PDButtons| (pushed & button) == button;
+        if is_pushed(PDButtons::kButtonDown) {
+            self.select_next_row();
+        }
+        if is_pushed(PDButtons::kButtonUp) {
+            self.select_previous_row();
+        }
+        if is_pushed(PDButtons::kButtonRight) {
+            self.select_next_column();
+        }
+        if is_pushed(PDButtons::kButtonLeft) {
+            self.select_previous_column();
+        }
+        if !system.is_crank_docked()? {
+            self.apply_crank(system.get_crank_change()?);
+        }
+        Ok(self.selection != before)
+    }
+
+    /// Handles input, advances scrolling and redraws the sprite if there is one and something
+    /// changed.  Call once a frame.
+    pub fn update(&mut self) -> Result<(), Error> {
+        self.handle_input()?;
+        self.animate_scroll(System::get().get_current_time_milliseconds()?);
+        if self.needs_display && self.sprite.is_some() {
+            self.render_sprite()?;
+        }
+        Ok(())
+    }
+
+    /// Draws the visible headers and cells with the view's top left at `origin` in the current
+    /// drawing context.
+    pub fn draw(&mut self, origin: ScreenPoint) -> Result<(), Error> {
+        let scroll = self.scroll_position();
+        let view = ScreenRect::new(origin, self.size);
+        let result = self.draw_sections(view, vec2(origin.x, origin.y - scroll));
+        self.needs_display = false;
+        result
+    }
+
+    /// Clears `bitmap` to the background and draws the view into it at its top left.
+    pub fn draw_into(&mut self, bitmap: &Bitmap) -> Result<(), Error> {
+        bitmap.clear(self.background.clone())?;
+        let graphics = Graphics::get();
+        graphics.with_context(Some(bitmap), || self.draw(point2(0, 0)))
+    }
+
+    /// Creates a sprite showing the view, adds it to the `SpriteManager`, and keeps it drawn
+    /// from `update`.
+    pub fn create_sprite(&mut self) -> Result<&mut Sprite, Error> {
+        let bitmap = Graphics::get().new_bitmap(self.size, self.background.clone())?;
+        let sprite_manager = SpriteManager::get_mut();
+        let mut sprite = sprite_manager.new_sprite()?;
+        sprite.set_image(bitmap.clone(), LCDBitmapFlip::kBitmapUnflipped)?;
+        sprite_manager.add_sprite(&sprite)?;
+        self.sprite = Some((sprite, bitmap));
+        self.render_sprite()?;
+        Ok(&mut self.sprite.as_mut().expect("sprite").0)
+    }
+
+    pub fn get_sprite_mut(&mut self) -> Option<&mut Sprite> {
+        self.sprite.as_mut().map(|(sprite, _)| sprite)
+    }
+
+    fn render_sprite(&mut self) -> Result<(), Error> {
+        if let Some((mut sprite, bitmap)) = self.sprite.take() {
+            let result = self.draw_into(&bitmap);
+            let marked = sprite.mark_dirty();
+            self.sprite = Some((sprite, bitmap));
+            result?;
+            marked?;
+        }
+        Ok(())
+    }
+
+    // Draws each visible header and cell clipped to `view`, offset from content coordinates
+    // by `offset`.
+    fn draw_sections(&mut self, view: ScreenRect, offset: ScreenVector) -> Result<(), Error> {
+        let graphics = Graphics::get();
+        for section in 0..self.rows.len() {
+            if self.header_height > 0 {
+                let rect = self.header_rect(section).translate(offset);
+                if let (Some(draw_header), Some(clip)) =
+                    (self.draw_header.as_mut(), rect.intersection(&view))
+                {
+                    graphics.set_clip_rect(clip)?;
+                    draw_header(section, rect)?;
+                }
+            }
+            for row in self.visible_rows(section) {
+                for column in 0..self.columns {
+                    let index = GridIndex::new(section, row, column);
+                    let rect = self.cell_rect(index).translate(offset);
+                    if let Some(clip) = rect.intersection(&view) {
+                        graphics.set_clip_rect(clip)?;
+                        (self.draw_cell)(index, index == self.selection, rect)?;
+                    }
+                }
+            }
+        }
+        graphics.clear_clip_rect()
+    }
+
+    // The rows of `section` that overlap the view at the current scroll position.
+    fn visible_rows(&self, section: usize) -> Range<usize> {
+        let scroll = self.scroll_position();
+        let first_y = self.section_top(section) + self.header_height;
+        let row_height = self.row_height().max(1);
+        let first_row = ((scroll - first_y) / row_height).max(0) as usize;
+        let last_row = ((scroll + self.size.height - first_y + row_height - 1) / row_height)
+            .clamp(0, self.rows_in(section) as i32) as usize;
+        first_row..last_row.max(first_row)
+    }
+
+    fn rows_in(&self, section: usize) -> usize {
+        self.rows.get(section).copied().unwrap_or(0)
+    }
+
+    fn clamped(&self, index: GridIndex) -> GridIndex {
+        let section = index.section.min(self.rows.len().saturating_sub(1));
+        GridIndex {
+            section,
+            row: index.row.min(self.rows_in(section).saturating_sub(1)),
+            column: index.column.min(self.columns - 1),
+        }
+    }
+
+    // Scrolls as little as possible to show the selected cell, and its section's header if
+    // it's in the first row.
+    fn scroll_to_selection(&mut self, animate: bool) {
+        if self.rows_in(self.selection.section) == 0 {
+            self.set_scroll_position(self.scroll_to, animate);
+            return;
+        }
+        let cell = self.cell_rect(self.selection);
+        let mut top = cell.min_y() - self.cell_padding;
+        if self.selection.row == 0 {
+            top = self.section_top(self.selection.section);
+        }
+        let bottom = cell.max_y() + self.cell_padding;
+        let mut target = self.scroll_to;
+        if bottom > target + self.size.height {
+            target = bottom - self.size.height;
+        }
+        if top < target {
+            target = top;
+        }
+        self.set_scroll_position(target, animate);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A 100x60 list with 20 pixel rows.
+    fn grid(rows: &[usize]) -> GridView {
+        let mut grid = GridView::new(size2(100, 60), size2(0, 20), |_, _, _| Ok(()));
+        grid.set_rows(rows);
+        grid
+    }
+
+    #[test]
+    fn scrolling_is_clamped_to_the_content() {
+        assert_eq!(grid(&[2]).max_scroll(), 0);
+        let mut grid = grid(&[10]);
+        assert_eq!(grid.max_scroll(), 140);
+        grid.set_scroll_position(500, false);
+        assert_eq!(grid.scroll_position(), 140);
+        grid.set_scroll_position(-5, false);
+        assert_eq!(grid.scroll_position(), 0);
+    }
+
+    #[test]
+    fn scrolls_as_little_as_possible_to_show_the_selection() {
+        let mut grid = grid(&[10]);
+        grid.set_selection(GridIndex::new(0, 5, 0), false);
+        assert_eq!(grid.scroll_target(), 60);
+        // Already fully visible, so no scrolling.
+        grid.set_selection(GridIndex::new(0, 4, 0), false);
+        assert_eq!(grid.scroll_target(), 60);
+        grid.set_selection(GridIndex::new(0, 1, 0), false);
+        assert_eq!(grid.scroll_target(), 20);
+        // Out of range selections are clamped to the last cell.
+        grid.set_selection(GridIndex::new(3, 50, 2), false);
+        assert_eq!(grid.selection(), GridIndex::new(0, 9, 0));
+        assert_eq!(grid.scroll_target(), 140);
+    }
+
+    #[test]
+    fn first_row_scrolls_its_header_into_view() {
+        let mut grid = grid(&[3, 3]);
+        grid.set_section_headers(10, Some(|_, _| Ok(())));
+        grid.set_selection(GridIndex::new(1, 2, 0), false);
+        assert_eq!(grid.scroll_target(), 80);
+        grid.set_selection(GridIndex::new(1, 0, 0), false);
+        assert_eq!(grid.scroll_target(), 70);
+    }
+
+    #[test]
+    fn animated_scrolling_reaches_the_target() {
+        let mut grid = grid(&[10]);
+        grid.set_scroll_duration(100);
+        grid.set_selection(GridIndex::new(0, 9, 0), true);
+        assert_eq!((grid.scroll_position(), grid.scroll_target()), (0, 140));
+        grid.animate_scroll(1000);
+        assert!(grid.animate_scroll(1050));
+        assert!((0..140).contains(&grid.scroll_position()));
+        grid.animate_scroll(1100);
+        assert_eq!(grid.scroll_position(), 140);
+        assert!(!grid.animate_scroll(1200));
+    }
+
+    #[test]
+    fn next_row_skips_empty_sections_and_wraps() {
+        let mut grid = grid(&[2, 0, 3]);
+        grid.set_selection(GridIndex::new(0, 1, 0), false);
+        grid.select_next_row();
+        assert_eq!(grid.selection(), GridIndex::new(2, 0, 0));
+        grid.set_selection(GridIndex::new(2, 2, 0), false);
+        grid.select_next_row();
+        assert_eq!(grid.selection(), GridIndex::new(0, 0, 0));
+
+        grid.set_wrap_selection(false);
+        grid.set_selection(GridIndex::new(2, 2, 0), false);
+        grid.select_next_row();
+        assert_eq!(grid.selection(), GridIndex::new(2, 2, 0));
+    }
+
+    #[test]
+    fn previous_row_skips_empty_sections_and_wraps() {
+        let mut grid = grid(&[2, 0, 3]);
+        grid.set_selection(GridIndex::new(2, 0, 0), false);
+        grid.select_previous_row();
+        assert_eq!(grid.selection(), GridIndex::new(0, 1, 0));
+        grid.set_selection(GridIndex::new(0, 0, 0), false);
+        grid.select_previous_row();
+        assert_eq!(grid.selection(), GridIndex::new(2, 2, 0));
+
+        grid.set_wrap_selection(false);
+        grid.set_selection(GridIndex::new(0, 0, 0), false);
+        grid.select_previous_row();
+        assert_eq!(grid.selection(), GridIndex::new(0, 0, 0));
+    }
+
+    #[test]
+    fn moving_through_empty_sections_does_nothing() {
+        let mut grid = grid(&[0, 0]);
+        grid.select_next_row();
+        grid.select_previous_row();
+        assert_eq!(grid.selection(), GridIndex::default());
+    }
+
+    #[test]
+    fn visible_rows_cover_the_view() {
+        let mut grid = grid(&[10]);
+        assert_eq!(grid.visible_rows(0), 0..3);
+        grid.set_scroll_position(30, false);
+        assert_eq!(grid.visible_rows(0), 1..5);
+        grid.set_scroll_position(140, false);
+        assert_eq!(grid.visible_rows(0), 7..10);
+    }
+
+    #[test]
+    fn visible_rows_per_section() {
+        let mut grid = grid(&[2, 10, 4]);
+        grid.set_section_headers(10, Some(|_, _| Ok(())));
+        // Section 1's rows start at 60, section 2's at 270.
+        assert_eq!(grid.visible_rows(0), 0..2);
+        assert!(grid.visible_rows(1).is_empty());
+        grid.set_scroll_position(250, false);
+        assert!(grid.visible_rows(0).is_empty());
+        assert_eq!(grid.visible_rows(1), 9..10);
+        assert_eq!(grid.visible_rows(2), 0..2);
+    }
+
+    #[test]
+    fn index_at_finds_cells_but_not_headers() {
+        let mut grid = grid(&[2, 2]);
+        grid.set_section_headers(10, Some(|_, _| Ok(())));
+        assert_eq!(grid.index_at(point2(5, 5)), None);
+        assert_eq!(grid.index_at(point2(5, 35)), Some(GridIndex::new(0, 1, 0)));
+        assert_eq!(grid.index_at(point2(5, 55)), None);
+        grid.set_scroll_position(20, false);
+        assert_eq!(grid.index_at(point2(5, 45)), Some(GridIndex::new(1, 0, 0)));
+    }
+}
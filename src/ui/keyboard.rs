@@ -0,0 +1,490 @@
+//! An on-screen keyboard for entering text, like the Lua SDK's `playdate.keyboard`.
+//!
+//! The keyboard slides in from the right as a panel with two columns: characters, and actions
+//! (letter case, numbers, space, delete and OK).  Up, down and the crank move through the
+//! current column, left and right switch columns, A types or activates the selection, and B
+//! deletes a character, or cancels if the text is already empty.
+//!
+//! The panel is a sprite at the top of the z-order, so it's drawn over the rest of the frame.
+//! Call `Keyboard::update` once a frame while it's shown.
+
+use {
+    crate::{
+        geometry::ScreenRect,
+        graphics::{Bitmap, Graphics, LCDBitmapFlip, LCDColor, LCDSolidColor, LCD_COLUMNS},
+        log_to_console,
+        sound::{AudioSample, SamplePlayer, Sound},
+        sprite::{Sprite, SpriteManager},
+        system::System,
+    },
+    alloc::{boxed::Box, format, string::String, vec::Vec},
+    anyhow::Error,
+    crankstart_sys::{PDButtons, LCD_ROWS},
+    euclid::{point2, size2},
+};
+
+const PANEL_WIDTH: i32 = 160;
+const CHARACTER_COLUMN_WIDTH: i32 = 96;
+const CHARACTER_ROW_HEIGHT: i32 = 26;
+const ACTION_ROW_HEIGHT: i32 = 28;
+const SLIDE_DURATION_MS: usize = 200;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyboardPage {
+    Uppercase,
+    Lowercase,
+    Numbers,
+}
+
+impl KeyboardPage {
+    fn characters(self) -> &'static str {
+        match self {
+            KeyboardPage::Uppercase => "ABCDEFGHIJKLMNOPQRSTUVWXYZ",
+            KeyboardPage::Lowercase => "abcdefghijklmnopqrstuvwxyz",
+            KeyboardPage::Numbers => "0123456789.,!?'\"-_:;()&@#$%*+=/",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Action {
+    Page(KeyboardPage),
+    Space,
+    Delete,
+    Ok,
+}
+
+const ACTIONS: [(Action, &str); 6] = [
+    (Action::Page(KeyboardPage::Uppercase), "ABC"),
+    (Action::Page(KeyboardPage::Lowercase), "abc"),
+    (Action::Page(KeyboardPage::Numbers), "123"),
+    (Action::Space, "space"),
+    (Action::Delete, "del"),
+    (Action::Ok, "OK"),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Column {
+    Characters,
+    Actions,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Slide {
+    Hidden,
+    In { started_ms: Option<usize> },
+    Shown,
+    Out { started_ms: Option<usize> },
+}
+
+/// The sounds the keyboard plays.  Any that are None are skipped.
+#[derive(Clone, Debug, Default)]
+pub struct KeyboardSounds {
+    /// Moving the selection.
+    pub click: Option<AudioSample>,
+    /// Typing or deleting a character.
+    pub key: Option<AudioSample>,
+    /// Switching column or page.
+    pub selection: Option<AudioSample>,
+    /// Something that can't be done, like typing past the maximum length.
+    pub denial: Option<AudioSample>,
+}
+
+impl KeyboardSounds {
+    /// Loads `click`, `key`, `selection` and `denial` from `directory`, skipping any that
+    /// aren't there.  The Lua SDK's sounds are in `CoreLibs/assets/sfx` for games that bundle
+    /// CoreLibs.
+    pub fn load(directory: &str) -> Self {
+        let load = |name: &str| {
+            Sound::get()
+                .load_audio_sample(&format!("{directory}/{name}"))
+                .ok()
+        };
+        Self {
+            click: load("click"),
+            key: load("key"),
+            selection: load("selection"),
+            denial: load("denial"),
+        }
+    }
+}
+
+type TextCallback = Box<dyn FnMut(&str)>;
+
+pub struct Keyboard {
+    text: String,
+    max_length: Option<usize>,
+    page: KeyboardPage,
+    column: Column,
+    character: usize,
+    action: usize,
+    slide: Slide,
+    crank_degrees_per_item: f32,
+    crank_accumulator: f32,
+    sounds: KeyboardSounds,
+    player: Option<SamplePlayer>,
+    sprite: Sprite,
+    bitmap: Bitmap,
+    on_change: Option<TextCallback>,
+    on_confirm: Option<TextCallback>,
+    on_cancel: Option<Box<dyn FnMut()>>,
+}
+
+impl Keyboard {
+    /// Creates a hidden keyboard, with sounds from `CoreLibs/assets/sfx` if the game has them.
+    pub fn new() -> Result<Self, Error> {
+        let graphics = Graphics::get();
+        let bitmap = graphics.new_bitmap(
+            size2(PANEL_WIDTH, LCD_ROWS as i32),
+            LCDColor::Solid(LCDSolidColor::kColorWhite),
+        )?;
+        let mut sprite = SpriteManager::get_mut().new_sprite()?;
+        sprite.set_image(bitmap.clone(), LCDBitmapFlip::kBitmapUnflipped)?;
+        sprite.set_z_index(i16::MAX)?;
+        let mut keyboard = Self {
+            text: String::new(),
+            max_length: None,
+            page: KeyboardPage::Uppercase,
+            column: Column::Characters,
+            character: 0,
+            action: ACTIONS.len() - 1,
+            slide: Slide::Hidden,
+            crank_degrees_per_item: 20.0,
+            crank_accumulator: 0.0,
+            sounds: KeyboardSounds::default(),
+            player: None,
+            sprite,
+            bitmap,
+            on_change: None,
+            on_confirm: None,
+            on_cancel: None,
+        };
+        keyboard.set_sounds(KeyboardSounds::load("CoreLibs/assets/sfx"))?;
+        Ok(keyboard)
+    }
+
+    /// Sets the sounds to play; see `KeyboardSounds::load`.
+    pub fn set_sounds(&mut self, sounds: KeyboardSounds) -> Result<(), Error> {
+        let any = sounds.click.is_some()
+            || sounds.key.is_some()
+            || sounds.selection.is_some()
+            || sounds.denial.is_some();
+        self.player = if any {
+            Some(Sound::get().get_sample_player()?)
+        } else {
+            None
+        };
+        self.sounds = sounds;
+        Ok(())
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Replaces the text, without calling the change callback.
+    pub fn set_text(&mut self, text: &str) {
+        self.text = String::from(text);
+    }
+
+    /// Limits the text to `max_length` characters.
+    pub fn set_max_length(&mut self, max_length: Option<usize>) {
+        self.max_length = max_length;
+    }
+
+    pub fn set_crank_degrees_per_item(&mut self, degrees: f32) {
+        self.crank_degrees_per_item = degrees;
+    }
+
+    /// Calls `callback` with the new text whenever it changes.
+    pub fn on_change<F>(&mut self, callback: Option<F>)
+    where
+        F: FnMut(&str) + 'static,
+    {
+        self.on_change = callback.map(|cb| Box::new(cb) as TextCallback);
+    }
+
+    /// Calls `callback` with the text when the player picks OK.  The keyboard then hides.
+    pub fn on_confirm<F>(&mut self, callback: Option<F>)
+    where
+        F: FnMut(&str) + 'static,
+    {
+        self.on_confirm = callback.map(|cb| Box::new(cb) as TextCallback);
+    }
+
+    /// Calls `callback` when the player cancels.  The keyboard then hides.
+    pub fn on_cancel<F>(&mut self, callback: Option<F>)
+    where
+        F: FnMut() + 'static,
+    {
+        self.on_cancel = callback.map(|cb| Box::new(cb) as Box<dyn FnMut()>);
+    }
+
+    /// Whether any part of the keyboard is on screen.
+    pub fn is_visible(&self) -> bool {
+        self.slide != Slide::Hidden
+    }
+
+    /// The left edge of the panel, which moves while it slides in and out.
+    pub fn left(&self) -> i32 {
+        let hidden = LCD_COLUMNS as i32;
+        let shown = hidden - PANEL_WIDTH;
+        let progress = |started_ms: Option<usize>| {
+            let now = System::get().get_current_time_milliseconds().unwrap_or(0);
+            let elapsed = started_ms
+                .map(|start| now.saturating_sub(start))
+                .unwrap_or(0);
+            let t = (elapsed as f32 / SLIDE_DURATION_MS as f32).min(1.0);
+            // Ease out (quadratic).
+            1.0 - (1.0 - t) * (1.0 - t)
+        };
+        match self.slide {
+            Slide::Hidden => hidden,
+            Slide::Shown => shown,
+            Slide::In { started_ms } => hidden - (PANEL_WIDTH as f32 * progress(started_ms)) as i32,
+            Slide::Out { started_ms } => shown + (PANEL_WIDTH as f32 * progress(started_ms)) as i32,
+        }
+    }
+
+    /// Slides the keyboard in and starts taking input.
+    pub fn show(&mut self) -> Result<(), Error> {
+        if matches!(self.slide, Slide::Shown | Slide::In { .. }) {
+            return Ok(());
+        }
+        self.slide = Slide::In { started_ms: None };
+        self.crank_accumulator = 0.0;
+        self.render()?;
+        self.position_sprite()?;
+        SpriteManager::get_mut().add_sprite(&self.sprite)
+    }
+
+    /// Slides the keyboard out.
+    pub fn hide(&mut self) {
+        if matches!(self.slide, Slide::Shown | Slide::In { .. }) {
+            self.slide = Slide::Out { started_ms: None };
+        }
+    }
+
+    /// Hides the keyboard and calls the cancel callback.
+    pub fn cancel(&mut self) {
+        self.hide();
+        if let Some(callback) = self.on_cancel.as_mut() {
+            callback();
+        }
+    }
+
+    /// Handles input and animates sliding.  Does nothing while hidden.
+    pub fn update(&mut self) -> Result<(), Error> {
+        let now = System::get().get_current_time_milliseconds()?;
+        match &mut self.slide {
+            Slide::Hidden => return Ok(()),
+            Slide::In { started_ms } | Slide::Out { started_ms } if started_ms.is_none() => {
+                *started_ms = Some(now);
+            }
+            Slide::In {
+                started_ms: Some(start),
+            } if now.saturating_sub(*start) >= SLIDE_DURATION_MS => {
+                self.slide = Slide::Shown;
+            }
+            Slide::Out {
+                started_ms: Some(start),
+            } if now.saturating_sub(*start) >= SLIDE_DURATION_MS => {
+                self.slide = Slide::Hidden;
+                return SpriteManager::get_mut().remove_sprite(&self.sprite);
+            }
+            _ => {}
+        }
+        if matches!(self.slide, Slide::In { .. } | Slide::Shown) && self.handle_input()? {
+            self.render()?;
+        }
+        self.position_sprite()
+    }
+
+    // Returns whether anything needs redrawing.
+    fn handle_input(&mut self) -> Result<bool, Error> {
+        let system = System::get();
+        let (_, pushed, _) = system.get_button_state()?;
+        let is_pushed = |button: PDButtons| (pushed & button) == button;
+        let mut changed = false;
+
+        let mut steps = 0;
+        if is_pushed(PDButtons::kButtonDown) {
+            steps += 1;
+        }
+        if is_pushed(PDButtons::kButtonUp) {
+            steps -= 1;
+        }
+        if !system.is_crank_docked()? && self.crank_degrees_per_item > 0.0 {
+            self.crank_accumulator += system.get_crank_change()?;
+            while self.crank_accumulator >= self.crank_degrees_per_item {
+                self.crank_accumulator -= self.crank_degrees_per_item;
+                steps += 1;
+            }
+            while self.crank_accumulator <= -self.crank_degrees_per_item {
+                self.crank_accumulator += self.crank_degrees_per_item;
+                steps -= 1;
+            }
+        }
+        if steps != 0 {
+            self.move_selection(steps);
+            self.play(self.sounds.click.clone());
+            changed = true;
+        }
+
+        if is_pushed(PDButtons::kButtonLeft) || is_pushed(PDButtons::kButtonRight) {
+            self.column = match self.column {
+                Column::Characters => Column::Actions,
+                Column::Actions => Column::Characters,
+            };
+            self.play(self.sounds.selection.clone());
+            changed = true;
+        }
+
+        if is_pushed(PDButtons::kButtonA) {
+            match self.column {
+                Column::Characters => {
+                    let c = self.page.characters().chars().nth(self.character);
+                    if let Some(c) = c {
+                        self.type_char(c);
+                    }
+                }
+                Column::Actions => match ACTIONS[self.action].0 {
+                    Action::Page(page) => {
+                        self.page = page;
+                        self.character = 0;
+                        self.column = Column::Characters;
+                        self.play(self.sounds.selection.clone());
+                    }
+                    Action::Space => self.type_char(' '),
+                    Action::Delete => self.delete(),
+                    Action::Ok => {
+                        self.hide();
+                        if let Some(callback) = self.on_confirm.as_mut() {
+                            callback(&self.text);
+                        }
+                    }
+                },
+            }
+            changed = true;
+        }
+
+        if is_pushed(PDButtons::kButtonB) {
+            if self.text.is_empty() {
+                self.cancel();
+            } else {
+                self.delete();
+            }
+        }
+        Ok(changed)
+    }
+
+    fn move_selection(&mut self, steps: i32) {
+        let (index, count) = match self.column {
+            Column::Characters => (&mut self.character, self.page.characters().chars().count()),
+            Column::Actions => (&mut self.action, ACTIONS.len()),
+        };
+        *index = (*index as i32 + steps).rem_euclid(count as i32) as usize;
+    }
+
+    fn type_char(&mut self, c: char) {
+        if let Some(max_length) = self.max_length {
+            if self.text.chars().count() >= max_length {
+                self.play(self.sounds.denial.clone());
+                return;
+            }
+        }
+        self.text.push(c);
+        self.play(self.sounds.key.clone());
+        self.changed();
+    }
+
+    fn delete(&mut self) {
+        if self.text.pop().is_some() {
+            self.play(self.sounds.key.clone());
+            self.changed();
+        } else {
+            self.play(self.sounds.denial.clone());
+        }
+    }
+
+    fn changed(&mut self) {
+        if let Some(callback) = self.on_change.as_mut() {
+            callback(&self.text);
+        }
+    }
+
+    fn play(&mut self, sample: Option<AudioSample>) {
+        if let (Some(player), Some(sample)) = (self.player.as_mut(), sample) {
+            let result = player.set_sample(&sample).and_then(|_| player.play(1, 1.0));
+            if let Err(err) = result {
+                log_to_console!("Keyboard sound failed: {err:#}");
+            }
+        }
+    }
+
+    fn position_sprite(&mut self) -> Result<(), Error> {
+        let x = self.left() as f32 + PANEL_WIDTH as f32 / 2.0;
+        self.sprite.move_to(x, LCD_ROWS as f32 / 2.0)
+    }
+
+    fn render(&mut self) -> Result<(), Error> {
+        let graphics = Graphics::get();
+        let black = LCDColor::Solid(LCDSolidColor::kColorBlack);
+        let height = LCD_ROWS as i32;
+        self.bitmap
+            .clear(LCDColor::Solid(LCDSolidColor::kColorWhite))?;
+        graphics.with_context(Some(&self.bitmap), || {
+            graphics.draw_line(point2(0, 0), point2(0, height), 2, black.clone())?;
+            graphics.draw_line(
+                point2(CHARACTER_COLUMN_WIDTH, 0),
+                point2(CHARACTER_COLUMN_WIDTH, height),
+                1,
+                black.clone(),
+            )?;
+
+            // The selected character sits in the middle, with its neighbours above and below.
+            let characters: Vec<char> = self.page.characters().chars().collect();
+            let middle = (height - CHARACTER_ROW_HEIGHT) / 2;
+            let visible = middle / CHARACTER_ROW_HEIGHT + 1;
+            for offset in -visible..=visible {
+                let index = (self.character as i32 + offset).rem_euclid(characters.len() as i32);
+                let y = middle + offset * CHARACTER_ROW_HEIGHT;
+                let text = String::from(characters[index as usize]);
+                let width = graphics.get_system_text_width(&text, 0)?;
+                let x = (CHARACTER_COLUMN_WIDTH - width) / 2;
+                graphics.draw_text(&text, point2(x, y + 4))?;
+            }
+            let selected = ScreenRect::new(
+                point2(4, middle),
+                size2(CHARACTER_COLUMN_WIDTH - 8, CHARACTER_ROW_HEIGHT),
+            );
+            if self.column == Column::Characters {
+                graphics.fill_rect(selected, LCDColor::Solid(LCDSolidColor::kColorXOR))?;
+            } else {
+                graphics.draw_rect(selected, black.clone())?;
+            }
+
+            let actions_top = (height - ACTION_ROW_HEIGHT * ACTIONS.len() as i32) / 2;
+            for (index, (action, label)) in ACTIONS.iter().enumerate() {
+                let y = actions_top + index as i32 * ACTION_ROW_HEIGHT;
+                let width = graphics.get_system_text_width(label, 0)?;
+                let x = CHARACTER_COLUMN_WIDTH + (PANEL_WIDTH - CHARACTER_COLUMN_WIDTH - width) / 2;
+                graphics.draw_text(label, point2(x, y + 5))?;
+                let rect = ScreenRect::new(
+                    point2(CHARACTER_COLUMN_WIDTH + 4, y + 2),
+                    size2(
+                        PANEL_WIDTH - CHARACTER_COLUMN_WIDTH - 8,
+                        ACTION_ROW_HEIGHT - 4,
+                    ),
+                );
+                if self.column == Column::Actions && index == self.action {
+                    graphics.fill_rect(rect, LCDColor::Solid(LCDSolidColor::kColorXOR))?;
+                } else if *action == Action::Page(self.page) {
+                    graphics.draw_rect(rect, black.clone())?;
+                }
+            }
+            Ok(())
+        })?;
+        self.sprite.mark_dirty()
+    }
+}